@@ -0,0 +1,38 @@
+//! Keeping the k closest of a stream of candidates: a capped `ClosestQueue` against sorting
+//! every candidate by distance
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use kademlia::{ClosestQueue, K, NodeId};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use std::hint::black_box;
+
+fn closest(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(14);
+    let target: NodeId = NodeId::random_with(&mut rng);
+    let mut group = c.benchmark_group("k closest of n candidates");
+    for n in [100, 1_000, 10_000] {
+        let candidates: Vec<NodeId> = (0..n).map(|_| NodeId::random_with(&mut rng)).collect();
+        group.bench_with_input(BenchmarkId::new("ClosestQueue", n), &candidates, |b, candidates| {
+            b.iter(|| {
+                let mut queue = ClosestQueue::new(target, K);
+                for id in candidates {
+                    queue.push(*id);
+                }
+                black_box(queue.into_vec())
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("full sort", n), &candidates, |b, candidates| {
+            b.iter(|| {
+                let mut sorted = candidates.clone();
+                sorted.sort_by_key(|id| target.xor_distance(id));
+                sorted.truncate(K);
+                black_box(sorted)
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, closest);
+criterion_main!(benches);
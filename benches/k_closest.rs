@@ -0,0 +1,32 @@
+//! `distance::k_closest`'s partial selection against sorting the whole candidate list
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use kademlia::distance::{k_closest, sort_by_distance};
+use kademlia::{K, NodeId};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use std::hint::black_box;
+
+fn selection(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(142);
+    let target: NodeId = NodeId::random_with(&mut rng);
+    let mut group = c.benchmark_group("K closest of a candidate vector");
+    for n in [1_000, 10_000, 100_000] {
+        let candidates: Vec<NodeId> = (0..n).map(|_| NodeId::random_with(&mut rng)).collect();
+        group.bench_with_input(BenchmarkId::new("k_closest", n), &candidates, |b, candidates| {
+            b.iter(|| black_box(k_closest(&target, candidates, K)))
+        });
+        group.bench_with_input(BenchmarkId::new("sort_by_distance", n), &candidates, |b, candidates| {
+            b.iter(|| {
+                let mut sorted = candidates.clone();
+                sort_by_distance(&target, &mut sorted);
+                sorted.truncate(K);
+                black_box(sorted)
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, selection);
+criterion_main!(benches);
@@ -0,0 +1,33 @@
+//! Sequential lookups against `SimNetwork`'s, which send each round's ALPHA queries at once
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use kademlia::{ALPHA, Network, NodeId, SimNetwork};
+use std::hint::black_box;
+
+fn lookups(c: &mut Criterion) {
+    let network: Network = Network::new_with_nodes_seeded(50, 5);
+    let ids: Vec<NodeId> = network.node_ids().copied().collect();
+    let targets: Vec<NodeId> = (0..ids.len() as u8).map(|i| network.key_to_id(&[i])).collect();
+    let mut sequential = Network::new_with_nodes_seeded(50, 5);
+    let parallel = SimNetwork::from_network(network);
+
+    let mut group = c.benchmark_group(format!("iterative_find_node, 50 nodes, alpha {ALPHA}"));
+    let mut i = 0;
+    group.bench_function("sequential", |b| {
+        b.iter(|| {
+            i = (i + 1) % ids.len();
+            black_box(sequential.iterative_find_node(&ids[i], &targets[i]))
+        })
+    });
+    let mut i = 0;
+    group.bench_function("parallel", |b| {
+        b.iter(|| {
+            i = (i + 1) % ids.len();
+            black_box(parallel.parallel_iterative_find_node(&ids[i], &targets[i]))
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, lookups);
+criterion_main!(benches);
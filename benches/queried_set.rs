@@ -0,0 +1,62 @@
+//! Lookups on a 500-node network, and the queried-set bookkeeping they do with a `Vec` or a
+//! `HashSet`
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use kademlia::{Network, NodeId, QueryTrace};
+use std::collections::HashSet;
+use std::hint::black_box;
+
+/// Replay a lookup's membership checks: every ID an answer lists is looked up in the
+/// queried set, and every node asked is added to it
+fn replay<S>(trace: &QueryTrace, mut queried: S, contains: impl Fn(&S, &NodeId) -> bool, insert: impl Fn(&mut S, NodeId)) -> usize {
+    let mut fresh = 0;
+    for step in &trace.steps {
+        insert(&mut queried, step.queried);
+        fresh += step.returned.iter().filter(|id| !contains(&queried, id)).count();
+    }
+    fresh
+}
+
+fn lookups(c: &mut Criterion) {
+    let mut network: Network = Network::new_with_nodes_seeded(500, 13);
+    let ids: Vec<NodeId> = network.node_ids().copied().collect();
+    let traces: Vec<QueryTrace> = ids
+        .iter()
+        .take(50)
+        .enumerate()
+        .map(|(i, id)| network.traced_iterative_find_node(id, &network.key_to_id(&[i as u8])).1)
+        .collect();
+
+    let mut group = c.benchmark_group("500 nodes");
+    let mut i = 0;
+    group.bench_function("iterative_find_node", |b| {
+        b.iter(|| {
+            i = (i + 1) % ids.len();
+            black_box(network.iterative_find_node(&ids[i], &ids[(i * 7) % ids.len()]))
+        })
+    });
+    group.bench_function("queried set as a Vec", |b| {
+        b.iter(|| {
+            traces
+                .iter()
+                .map(|trace| replay(trace, Vec::new(), |s: &Vec<NodeId>, id| s.contains(id), Vec::push))
+                .sum::<usize>()
+        })
+    });
+    group.bench_function("queried set as a HashSet", |b| {
+        b.iter(|| {
+            traces
+                .iter()
+                .map(|trace| {
+                    replay(trace, HashSet::new(), |s: &HashSet<NodeId>, id| s.contains(id), |s, id| {
+                        s.insert(id);
+                    })
+                })
+                .sum::<usize>()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, lookups);
+criterion_main!(benches);
@@ -0,0 +1,50 @@
+//! `RoutingTable::closest_k` on a table of 10,000 contacts, against sorting every contact by
+//! distance
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use kademlia::{K, NetworkConfig, NodeId, RoutingTable};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::hint::black_box;
+use std::time::Instant;
+
+const CONTACTS: usize = 10_000;
+
+fn closest(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(28);
+    let config = NetworkConfig::builder().k(100).build();
+    let own: NodeId = NodeId::random_with(&mut rng);
+    let now = Instant::now();
+    let mut table = RoutingTable::new(own, now, &config);
+    // random IDs would crowd into the first few buckets; spread them over every depth instead
+    while table.len() < CONTACTS {
+        let depth = rng.gen_range(0..NodeId::<20>::BITS - 1);
+        table.insert(&NodeId::random_in_bucket(&own, depth, &mut rng), 0, now);
+    }
+    let targets: Vec<NodeId> = (0..64).map(|_| NodeId::random_with(&mut rng)).collect();
+
+    let mut group = c.benchmark_group(format!("K closest in a table of {CONTACTS} contacts"));
+    let mut i = 0;
+    group.bench_function("closest_k", |b| {
+        b.iter(|| {
+            i = (i + 1) % targets.len();
+            black_box(table.closest_k(&targets[i], K))
+        })
+    });
+    let mut i = 0;
+    group.bench_function("brute force", |b| {
+        b.iter(|| {
+            i = (i + 1) % targets.len();
+            let target = targets[i];
+            // every contact here was inserted first-hand and never failed, so all are usable
+            let mut all: Vec<NodeId> = table.peers().copied().collect();
+            all.sort_by_key(|id| id.xor_distance(&target));
+            all.truncate(K);
+            black_box(all)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, closest);
+criterion_main!(benches);
@@ -0,0 +1,40 @@
+//! Storing 100 entries on a 60-node network: one `iterative_store_batch` against 100
+//! `iterative_store` calls
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use kademlia::{DEFAULT_TTL, Network, NodeId};
+use std::hint::black_box;
+
+fn stores(c: &mut Criterion) {
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..100).map(|i| (format!("batch-{i}").into_bytes(), vec![i as u8; 16])).collect();
+    let network = || {
+        let mut net: Network = Network::new();
+        net.populate(60);
+        let start: NodeId = *net.node_ids().min().expect("populated");
+        (net, start)
+    };
+
+    let mut group = c.benchmark_group("100 entries, 60 nodes");
+    group.bench_function("iterative_store_batch", |b| {
+        b.iter_batched(
+            network,
+            |(mut net, start)| black_box(net.iterative_store_batch(&start, entries.clone())),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("100 iterative_store calls", |b| {
+        b.iter_batched(
+            network,
+            |(mut net, start)| {
+                for (key, value) in entries.clone() {
+                    black_box(net.iterative_store(&start, key, value, DEFAULT_TTL));
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, stores);
+criterion_main!(benches);
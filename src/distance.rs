@@ -0,0 +1,74 @@
+//! Ordering node IDs by XOR distance to a target
+
+use crate::NodeId;
+
+/// Sort `ids` closest-first by XOR distance to `target`
+pub fn sort_by_distance<const N: usize>(target: &NodeId<N>, ids: &mut [NodeId<N>]) {
+    ids.sort_by_key(|id| target.xor_distance(id));
+}
+
+/// Up to `k` of `ids` closest to `target`, closest first. Only the winners get sorted: a
+/// partial selection first moves the `k` closest to the front in linear time.
+pub fn k_closest<const N: usize>(target: &NodeId<N>, ids: &[NodeId<N>], k: usize) -> Vec<NodeId<N>> {
+    if k == 0 {
+        return Vec::new();
+    }
+    let mut closest = ids.to_vec();
+    if closest.len() > k {
+        closest.select_nth_unstable_by_key(k - 1, |id| target.xor_distance(id));
+        closest.truncate(k);
+    }
+    sort_by_distance(target, &mut closest);
+    closest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn ids(bytes: &[u8]) -> Vec<NodeId<1>> {
+        bytes.iter().map(|&b| NodeId::from_bytes([b])).collect()
+    }
+
+    #[test]
+    fn ids_equally_far_apart_in_value_are_ordered_by_xor() {
+        // 0x0f and 0x11 both sit 1 away from 0x10 numerically; XOR puts the one sharing the
+        // longer prefix first, whichever order they come in
+        let target = NodeId::from_bytes([0x10]);
+        for mut candidates in [ids(&[0x0f, 0x11]), ids(&[0x11, 0x0f])] {
+            sort_by_distance(&target, &mut candidates);
+            assert_eq!(candidates, ids(&[0x11, 0x0f]));
+            assert_eq!(k_closest(&target, &ids(&[0x0f, 0x11]), 1), ids(&[0x11]));
+        }
+        // the target's mirror images around a bit are never equidistant either
+        let target = NodeId::from_bytes([0x80]);
+        assert_eq!(k_closest(&target, &ids(&[0x7f, 0x81, 0x00, 0xff]), 4), ids(&[0x81, 0xff, 0x00, 0x7f]));
+    }
+
+    #[test]
+    fn only_copies_of_one_id_tie_and_they_stay_together() {
+        let target = NodeId::from_bytes([0x00]);
+        let candidates = ids(&[0x40, 0x01, 0x40, 0x02, 0x01]);
+        assert_eq!(k_closest(&target, &candidates, 5), ids(&[0x01, 0x01, 0x02, 0x40, 0x40]));
+        // a cut through a run of copies still returns exactly k
+        assert_eq!(k_closest(&target, &candidates, 1), ids(&[0x01]));
+        assert_eq!(k_closest(&target, &candidates, 4), ids(&[0x01, 0x01, 0x02, 0x40]));
+    }
+
+    #[test]
+    fn k_closest_matches_a_full_sort() {
+        let mut rng = StdRng::seed_from_u64(14);
+        for len in [0, 1, 5, 50, 300] {
+            // one-byte IDs, so larger lists are full of copies
+            let candidates: Vec<NodeId<1>> = (0..len).map(|_| NodeId::from_bytes([rng.r#gen()])).collect();
+            let target = NodeId::from_bytes([rng.r#gen()]);
+            let mut sorted = candidates.clone();
+            sort_by_distance(&target, &mut sorted);
+            for k in [0, 1, 3, len, len + 5] {
+                assert_eq!(k_closest(&target, &candidates, k), sorted[..k.min(len)], "{len} candidates, k = {k}");
+            }
+        }
+    }
+}
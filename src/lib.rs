@@ -0,0 +1,7114 @@
+//! An in-memory Kademlia DHT simulation: node IDs, k-bucket routing tables, the four RPCs and
+//! the iterative lookups built on them, run over a simulated network
+
+pub mod distance;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::{CryptoRng, RngCore};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fmt;
+#[cfg(feature = "serde")]
+use std::{fs, io, path::Path};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// Default bucket size (commonly 20 in papers); we use a smaller number for demo
+pub const K: usize = 8;
+/// Default concurrency factor alpha (number of parallel queries); we serialize for simplicity
+pub const ALPHA: usize = 3;
+/// Default max iterations for lookup to avoid infinite loops in small demos
+pub const MAX_STEPS: usize = 8;
+/// Default lifetime of a stored value; publishers must re-store before it runs out
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Lifetime of a copy cached at the closest node a successful lookup found without it
+pub const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+/// Buckets with no lookup in this long get refreshed with a lookup for a random ID in their range
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Default proof-of-work difficulty in bits, about 256 hashes per ID
+pub const DEFAULT_POW_DIFFICULTY: u8 = 8;
+/// Hardest proof of work a network may ask for: 2^32 hashes per ID already takes minutes
+pub const MAX_POW_DIFFICULTY: u8 = 32;
+/// Default ID width in bytes (160 bits, the SHA-1 space used by the paper)
+pub const ID_BYTES: usize = 20;
+/// RFC 4648 base32 alphabet (lowercase, unpadded) used by `NodeId::to_base32`
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// An N-byte identifier; defaults to 160 bits like in Kademlia (commonly from SHA-1 space)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId<const N: usize = ID_BYTES>([u8; N]);
+
+/// The classic 160-bit Kademlia ID
+pub type NodeId160 = NodeId<20>;
+
+impl<const N: usize> NodeId<N> {
+    /// Number of bits at this ID width, which is also the most k-buckets a node can have
+    pub const BITS: usize = N * 8;
+
+    /// The all-zero ID, the low end of the keyspace
+    pub const ZERO: Self = NodeId([0; N]);
+
+    /// The all-ones ID, the high end of the keyspace
+    pub const MAX: Self = NodeId([0xff; N]);
+
+    /// Create a random NodeId
+    pub fn random() -> Self {
+        Self::random_with(&mut rand::thread_rng())
+    }
+
+    /// Create a random NodeId from the given RNG (seed it for reproducible simulations)
+    pub fn random_with(rng: &mut impl Rng) -> Self {
+        let mut bytes = [0u8; N];
+        rng.fill(&mut bytes[..]);
+        NodeId(bytes)
+    }
+
+    /// Random ID in bucket `bucket` relative to `base` (see `bucket_index`): it shares exactly
+    /// the first `bucket` bits with `base`, differs at that bit, and is random below it
+    pub fn random_in_bucket(base: &NodeId<N>, bucket: usize, rng: &mut impl Rng) -> Self {
+        assert!(bucket < Self::BITS, "bucket {bucket} out of range for {}-bit IDs", Self::BITS);
+        let mut prefix = base.0;
+        prefix[bucket / 8] ^= 0x80 >> (bucket % 8);
+        Self::random_with_prefix(&prefix, bucket + 1, rng)
+    }
+
+    /// Random ID whose first `bit_len` bits are copied from `prefix` (the prefix may end
+    /// mid-byte; bits of `prefix` past `bit_len` are ignored) and whose other bits are random
+    pub fn random_with_prefix(prefix: &[u8], bit_len: usize, rng: &mut impl Rng) -> Self {
+        assert!(bit_len <= Self::BITS, "prefix of {bit_len} bits is longer than {}-bit IDs", Self::BITS);
+        assert!(bit_len <= prefix.len() * 8, "prefix holds only {} bits, not {bit_len}", prefix.len() * 8);
+        let mut bytes = [0u8; N];
+        rng.fill(&mut bytes[..]);
+        let whole = bit_len / 8;
+        bytes[..whole].copy_from_slice(&prefix[..whole]);
+        let partial = bit_len % 8;
+        if partial > 0 {
+            let fixed = !(0xffu8 >> partial);
+            bytes[whole] = (prefix[whole] & fixed) | (bytes[whole] & !fixed);
+        }
+        NodeId(bytes)
+    }
+
+    /// The ID a node holding the public key `pubkey` (raw bytes) is entitled to: its SHA-1 hash
+    pub fn from_public_key(pubkey: &[u8]) -> Self {
+        id_from_digest::<Sha1, N>(pubkey)
+    }
+
+    /// The key of an immutable value: the SHA-1 hash of its bytes, whatever the network's
+    /// key hasher, so any node can check a value against its key
+    pub fn for_content(value: &[u8]) -> Self {
+        id_from_digest::<Sha1, N>(value)
+    }
+
+    /// Construct from a byte array
+    pub fn from_bytes(bytes: [u8; N]) -> Self {
+        NodeId(bytes)
+    }
+
+    /// `b` followed by zero bytes, for laying out test IDs by hand
+    pub fn from_u8(b: u8) -> Self {
+        let mut bytes = [0; N];
+        bytes[0] = b;
+        NodeId(bytes)
+    }
+
+    /// Parse exactly two hex characters (either case) per byte into a NodeId, optionally
+    /// prefixed with `0x`
+    pub fn from_hex(s: &str) -> Result<Self, NodeIdParseError> {
+        let s = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+        let len = s.chars().count();
+        if len != N * 2 {
+            return Err(NodeIdParseError::WrongLength(len));
+        }
+        let digits: Vec<u8> = s
+            .chars()
+            .map(|c| c.to_digit(16).map(|d| d as u8).ok_or(NodeIdParseError::InvalidChar(c)))
+            .collect::<Result<_, _>>()?;
+        let mut bytes = [0u8; N];
+        for (b, pair) in bytes.iter_mut().zip(digits.chunks(2)) {
+            *b = (pair[0] << 4) | pair[1];
+        }
+        Ok(NodeId::from_bytes(bytes))
+    }
+
+    /// Render as unpadded lowercase base32 (32 chars at the default width)
+    pub fn to_base32(self) -> String {
+        let mut out = String::with_capacity((N * 8).div_ceil(5));
+        let (mut buffer, mut bits) = (0u32, 0);
+        for &b in &self.0 {
+            buffer = (buffer << 8) | u32::from(b);
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                out.push(BASE32_ALPHABET[(buffer >> bits) as usize & 31] as char);
+            }
+            buffer &= (1 << bits) - 1;
+        }
+        if bits > 0 {
+            out.push(BASE32_ALPHABET[(buffer << (5 - bits)) as usize & 31] as char);
+        }
+        out
+    }
+
+    /// Parse the form produced by `to_base32` (either case)
+    pub fn from_base32(s: &str) -> Result<Self, NodeIdParseError> {
+        let len = s.chars().count();
+        if len != (N * 8).div_ceil(5) {
+            return Err(NodeIdParseError::WrongLength(len));
+        }
+        let mut bytes = [0u8; N];
+        let (mut buffer, mut bits, mut filled) = (0u32, 0, 0);
+        let mut last = ' ';
+        for c in s.chars() {
+            let digit = BASE32_ALPHABET
+                .iter()
+                .position(|&a| a as char == c.to_ascii_lowercase())
+                .ok_or(NodeIdParseError::InvalidChar(c))?;
+            buffer = (buffer << 5) | digit as u32;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                bytes[filled] = (buffer >> bits) as u8;
+                filled += 1;
+                buffer &= (1 << bits) - 1;
+            }
+            last = c;
+        }
+        // the final character's padding bits must be zero, or two strings would map to one ID
+        if buffer != 0 {
+            return Err(NodeIdParseError::InvalidChar(last));
+        }
+        Ok(NodeId::from_bytes(bytes))
+    }
+
+    /// Compact label for logs and traces: the first 6 hex characters and an ellipsis
+    pub fn short(&self) -> String {
+        let hex = self.to_string();
+        format!("{}…", &hex[..hex.len().min(6)])
+    }
+
+    /// Compute XOR distance between two IDs
+    pub fn xor_distance(&self, other: &NodeId<N>) -> Distance<N> {
+        Distance(self.xor(other).0)
+    }
+
+    /// Mine a random ID and a nonce such that SHA-1(ID || nonce) starts with
+    /// `difficulty_bits` zero bits, so minting many IDs (e.g. for a Sybil attack) costs about
+    /// 2^difficulty hashes each. Returns the ID and the nonce.
+    pub fn generate_with_pow(difficulty_bits: u8, rng: &mut impl Rng) -> (Self, u64) {
+        let id = Self::random_with(rng);
+        (id, id.mine_pow_nonce(difficulty_bits))
+    }
+
+    /// The first nonce proving `difficulty_bits` of work on this ID, for IDs that cannot be
+    /// chosen freely (e.g. derived from a public key)
+    pub fn mine_pow_nonce(&self, difficulty_bits: u8) -> u64 {
+        (0..=u64::MAX)
+            .find(|nonce| self.verify_pow(*nonce, difficulty_bits))
+            .expect("2^64 nonces cover any practical difficulty")
+    }
+
+    /// Whether SHA-1(ID || nonce), with the nonce big-endian, starts with at least
+    /// `difficulty_bits` zero bits
+    pub fn verify_pow(&self, nonce: u64, difficulty_bits: u8) -> bool {
+        let digest = Sha1::new().chain_update(self.0).chain_update(nonce.to_be_bytes()).finalize();
+        let mut zeros = 0;
+        for b in digest {
+            zeros += b.leading_zeros() as usize;
+            if b != 0 {
+                break;
+            }
+        }
+        zeros >= usize::from(difficulty_bits)
+    }
+
+    /// Number of leading bits shared with `other` (0..=BITS, BITS means equal IDs).
+    /// Stops at the first differing byte rather than building the full distance.
+    pub fn common_prefix_len(&self, other: &NodeId<N>) -> usize {
+        for (i, (a, b)) in self.0.iter().zip(other.0.iter()).enumerate() {
+            let x = a ^ b;
+            if x != 0 {
+                return i * 8 + x.leading_zeros() as usize;
+            }
+        }
+        Self::BITS
+    }
+
+    /// Leading zero bits of the XOR distance to `other`: the same count as
+    /// `common_prefix_len`, seen from the distance's side
+    pub fn leading_zeros_xor(&self, other: &NodeId<N>) -> usize {
+        self.common_prefix_len(other)
+    }
+
+    /// Bit length of the XOR distance to `other`: BITS - `bucket_index`, without the Option
+    /// (0 for equal IDs, BITS when the first bit differs)
+    pub fn distance_bits(&self, other: &NodeId<N>) -> usize {
+        Self::BITS - self.common_prefix_len(other)
+    }
+
+    /// Bucket `other` falls in relative to us, numbered like routing-table buckets: by the
+    /// prefix length the two share, so bucket 0 is the far half of the keyspace and bucket
+    /// BITS-1 the one ID differing only in the last bit. None when the IDs are equal
+    pub fn bucket_index(&self, other: &NodeId<N>) -> Option<usize> {
+        let cpl = self.common_prefix_len(other);
+        if cpl == Self::BITS { None } else { Some(cpl) }
+    }
+
+    /// Bit `i`, counted big-endian (bit 0 is the top bit of byte 0) like distances compare
+    pub fn bit(&self, i: usize) -> bool {
+        assert!(i < Self::BITS, "bit {i} out of range for {}-bit IDs", Self::BITS);
+        self.0[i / 8] & (0x80 >> (i % 8)) != 0
+    }
+
+    /// This ID with bit `i` inverted; the result lands in bucket `i` relative to us
+    pub fn with_bit_flipped(mut self, i: usize) -> Self {
+        assert!(i < Self::BITS, "bit {i} out of range for {}-bit IDs", Self::BITS);
+        self.0[i / 8] ^= 0x80 >> (i % 8);
+        self
+    }
+
+    /// Combine two IDs byte by byte with `op`
+    fn zip_with(&self, other: &NodeId<N>, op: impl Fn(u8, u8) -> u8) -> Self {
+        let mut out = [0u8; N];
+        for (o, (a, b)) in out.iter_mut().zip(self.0.iter().zip(other.0.iter())) {
+            *o = op(*a, *b);
+        }
+        NodeId(out)
+    }
+
+    /// Bitwise XOR, as an ID rather than a `Distance`
+    pub fn xor(&self, other: &NodeId<N>) -> Self {
+        self.zip_with(other, |a, b| a ^ b)
+    }
+
+    /// Bitwise OR
+    pub fn or(&self, other: &NodeId<N>) -> Self {
+        self.zip_with(other, |a, b| a | b)
+    }
+
+    /// Bitwise AND
+    pub fn and(&self, other: &NodeId<N>) -> Self {
+        self.zip_with(other, |a, b| a & b)
+    }
+
+    /// Every bit inverted
+    pub fn not(&self) -> Self {
+        self.xor(&Self::MAX)
+    }
+
+    /// True for the all-zero ID
+    pub fn is_zero(&self) -> bool {
+        *self == Self::ZERO
+    }
+
+    /// This ID with its first `bits` bits replaced by those of `other`
+    pub fn with_prefix_from(mut self, other: &NodeId<N>, bits: usize) -> Self {
+        assert!(bits <= Self::BITS, "prefix of {bits} bits is longer than {}-bit IDs", Self::BITS);
+        let whole = bits / 8;
+        self.0[..whole].copy_from_slice(&other.0[..whole]);
+        let partial = bits % 8;
+        if partial > 0 {
+            let fixed = !(0xffu8 >> partial);
+            self.0[whole] = (other.0[whole] & fixed) | (self.0[whole] & !fixed);
+        }
+        self
+    }
+}
+
+/// Mine a node ID and nonce whose SHA-1(ID || nonce) starts with `difficulty` zero bits
+pub fn generate_pow_node_id(difficulty: u8) -> (NodeId, u64) {
+    NodeId::generate_with_pow(difficulty, &mut rand::thread_rng())
+}
+
+/// Whether `nonce` proves `difficulty` bits of work for `id`
+pub fn verify_pow<const N: usize>(id: &NodeId<N>, nonce: u64, difficulty: u8) -> bool {
+    id.verify_pow(nonce, difficulty)
+}
+
+impl<const N: usize> fmt::LowerHex for NodeId<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in &self.0 {
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Display a NodeId as lowercase hex (40 chars at the default width)
+impl<const N: usize> fmt::Display for NodeId<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(self, f)
+    }
+}
+
+/// Reasons a hex or base32 string can fail to parse into a NodeId
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NodeIdParseError {
+    /// Input did not have the number of characters the encoding needs for an ID
+    WrongLength(usize),
+    /// Input contained a character outside the encoding's alphabet
+    InvalidChar(char),
+}
+
+impl fmt::Display for NodeIdParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeIdParseError::WrongLength(len) => {
+                write!(f, "wrong number of characters: {len}")
+            }
+            NodeIdParseError::InvalidChar(c) => write!(f, "invalid character {c:?}"),
+        }
+    }
+}
+
+impl std::error::Error for NodeIdParseError {}
+
+/// Why an RPC between two simulated nodes did not produce an answer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KademliaError<const N: usize = ID_BYTES> {
+    /// No node with this ID is registered in the network
+    NodeNotFound(NodeId<N>),
+    /// The node exists but is dead, so the RPC never gets a reply
+    Timeout(NodeId<N>),
+    /// The node refused a store because it has no room left
+    StoreFull(NodeId<N>),
+    /// The queried node does not hold (an unexpired copy of) the key
+    KeyNotFound,
+    /// The RPC itself was malformed, e.g. sent on behalf of an unknown node
+    ProtocolError(String),
+    /// A mutable value whose signature does not match its key, sequence number and bytes
+    InvalidSignature,
+    /// A mutable value no newer than the (different) version already stored under its key
+    OutdatedSequence { stored: u64, offered: u64 },
+    /// An immutable value offered under a key that is not its hash
+    HashMismatch,
+    /// Fewer nodes confirmed a store than the caller required
+    InsufficientReplicas { stored: usize, required: usize },
+    /// The node is on the other side of a network partition from the sender
+    Partitioned(NodeId<N>),
+    /// A node with this ID is already registered
+    DuplicateNodeId(NodeId<N>),
+}
+
+impl<const N: usize> fmt::Display for KademliaError<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KademliaError::NodeNotFound(id) => write!(f, "node {id} not found"),
+            KademliaError::Timeout(id) => write!(f, "node {id} timed out"),
+            KademliaError::Partitioned(id) => write!(f, "node {id} is cut off by a partition"),
+            KademliaError::DuplicateNodeId(id) => write!(f, "ID {id} is already in use"),
+            KademliaError::StoreFull(id) => write!(f, "node {id} has no room to store"),
+            KademliaError::KeyNotFound => write!(f, "key not found"),
+            KademliaError::ProtocolError(msg) => write!(f, "protocol error: {msg}"),
+            KademliaError::InvalidSignature => write!(f, "invalid signature"),
+            KademliaError::OutdatedSequence { stored, offered } => {
+                write!(f, "sequence number {offered} is not newer than the stored {stored}")
+            }
+            KademliaError::HashMismatch => write!(f, "value does not hash to its key"),
+            KademliaError::InsufficientReplicas { stored, required } => {
+                write!(f, "stored on {stored} nodes, {required} required")
+            }
+        }
+    }
+}
+
+impl<const N: usize> std::error::Error for KademliaError<N> {}
+
+/// Parse a NodeId back from the hex form produced by Display
+impl<const N: usize> FromStr for NodeId<N> {
+    type Err = NodeIdParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NodeId::from_hex(s)
+    }
+}
+
+/// Serialize as the hex string in human-readable formats (JSON), a fixed N-byte tuple otherwise
+#[cfg(feature = "serde")]
+impl<const N: usize> serde::Serialize for NodeId<N> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeTuple;
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            let mut tuple = serializer.serialize_tuple(N)?;
+            for b in &self.0 {
+                tuple.serialize_element(b)?;
+            }
+            tuple.end()
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const N: usize> serde::Deserialize<'de> for NodeId<N> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BytesVisitor<const N: usize>;
+
+        impl<'de, const N: usize> serde::de::Visitor<'de> for BytesVisitor<N> {
+            type Value = NodeId<N>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{N} bytes")
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut bytes = [0u8; N];
+                for (i, b) in bytes.iter_mut().enumerate() {
+                    *b = seq
+                        .next_element()?
+                        .ok_or_else(|| serde::de::Error::invalid_length(i, &self))?;
+                }
+                Ok(NodeId::from_bytes(bytes))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+            NodeId::from_hex(&s).map_err(serde::de::Error::custom)
+        } else {
+            deserializer.deserialize_tuple(N, BytesVisitor::<N>)
+        }
+    }
+}
+
+#[cfg(test)]
+mod node_id_tests {
+    use super::*;
+
+    #[test]
+    fn from_hex_accepts_either_case_and_an_optional_prefix() {
+        let id: NodeId = NodeId::random_with(&mut StdRng::seed_from_u64(16));
+        let hex = id.to_string();
+        assert_eq!(NodeId::from_hex(&hex), Ok(id));
+        assert_eq!(NodeId::from_hex(&hex.to_uppercase()), Ok(id));
+        assert_eq!(NodeId::from_hex(&format!("0x{hex}")), Ok(id));
+        assert_eq!(NodeId::from_hex(&format!("0X{}", hex.to_uppercase())), Ok(id));
+        assert_eq!(hex.parse::<NodeId>(), Ok(id));
+    }
+
+    #[test]
+    fn from_hex_reports_wrong_lengths_and_invalid_characters() {
+        let hex = "00".repeat(ID_BYTES);
+        assert_eq!(NodeId::<ID_BYTES>::from_hex(""), Err(NodeIdParseError::WrongLength(0)));
+        assert_eq!(NodeId::<ID_BYTES>::from_hex(&hex[1..]), Err(NodeIdParseError::WrongLength(39)));
+        assert_eq!(NodeId::<ID_BYTES>::from_hex(&format!("{hex}0")), Err(NodeIdParseError::WrongLength(41)));
+        assert_eq!(NodeId::<ID_BYTES>::from_hex("0x0"), Err(NodeIdParseError::WrongLength(1)));
+        let bad = format!("{}g", &hex[1..]);
+        assert_eq!(NodeId::<ID_BYTES>::from_hex(&bad), Err(NodeIdParseError::InvalidChar('g')));
+        // multi-byte characters count as one character each, not by their UTF-8 length
+        let wide = format!("{}é", &hex[1..]);
+        assert_eq!(NodeId::<ID_BYTES>::from_hex(&wide), Err(NodeIdParseError::InvalidChar('é')));
+    }
+
+    #[test]
+    fn random_with_prefix_keeps_every_prefix_bit() {
+        let mut rng = StdRng::seed_from_u64(17);
+        for bit_len in 0..=NodeId::<ID_BYTES>::BITS {
+            for _ in 0..20 {
+                let prefix: NodeId = NodeId::random_with(&mut rng);
+                let id = NodeId::<ID_BYTES>::random_with_prefix(&prefix.0, bit_len, &mut rng);
+                assert!(id.common_prefix_len(&prefix) >= bit_len, "{bit_len}-bit prefix {prefix} not kept in {id}");
+                assert!((0..bit_len).all(|i| id.bit(i) == prefix.bit(i)));
+            }
+        }
+    }
+
+    #[test]
+    fn random_with_prefix_accepts_a_short_prefix_and_randomizes_the_rest() {
+        let mut rng = StdRng::seed_from_u64(17);
+        // 0b101 followed by bits the prefix slice does not cover
+        let ids: Vec<NodeId> = (0..64).map(|_| NodeId::random_with_prefix(&[0b1011_0000], 3, &mut rng)).collect();
+        assert!(ids.iter().all(|id| id.0[0] >> 5 == 0b101));
+        assert!(ids.iter().any(|id| id.bit(3)) && ids.iter().any(|id| !id.bit(3)), "bits past the prefix stay random");
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), ids.len());
+    }
+
+    #[test]
+    #[should_panic(expected = "prefix holds only 8 bits")]
+    fn random_with_prefix_rejects_a_prefix_shorter_than_bit_len() {
+        NodeId::<ID_BYTES>::random_with_prefix(&[0xff], 9, &mut StdRng::seed_from_u64(17));
+    }
+
+    #[test]
+    fn flipping_bit_i_lands_in_bucket_i() {
+        let id: NodeId = NodeId::random_with(&mut StdRng::seed_from_u64(19));
+        for i in 0..NodeId::<ID_BYTES>::BITS {
+            let flipped = id.with_bit_flipped(i);
+            assert_ne!(flipped.bit(i), id.bit(i));
+            assert_eq!(id.bucket_index(&flipped), Some(i));
+            assert_eq!(flipped.with_bit_flipped(i), id);
+        }
+    }
+
+    #[test]
+    fn bits_are_indexed_big_endian() {
+        let id: NodeId = NodeId::from_u8(0b1000_0001);
+        assert!(id.bit(0) && id.bit(7));
+        assert!((1..7).chain(8..NodeId::<ID_BYTES>::BITS).all(|i| !id.bit(i)));
+        assert_eq!(NodeId::<ID_BYTES>::ZERO.with_bit_flipped(0), NodeId::from_u8(0x80));
+    }
+
+    #[test]
+    fn with_prefix_from_copies_only_the_leading_bits() {
+        let (zero, ones) = (NodeId::<ID_BYTES>::ZERO, NodeId::<ID_BYTES>::MAX);
+        assert_eq!(zero.with_prefix_from(&ones, 0), zero);
+        assert_eq!(zero.with_prefix_from(&ones, 3), NodeId::from_u8(0b1110_0000));
+        assert_eq!(zero.with_prefix_from(&ones, NodeId::<ID_BYTES>::BITS), ones);
+        let mut rng = StdRng::seed_from_u64(19);
+        for bits in 0..=NodeId::<ID_BYTES>::BITS {
+            let (a, b): (NodeId, NodeId) = (NodeId::random_with(&mut rng), NodeId::random_with(&mut rng));
+            let mixed = a.with_prefix_from(&b, bits);
+            assert!((0..bits).all(|i| mixed.bit(i) == b.bit(i)));
+            assert!((bits..NodeId::<ID_BYTES>::BITS).all(|i| mixed.bit(i) == a.bit(i)));
+        }
+    }
+
+    #[test]
+    fn boolean_operations_obey_their_identity_laws() {
+        let (zero, max) = (NodeId::<ID_BYTES>::ZERO, NodeId::<ID_BYTES>::MAX);
+        let mut rng = StdRng::seed_from_u64(21);
+        for _ in 0..100 {
+            let (a, b): (NodeId, NodeId) = (NodeId::random_with(&mut rng), NodeId::random_with(&mut rng));
+            assert_eq!(a.xor(&zero), a);
+            assert!(a.xor(&a).is_zero());
+            assert_eq!(a.xor(&b).xor(&b), a);
+            assert_eq!(a.or(&zero), a);
+            assert_eq!(a.or(&max), max);
+            assert_eq!(a.and(&max), a);
+            assert!(a.and(&zero).is_zero());
+            assert_eq!(a.not().not(), a);
+            assert!(a.and(&a.not()).is_zero());
+            assert_eq!(a.or(&a.not()), max);
+            // De Morgan
+            assert_eq!(a.and(&b).not(), a.not().or(&b.not()));
+            assert_eq!(a.xor(&b).0, a.xor_distance(&b).0);
+        }
+    }
+
+    #[test]
+    fn zero_and_max_bound_the_keyspace() {
+        let (zero, max) = (NodeId::<ID_BYTES>::ZERO, NodeId::<ID_BYTES>::MAX);
+        assert!(zero.is_zero() && !max.is_zero() && !NodeId::<ID_BYTES>::from_u8(1).is_zero());
+        assert_eq!(zero.not(), max);
+        assert_eq!((zero.0, max.0), ([0; ID_BYTES], [0xff; ID_BYTES]));
+        let id: NodeId = NodeId::random_with(&mut StdRng::seed_from_u64(21));
+        assert!(zero <= id && id <= max);
+    }
+
+    #[test]
+    fn a_mined_id_proves_its_work_and_a_random_one_does_not() {
+        let mut rng = StdRng::seed_from_u64(40);
+        let (mined, nonce) = NodeId::<ID_BYTES>::generate_with_pow(8, &mut rng);
+        assert!(verify_pow(&mined, nonce, 8));
+        let digest = Sha1::new().chain_update(mined.0).chain_update(nonce.to_be_bytes()).finalize();
+        assert_eq!(digest[0], 0);
+        // the nonce belongs to its ID; another ID would have to be mined anew
+        let random: NodeId = NodeId::random_with(&mut rng);
+        assert!(!verify_pow(&random, nonce, 8));
+        assert!(verify_pow(&random, random.mine_pow_nonce(8), 8));
+        assert!(verify_pow(&random, 0, 0), "zero bits of work are always proven");
+        let (free, free_nonce) = generate_pow_node_id(4);
+        assert!(verify_pow(&free, free_nonce, 4));
+    }
+
+    #[test]
+    fn distance_bits_covers_equal_ids_single_bit_differences_and_the_farthest_pair() {
+        let base: NodeId = NodeId::random_with(&mut StdRng::seed_from_u64(50));
+        assert_eq!((base.distance_bits(&base), base.leading_zeros_xor(&base)), (0, NodeId::<ID_BYTES>::BITS));
+        assert_eq!(base.bucket_index(&base), None);
+        for bit in 0..NodeId::<ID_BYTES>::BITS {
+            let mut flipped = base;
+            flipped.0[bit / 8] ^= 0x80 >> (bit % 8);
+            assert_eq!(base.distance_bits(&flipped), NodeId::<ID_BYTES>::BITS - bit);
+            assert_eq!(base.leading_zeros_xor(&flipped), bit);
+            assert_eq!(base.bucket_index(&flipped), Some(NodeId::<ID_BYTES>::BITS - base.distance_bits(&flipped)));
+        }
+        assert_eq!(NodeId::<ID_BYTES>::ZERO.distance_bits(&NodeId::MAX), 160);
+        assert_eq!(NodeId::<ID_BYTES>::ZERO.leading_zeros_xor(&NodeId::MAX), 0);
+    }
+
+    #[test]
+    fn random_ids_in_a_bucket_land_in_that_bucket() {
+        let mut rng = StdRng::seed_from_u64(12);
+        for base in [NodeId::ZERO, NodeId::MAX, NodeId::random_with(&mut rng)] {
+            for bucket in 0..NodeId::<ID_BYTES>::BITS {
+                for _ in 0..50 {
+                    let id: NodeId = NodeId::random_in_bucket(&base, bucket, &mut rng);
+                    assert_eq!(base.bucket_index(&id), Some(bucket), "{base} bucket {bucket}: {id}");
+                }
+            }
+        }
+        // the bits below the fixed prefix are random, not copied from the base
+        let below: HashSet<NodeId> = (0..20).map(|_| NodeId::random_in_bucket(&NodeId::ZERO, 3, &mut rng)).collect();
+        assert!(below.len() > 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn ids_and_records_round_trip_as_hex_in_json_and_raw_bytes_in_bincode() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let id: NodeId = NodeId::random_with(&mut rng);
+        let json = serde_json::to_string(&id).expect("ids serialize");
+        assert_eq!(json, format!("\"{id}\""), "the full lowercase hex form");
+        assert_eq!(serde_json::from_str::<NodeId>(&json).expect("hex parses"), id);
+        assert!(serde_json::from_str::<NodeId>("\"not hex\"").is_err());
+        let bytes = bincode::serialize(&id).expect("ids serialize");
+        assert_eq!(bytes, id.0, "a fixed 20-byte array, with no length prefix");
+        assert_eq!(bincode::deserialize::<NodeId>(&bytes).expect("bytes parse"), id);
+        let short: NodeId<4> = NodeId::random_with(&mut rng);
+        assert_eq!(bincode::deserialize::<NodeId<4>>(&bincode::serialize(&short).expect("ids serialize")).expect("bytes parse"), short);
+
+        let record = StoredRecord { key: id, value: b"value".to_vec(), ttl_remaining: DEFAULT_TTL };
+        let json = serde_json::to_string(&record).expect("records serialize");
+        assert!(json.contains(&id.to_string()), "{json}");
+        assert_eq!(serde_json::from_str::<StoredRecord>(&json).expect("records parse"), record);
+        let bytes = bincode::serialize(&record).expect("records serialize");
+        assert_eq!(bincode::deserialize::<StoredRecord>(&bytes).expect("records parse"), record);
+    }
+
+    #[test]
+    fn ids_sort_in_big_endian_numeric_order() {
+        // the ID as a 160-bit number: its top 128 bits, then its low 32
+        fn as_u128_pair(id: &NodeId) -> (u128, u128) {
+            let (high, low) = id.0.split_at(16);
+            let low: [u8; 4] = low.try_into().expect("20-byte IDs");
+            (u128::from_be_bytes(high.try_into().expect("16 bytes")), u32::from_be_bytes(low) as u128)
+        }
+        let mut rng = StdRng::seed_from_u64(22);
+        let mut ids: Vec<NodeId> = (0..200).map(|_| NodeId::random_with(&mut rng)).collect();
+        // pairs equal in their top 128 bits are told apart by the low 32
+        let shared = ids[0];
+        ids.extend((0..20).map(|_| NodeId::random_with(&mut rng).with_prefix_from(&shared, 128)));
+        ids.extend([NodeId::ZERO, NodeId::MAX, NodeId::from_u8(1)]);
+
+        let mut sorted = ids.clone();
+        sorted.sort();
+        let mut brute = ids;
+        brute.sort_by_key(as_u128_pair);
+        assert_eq!(sorted, brute);
+        assert_eq!((sorted[0], sorted[sorted.len() - 1]), (NodeId::ZERO, NodeId::MAX));
+    }
+}
+
+/// An XOR distance between two IDs, ordered as a big-endian unsigned integer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Distance<const N: usize = ID_BYTES>([u8; N]);
+
+impl<const N: usize> Distance<N> {
+    /// The distance as a big-endian unsigned integer
+    pub fn as_bytes(&self) -> &[u8; N] {
+        &self.0
+    }
+
+    /// Number of leading zero bits (0..=N*8, N*8 for a zero distance).
+    /// Scans byte-by-byte and counts leading zeros of the first non-zero byte.
+    pub fn leading_zeros(&self) -> usize {
+        for (i, b) in self.0.iter().enumerate() {
+            if *b != 0 {
+                return i * 8 + b.leading_zeros() as usize;
+            }
+        }
+        N * 8
+    }
+
+    /// True when the two IDs that produced this distance are equal
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|b| *b == 0)
+    }
+
+    /// The distance as a fraction of the whole keyspace, in [0, 1)
+    pub fn to_f64(self) -> f64 {
+        self.0
+            .iter()
+            .rev()
+            .fold(0.0, |acc, b| (acc + f64::from(*b)) / 256.0)
+    }
+
+    /// Approximate base-2 logarithm of the distance (negative infinity for zero)
+    pub fn log2(self) -> f64 {
+        self.to_f64().log2() + (N * 8) as f64
+    }
+}
+
+/// The `cap` nodes closest to a target among those pushed so far. A max-heap on distance
+/// keeps the farthest kept node on top, so a closer arrival replaces it in O(log cap).
+#[derive(Debug, Clone)]
+pub struct ClosestQueue<const N: usize = ID_BYTES> {
+    target: NodeId<N>,
+    heap: BinaryHeap<(Distance<N>, NodeId<N>)>,
+    cap: usize,
+}
+
+impl<const N: usize> ClosestQueue<N> {
+    pub fn new(target: NodeId<N>, cap: usize) -> Self {
+        Self {
+            target,
+            heap: BinaryHeap::with_capacity(cap + 1),
+            cap,
+        }
+    }
+
+    /// Offer a node; returns whether it is now among the closest (i.e. the set changed)
+    pub fn push(&mut self, id: NodeId<N>) -> bool {
+        if self.heap.iter().any(|(_, kept)| *kept == id) {
+            return false;
+        }
+        let distance = self.target.xor_distance(&id);
+        if self.heap.len() == self.cap {
+            match self.heap.peek() {
+                Some((farthest, _)) if distance < *farthest => {
+                    self.heap.pop();
+                }
+                _ => return false,
+            }
+        }
+        self.heap.push((distance, id));
+        true
+    }
+
+    /// Drop a node (e.g. one that turned out to be unreachable)
+    pub fn remove(&mut self, id: &NodeId<N>) {
+        self.heap.retain(|(_, kept)| kept != id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// The nodes a lookup should query next: the `alpha` closest kept nodes not yet in
+    /// `queried`, or every one of them if the last round left the closest set as it was in
+    /// `previously_closest` (which is then updated). Empty once all the kept nodes have been
+    /// queried, which is when the paper's lookup terminates.
+    pub fn next_round(
+        &self,
+        queried: &HashSet<NodeId<N>>,
+        previously_closest: &mut Vec<NodeId<N>>,
+        alpha: usize,
+    ) -> Vec<NodeId<N>> {
+        let closest = self.to_sorted_vec();
+        let all_queried = closest.iter().all(|n| queried.contains(n));
+        if all_queried {
+            return Vec::new();
+        }
+        let width = if closest == *previously_closest { closest.len() } else { alpha };
+        let batch = closest.iter().filter(|n| !queried.contains(n)).take(width).copied().collect();
+        *previously_closest = closest;
+        batch
+    }
+
+    /// The kept nodes, closest first
+    pub fn to_sorted_vec(&self) -> Vec<NodeId<N>> {
+        self.clone().into_vec()
+    }
+
+    /// The kept nodes, closest first
+    pub fn into_vec(self) -> Vec<NodeId<N>> {
+        self.heap.into_sorted_vec().into_iter().map(|(_, id)| id).collect()
+    }
+}
+
+/// A known peer and what we have observed of its liveness
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Contact<const N: usize = ID_BYTES> {
+    id: NodeId<N>,
+    // when we last heard from it; None (e.g. after deserializing) means unknown
+    #[cfg_attr(feature = "serde", serde(skip))]
+    last_seen: Option<Instant>,
+    failed_attempts: u32,  // RPCs to it that went unanswered since it last answered
+    rtt: Option<Duration>, // round-trip time of its last answer, once measured
+    verified: bool,        // false until it answers, for contacts restored from a saved table
+    #[cfg_attr(feature = "serde", serde(default))]
+    origin: u32,           // where the peer connects from (later an IP prefix), for diversity limits
+}
+
+impl<const N: usize> Contact<N> {
+    /// A peer heard from at `now`
+    fn new(id: NodeId<N>, now: Instant) -> Self {
+        Self {
+            id,
+            last_seen: Some(now),
+            failed_attempts: 0,
+            rtt: None,
+            verified: true,
+            origin: 0,
+        }
+    }
+
+    /// The same contact, tagged as connecting from `origin`
+    fn with_origin(self, origin: u32) -> Self {
+        Self { origin, ..self }
+    }
+
+    /// A peer known only from a saved routing table, not yet heard from
+    fn unverified(id: NodeId<N>) -> Self {
+        Self {
+            id,
+            last_seen: None,
+            failed_attempts: 0,
+            rtt: None,
+            verified: false,
+            origin: 0,
+        }
+    }
+
+    /// It answered again: refresh `last_seen` and forgive earlier failures
+    fn seen(&mut self, now: Instant) {
+        self.last_seen = Some(now);
+        self.failed_attempts = 0;
+        self.verified = true;
+    }
+
+    /// Whether it has missed at least `threshold` RPCs in a row
+    pub fn is_stale(&self, threshold: u32) -> bool {
+        self.failed_attempts >= threshold
+    }
+}
+
+/// Peers seen while their bucket was full, waiting to replace an unresponsive entry
+/// (up to the bucket size, least-recently-seen first)
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReplacementCache<const N: usize = ID_BYTES> {
+    peers: Vec<Contact<N>>,
+}
+
+impl<const N: usize> ReplacementCache<N> {
+    /// Remember a candidate as most recently seen, dropping the oldest when over `capacity`
+    fn push(&mut self, contact: Contact<N>, capacity: usize) {
+        self.remove(&contact.id);
+        self.peers.push(contact);
+        if self.peers.len() > capacity {
+            self.peers.remove(0);
+        }
+    }
+
+    /// Remember a candidate behind every other, so any candidate heard from first-hand is
+    /// promoted before it; false (and nothing changes) if the cache is full or has it already
+    fn push_last_resort(&mut self, contact: Contact<N>, capacity: usize) -> bool {
+        if self.peers.len() >= capacity || self.peers.iter().any(|p| p.id == contact.id) {
+            return false;
+        }
+        self.peers.insert(0, contact);
+        true
+    }
+
+    /// Take the most recently seen candidate that `allowed` accepts
+    fn pop_freshest(&mut self, allowed: impl Fn(&Contact<N>) -> bool) -> Option<Contact<N>> {
+        let pos = self.peers.iter().rposition(allowed)?;
+        Some(self.peers.remove(pos))
+    }
+
+    fn remove(&mut self, peer: &NodeId<N>) -> bool {
+        if let Some(pos) = self.peers.iter().position(|p| p.id == *peer) {
+            self.peers.remove(pos);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+}
+
+/// A k-bucket: up to k peers in least-recently-seen order (oldest first), plus
+/// a replacement cache for newcomers that arrive while it is full
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KBucket<const N: usize = ID_BYTES> {
+    peers: Vec<Contact<N>>,
+    replacements: ReplacementCache<N>,
+    // last lookup into this bucket's range; None (e.g. after deserializing) means refresh now
+    #[cfg_attr(feature = "serde", serde(skip))]
+    last_refresh: Option<Instant>,
+    // has held a contact; a bucket that never has is left out of refreshes
+    #[cfg_attr(feature = "serde", serde(default))]
+    filled: bool,
+}
+
+impl<const N: usize> KBucket<N> {
+    /// Move an existing peer to the tail, or append a new one from `origin`, as seen at
+    /// `now`; when the bucket already holds `capacity` peers the newcomer waits in the
+    /// replacement cache instead, and the least-recently-seen peer it could replace is returned
+    fn touch(&mut self, peer: &NodeId<N>, origin: u32, now: Instant, capacity: usize) -> Option<NodeId<N>> {
+        if let Some(pos) = self.position(peer) {
+            let mut existing = self.peers.remove(pos);
+            existing.seen(now);
+            self.peers.push(existing);
+        } else if self.peers.len() < capacity {
+            self.replacements.remove(peer);
+            self.peers.push(Contact::new(*peer, now).with_origin(origin));
+            self.filled = true;
+        } else {
+            self.replacements.push(Contact::new(*peer, now).with_origin(origin), capacity);
+            return self.peers.first().map(|p| p.id);
+        }
+        None
+    }
+
+    /// The contact that has missed the most RPCs in a row, least recently seen among ties
+    fn least_reliable(&self) -> Option<NodeId<N>> {
+        // peers run from least to most recently seen, and max_by_key keeps the last maximum
+        self.peers.iter().rev().max_by_key(|p| p.failed_attempts).map(|p| p.id)
+    }
+
+    /// Peers not heard from within `threshold` of `now`, least recently seen first; a peer
+    /// never heard from (restored or learned second-hand) counts too. Unlike `Contact::is_stale`
+    /// this goes by silence, not by missed RPCs.
+    fn stale_peers(&self, now: Instant, threshold: Duration) -> Vec<NodeId<N>> {
+        self.peers
+            .iter()
+            .filter(|p| p.last_seen.is_none_or(|seen| now.saturating_duration_since(seen) >= threshold))
+            .map(|p| p.id)
+            .collect()
+    }
+
+    /// Whether another contact from `origin` fits under a cap of `max_per_origin` per bucket
+    fn admits(&self, origin: u32, max_per_origin: Option<usize>) -> bool {
+        max_per_origin.is_none_or(|max| self.peers.iter().filter(|p| p.origin == origin).count() < max)
+    }
+
+    /// Take the freshest replacement candidate whose origin still fits in the bucket
+    fn take_replacement(&mut self, max_per_origin: Option<usize>) -> Option<Contact<N>> {
+        let counts = |origin| self.peers.iter().filter(|p| p.origin == origin).count();
+        let full: HashSet<u32> = match max_per_origin {
+            Some(max) => self.peers.iter().map(|p| p.origin).filter(|&o| counts(o) >= max).collect(),
+            None => HashSet::new(),
+        };
+        self.replacements.pop_freshest(|c| !full.contains(&c.origin))
+    }
+
+    fn position(&self, peer: &NodeId<N>) -> Option<usize> {
+        self.peers.iter().position(|p| p.id == *peer)
+    }
+
+    /// Remove a peer if present; returns whether it was there
+    fn remove(&mut self, peer: &NodeId<N>) -> bool {
+        if let Some(pos) = self.position(peer) {
+            self.peers.remove(pos);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Promote the freshest replacement candidates while there is room for them
+    fn fill_from_replacements(&mut self, capacity: usize, max_per_origin: Option<usize>) {
+        while self.peers.len() < capacity {
+            let Some(candidate) = self.take_replacement(max_per_origin) else { break };
+            self.peers.push(candidate);
+        }
+    }
+
+    /// Remove a peer that failed to answer (or left) and promote the freshest replacement
+    /// candidate allowed by `max_per_origin` into its place; returns whether the peer was in
+    /// the bucket
+    fn evict(&mut self, peer: &NodeId<N>, max_per_origin: Option<usize>) -> bool {
+        self.replacements.remove(peer);
+        if !self.remove(peer) {
+            return false;
+        }
+        if let Some(candidate) = self.take_replacement(max_per_origin) {
+            self.peers.push(candidate);
+        }
+        true
+    }
+
+    /// Evict the least-recently-seen stale peer, if there is one and a replacement candidate
+    /// waiting to take its place; returns whether an eviction happened
+    fn evict_stale(&mut self, threshold: u32, max_per_origin: Option<usize>) -> bool {
+        if self.replacements.is_empty() {
+            return false;
+        }
+        match self.peers.iter().find(|p| p.is_stale(threshold)).map(|p| p.id) {
+            Some(stale) => self.evict(&stale, max_per_origin),
+            None => false,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.peers.len()
+    }
+}
+
+/// Observer of routing-table changes, e.g. to gather churn statistics or to check an exact
+/// sequence of events. Each call names the table's owner, so one observer can watch every
+/// node of a network. All methods do nothing by default.
+pub trait RoutingEvents<const N: usize = ID_BYTES>: fmt::Debug + Send + Sync {
+    /// `peer` took a free slot in one of `owner`'s buckets
+    fn on_added(&self, _owner: &NodeId<N>, _peer: &NodeId<N>) {}
+    /// `peer`, already in a bucket, was heard from again
+    fn on_refreshed(&self, _owner: &NodeId<N>, _peer: &NodeId<N>) {}
+    /// `peer` left its bucket: it failed, left the network, or gave up its slot
+    fn on_evicted(&self, _owner: &NodeId<N>, _peer: &NodeId<N>) {}
+    /// `peer` has just missed `stale_after` RPCs in a row, so find_node stops handing it out
+    fn on_stale(&self, _owner: &NodeId<N>, _peer: &NodeId<N>) {}
+    /// `peer` moved from the replacement cache into its bucket
+    fn on_promoted_from_cache(&self, _owner: &NodeId<N>, _peer: &NodeId<N>) {}
+}
+
+/// The kinds of change `RoutingEvents` reports
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RoutingChange {
+    Added,
+    Refreshed,
+    Evicted,
+    Stale,
+    Promoted,
+}
+
+/// One routing-table change, as `RoutingEventLog` records it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RoutingEvent<const N: usize = ID_BYTES> {
+    owner: NodeId<N>,
+    change: RoutingChange,
+    peer: NodeId<N>,
+}
+
+/// `RoutingEvents` that records every change in order
+#[derive(Debug, Default)]
+pub struct RoutingEventLog<const N: usize = ID_BYTES> {
+    events: Mutex<Vec<RoutingEvent<N>>>,
+}
+
+impl<const N: usize> RoutingEventLog<N> {
+    fn record(&self, owner: &NodeId<N>, change: RoutingChange, peer: &NodeId<N>) {
+        let event = RoutingEvent { owner: *owner, change, peer: *peer };
+        self.events.lock().expect("event log poisoned").push(event);
+    }
+
+    /// Everything recorded so far, oldest first
+    pub fn events(&self) -> Vec<RoutingEvent<N>> {
+        self.events.lock().expect("event log poisoned").clone()
+    }
+
+    /// How many changes of each kind were recorded
+    pub fn counts(&self) -> HashMap<RoutingChange, usize> {
+        let mut counts = HashMap::new();
+        for event in self.events.lock().expect("event log poisoned").iter() {
+            *counts.entry(event.change).or_default() += 1;
+        }
+        counts
+    }
+}
+
+impl<const N: usize> RoutingEvents<N> for RoutingEventLog<N> {
+    fn on_added(&self, owner: &NodeId<N>, peer: &NodeId<N>) {
+        self.record(owner, RoutingChange::Added, peer);
+    }
+
+    fn on_refreshed(&self, owner: &NodeId<N>, peer: &NodeId<N>) {
+        self.record(owner, RoutingChange::Refreshed, peer);
+    }
+
+    fn on_evicted(&self, owner: &NodeId<N>, peer: &NodeId<N>) {
+        self.record(owner, RoutingChange::Evicted, peer);
+    }
+
+    fn on_stale(&self, owner: &NodeId<N>, peer: &NodeId<N>) {
+        self.record(owner, RoutingChange::Stale, peer);
+    }
+
+    fn on_promoted_from_cache(&self, owner: &NodeId<N>, peer: &NodeId<N>) {
+        self.record(owner, RoutingChange::Promoted, peer);
+    }
+}
+
+/// Per-node routing table: one k-bucket per shared-prefix length with the owner's ID.
+/// Bucket `i` holds peers whose IDs agree with ours on exactly the first `i` bits, except
+/// the last, which holds every peer sharing a longer prefix too: our own neighborhood.
+///
+/// This is the paper's binary tree with its one splittable branch laid out flat. The table
+/// starts as a single bucket covering the whole keyspace, and only the last bucket splits
+/// when it overflows, so buckets exist only as deep as the network is dense around us.
+///
+/// With `bits_per_bucket` b above 1 (the paper's acceleration), each split consumes b bits
+/// of the prefix instead of one: a level holds the peers sharing between `level * b` and
+/// `level * b + b - 1` bits with us, spread over 2^b - 1 buckets by their next b bits, so a
+/// lookup gets about b bits closer per hop.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RoutingTable<const N: usize = ID_BYTES> {
+    own_id: NodeId<N>,
+    k: usize,           // bucket size
+    max_buckets: usize, // splitting stops here
+    stale_after: u32,   // consecutive failures before a contact is no longer handed out
+    #[cfg_attr(feature = "serde", serde(default = "one"))]
+    bits_per_bucket: usize, // prefix bits each split consumes
+    #[cfg_attr(feature = "serde", serde(default))]
+    max_per_origin: Option<usize>, // contacts one origin may hold in a bucket, if capped
+    #[cfg_attr(feature = "serde", serde(default))]
+    origin_rejections: u64, // newcomers sent to a replacement cache by that cap
+    #[cfg_attr(feature = "serde", serde(default))]
+    eviction_policy: EvictionPolicy, // which contact a full bucket gives up
+    #[cfg_attr(feature = "serde", serde(default))]
+    bucket_sizes: Option<BucketSizes>, // per-depth capacities, if not k everywhere
+    #[cfg_attr(feature = "serde", serde(default))]
+    max_contacts: Option<usize>, // contacts plus replacement candidates across all buckets, if capped
+    #[cfg_attr(feature = "serde", serde(skip))]
+    events: Option<Arc<dyn RoutingEvents<N>>>, // told about every change, if set
+    buckets: Vec<KBucket<N>>,
+}
+
+/// Where `RoutingTable::merge_contact` put a contact
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeOutcome {
+    Inserted,
+    Cached,
+    Rejected,
+}
+
+/// How a batch of contacts fared in `Node::merge_contacts`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MergeSummary {
+    pub inserted: usize, // joined a bucket, unverified
+    pub cached: usize,   // waiting in a replacement cache
+    pub rejected: usize, // ourselves, already known, lacking proof of work, or no room anywhere
+}
+
+/// Default `bits_per_bucket` for tables saved before it existed
+#[cfg(feature = "serde")]
+fn one() -> usize {
+    1
+}
+
+impl<const N: usize> RoutingTable<N> {
+    /// Empty table for `own_id` shaped by `config`: one bucket, counted as refreshed at `now`
+    pub fn new(own_id: NodeId<N>, now: Instant, config: &NetworkConfig) -> Self {
+        let bucket = KBucket {
+            last_refresh: Some(now),
+            ..KBucket::default()
+        };
+        let b = config.bits_per_bucket;
+        // levels may go on while a level still leaves a bit for the neighborhood bucket
+        let most_buckets = (NodeId::<N>::BITS - 1) / b * ((1 << b) - 1) + 1;
+        Self {
+            own_id,
+            k: config.k,
+            max_buckets: config.bucket_count.clamp(1, most_buckets),
+            stale_after: config.stale_after,
+            bits_per_bucket: b,
+            max_per_origin: config.max_per_origin_per_bucket,
+            origin_rejections: 0,
+            eviction_policy: config.eviction_policy,
+            bucket_sizes: config.bucket_sizes,
+            max_contacts: config.max_contacts,
+            events: None,
+            buckets: vec![bucket],
+        }
+    }
+
+    /// Buckets each level of the tree splits into: 2^b - 1
+    fn buckets_per_level(&self) -> usize {
+        (1 << self.bits_per_bucket) - 1
+    }
+
+    /// Contacts bucket `index` may hold: k, or as `bucket_sizes` says for its depth (the
+    /// prefix bits its peers share with us, at least; the last bucket counts as its level)
+    fn capacity(&self, index: usize) -> usize {
+        match self.bucket_sizes {
+            Some(sizes) => {
+                let depth = index / self.buckets_per_level() * self.bits_per_bucket;
+                if depth >= sizes.near_depth { sizes.near } else { sizes.far }
+            }
+            None => self.k,
+        }
+    }
+
+    /// Levels split off so far; the last bucket holds every peer deeper than them
+    fn levels(&self) -> usize {
+        (self.buckets.len() - 1) / self.buckets_per_level()
+    }
+
+    /// The b bits of our distance to `peer` at `level`, as a number (bits past the end of
+    /// the ID count as zero)
+    fn digit(&self, peer: &NodeId<N>, level: usize) -> usize {
+        let distance = self.own_id.xor(peer);
+        (level * self.bits_per_bucket..(level + 1) * self.bits_per_bucket).fold(0, |digit, bit| {
+            let set = bit < NodeId::<N>::BITS && distance.0[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            digit << 1 | usize::from(set)
+        })
+    }
+
+    /// Index of the bucket a peer belongs in, or None for our own ID
+    fn bucket_for(&self, peer: &NodeId<N>) -> Option<usize> {
+        let cpl = self.own_id.common_prefix_len(peer);
+        if cpl == NodeId::<N>::BITS {
+            return None;
+        }
+        let level = cpl / self.bits_per_bucket;
+        if level >= self.levels() {
+            return Some(self.buckets.len() - 1);
+        }
+        // the digit is non-zero, since the peer differs from us within this level
+        Some(level * self.buckets_per_level() + self.digit(peer, level) - 1)
+    }
+
+    /// Record that we heard from `peer`, connecting from `origin`, at `now` (LRU within its
+    /// bucket, overflow to its replacement cache). If the bucket was full, names the peer to
+    /// ping before evicting, chosen by the table's `EvictionPolicy` (`OldestFirst` evicts at
+    /// once instead). A stale contact makes way for the newcomer without a ping. A newcomer
+    /// whose origin already has `max_per_origin` contacts in the bucket waits in the
+    /// replacement cache too, with nobody pinged on its behalf. A newcomer that does not fit
+    /// under `max_contacts` is `Refused`.
+    pub fn insert(&mut self, peer: &NodeId<N>, origin: u32, now: Instant) -> TrackOutcome<N> {
+        let Some(index) = self.make_room_for(peer) else {
+            return TrackOutcome::Tracked;
+        };
+        if !self.has_room_under_cap(index, peer) {
+            return TrackOutcome::Refused;
+        }
+        let capacity = self.capacity(index);
+        let (max_per_origin, stale_after, policy) = (self.max_per_origin, self.stale_after, self.eviction_policy);
+        let known = self.buckets[index].position(peer).is_some();
+        if !known && !self.buckets[index].admits(origin, max_per_origin) {
+            self.buckets[index].replacements.push(Contact::new(*peer, now).with_origin(origin), capacity);
+            self.origin_rejections += 1;
+            return TrackOutcome::Tracked;
+        }
+        let candidate = self.with_bucket(index, |bucket| {
+            let least_recent = bucket.touch(peer, origin, now, capacity)?;
+            if bucket.evict_stale(stale_after, max_per_origin) {
+                return None;
+            }
+            match policy {
+                EvictionPolicy::LruPing => Some(least_recent),
+                EvictionPolicy::LeastReliable => bucket.least_reliable(),
+                EvictionPolicy::OldestFirst => {
+                    // the newcomer is now the freshest replacement, so it takes the freed slot
+                    bucket.evict(&least_recent, max_per_origin);
+                    None
+                }
+            }
+        });
+        if known {
+            self.notify(|events, owner| events.on_refreshed(owner, peer));
+        }
+        match candidate {
+            Some(evict_candidate) => TrackOutcome::BucketFull { evict_candidate },
+            None => TrackOutcome::Tracked,
+        }
+    }
+
+    /// Apply `change` to bucket `index` and tell the observer, if any, what it did: peers
+    /// that left were evicted, and peers that arrived were promoted if they were waiting in
+    /// the replacement cache, added otherwise
+    fn with_bucket<T>(&mut self, index: usize, change: impl FnOnce(&mut KBucket<N>) -> T) -> T {
+        if self.events.is_none() {
+            return change(&mut self.buckets[index]);
+        }
+        let (before, waiting) = self.bucket_state(index);
+        let result = change(&mut self.buckets[index]);
+        self.report_changes(index, &before, &waiting);
+        result
+    }
+
+    /// Bucket `index`'s peers and replacement candidates, to compare against after a change
+    fn bucket_state(&self, index: usize) -> (Vec<NodeId<N>>, HashSet<NodeId<N>>) {
+        let bucket = &self.buckets[index];
+        (bucket.peers.iter().map(|p| p.id).collect(), bucket.replacements.peers.iter().map(|c| c.id).collect())
+    }
+
+    /// Tell the observer how bucket `index` differs from the `bucket_state` taken earlier
+    fn report_changes(&self, index: usize, before: &[NodeId<N>], waiting: &HashSet<NodeId<N>>) {
+        let Some(events) = &self.events else { return };
+        let after = &self.buckets[index].peers;
+        for peer in before.iter().filter(|id| after.iter().all(|p| p.id != **id)) {
+            events.on_evicted(&self.own_id, peer);
+        }
+        for peer in after.iter().map(|p| &p.id).filter(|id| !before.contains(id)) {
+            if waiting.contains(peer) {
+                events.on_promoted_from_cache(&self.own_id, peer);
+            } else {
+                events.on_added(&self.own_id, peer);
+            }
+        }
+    }
+
+    /// Hand the observer, if any, one event along with this table's owner
+    fn notify(&self, event: impl FnOnce(&dyn RoutingEvents<N>, &NodeId<N>)) {
+        if let Some(events) = &self.events {
+            event(events.as_ref(), &self.own_id);
+        }
+    }
+
+    /// Index of the bucket `peer` belongs in, after splitting the last bucket as often as
+    /// needed to fit it; None for our own ID
+    fn make_room_for(&mut self, peer: &NodeId<N>) -> Option<usize> {
+        let mut index = self.bucket_for(peer)?;
+        while self.should_split(index, peer) {
+            self.split_last();
+            index = self.bucket_for(peer)?;
+        }
+        Some(index)
+    }
+
+    /// Add `peer`, known only second-hand (from a saved table or another node's answer), as
+    /// an unverified contact, first in line for eviction. It never displaces a known contact,
+    /// so it is dropped if its bucket is full; returns whether it was added.
+    fn insert_unverified(&mut self, peer: &NodeId<N>) -> bool {
+        if self.contact(peer).is_some() {
+            return false;
+        }
+        let Some(index) = self.make_room_for(peer) else {
+            return false;
+        };
+        let capacity = self.capacity(index);
+        if self.buckets[index].len() >= capacity || !self.has_room_under_cap(index, peer) {
+            return false;
+        }
+        let bucket = &mut self.buckets[index];
+        bucket.replacements.remove(peer);
+        bucket.peers.insert(0, Contact::unverified(*peer));
+        bucket.filled = true;
+        self.notify(|events, owner| events.on_added(owner, peer));
+        true
+    }
+
+    /// Merge a contact copied from another node's table: it joins its bucket unverified if
+    /// there is room (and its origin is under the cap), or else waits behind the other
+    /// replacement candidates. Our own ID, contacts we already have and contacts that find
+    /// the replacement cache full too are rejected.
+    pub fn merge_contact(&mut self, contact: &Contact<N>) -> MergeOutcome {
+        let peer = &contact.id;
+        if self.contact(peer).is_some() {
+            return MergeOutcome::Rejected;
+        }
+        let Some(index) = self.make_room_for(peer) else {
+            return MergeOutcome::Rejected;
+        };
+        if !self.has_room_under_cap(index, peer) {
+            return MergeOutcome::Rejected;
+        }
+        let capacity = self.capacity(index);
+        let copy = Contact::unverified(*peer).with_origin(contact.origin);
+        let bucket = &mut self.buckets[index];
+        if bucket.len() >= capacity || !bucket.admits(contact.origin, self.max_per_origin) {
+            return if bucket.replacements.push_last_resort(copy, capacity) {
+                MergeOutcome::Cached
+            } else {
+                MergeOutcome::Rejected
+            };
+        }
+        bucket.replacements.remove(peer);
+        bucket.peers.insert(0, copy);
+        bucket.filled = true;
+        self.notify(|events, owner| events.on_added(owner, peer));
+        MergeOutcome::Inserted
+    }
+
+    /// Contacts plus replacement candidates across all buckets, the figure `max_contacts` caps
+    fn contact_count(&self) -> usize {
+        self.buckets.iter().map(|b| b.len() + b.replacements.len()).sum()
+    }
+
+    /// Whether `peer`, headed for bucket `index`, fits under `max_contacts`. A peer the bucket
+    /// or its cache already holds always fits; a newcomer gets room by dropping the oldest
+    /// replacement candidate of the farthest bucket that has any, and is refused if every
+    /// cache is empty, since contacts in the buckets are never dropped to make room.
+    fn has_room_under_cap(&mut self, index: usize, peer: &NodeId<N>) -> bool {
+        let Some(max) = self.max_contacts else { return true };
+        let bucket = &self.buckets[index];
+        if bucket.position(peer).is_some() || bucket.replacements.peers.iter().any(|c| c.id == *peer) {
+            return true;
+        }
+        while self.contact_count() >= max {
+            // within a level, a bigger digit (a later bucket) means a farther peer
+            let per_level = self.buckets_per_level();
+            let farthest = (0..self.buckets.len())
+                .filter(|&i| !self.buckets[i].replacements.is_empty())
+                .min_by_key(|&i| (i / per_level, std::cmp::Reverse(i % per_level)));
+            let Some(farthest) = farthest else { return false };
+            self.buckets[farthest].replacements.peers.remove(0);
+        }
+        true
+    }
+
+    /// Rough heap and inline bytes this table takes up: the table itself, its buckets and
+    /// the contact slots they have allocated
+    pub fn approx_bytes(&self) -> usize {
+        let slots: usize = self.buckets.iter().map(|b| b.peers.capacity() + b.replacements.peers.capacity()).sum();
+        std::mem::size_of::<Self>()
+            + self.buckets.capacity() * std::mem::size_of::<KBucket<N>>()
+            + slots * std::mem::size_of::<Contact<N>>()
+    }
+
+    /// Whether every contact and candidate sits in the bucket its ID maps to, no bucket holds
+    /// more than its capacity, our own ID is absent and no ID appears twice
+    pub fn is_well_formed(&self) -> bool {
+        let mut ids: HashSet<NodeId<N>> = HashSet::new();
+        self.buckets.iter().enumerate().all(|(index, bucket)| {
+            bucket.len() <= self.capacity(index)
+                && bucket.replacements.len() <= self.capacity(index)
+                && bucket
+                    .peers
+                    .iter()
+                    .chain(&bucket.replacements.peers)
+                    .all(|c| self.bucket_for(&c.id) == Some(index) && ids.insert(c.id))
+        })
+    }
+
+    /// A newcomer to full bucket `index` gets room by splitting it if the bucket covers our
+    /// own neighborhood (the last one) and the table may still grow
+    fn should_split(&self, index: usize, peer: &NodeId<N>) -> bool {
+        let bucket = &self.buckets[index];
+        index == self.buckets.len() - 1
+            && self.buckets.len() + self.buckets_per_level() <= self.max_buckets
+            && bucket.len() >= self.capacity(index)
+            && bucket.position(peer).is_none()
+    }
+
+    /// Split the last bucket, which holds every peer in the levels not yet split off, into
+    /// the next level's buckets (one per b-bit digit other than ours; with b = 1 that is the
+    /// peers sharing exactly `depth` bits, kept in place) and a new last bucket for the rest.
+    /// Replacement candidates move with their peers and fill any room opened up.
+    fn split_last(&mut self) {
+        let last = self.buckets.pop().expect("a table always has a bucket");
+        let fresh = || KBucket {
+            last_refresh: last.last_refresh,
+            ..KBucket::default()
+        };
+        self.buckets.extend((0..=self.buckets_per_level()).map(|_| fresh()));
+        let first = self.buckets.len() - 1 - self.buckets_per_level();
+        for contact in last.peers {
+            let index = self.bucket_for(&contact.id).expect("own ID is never a contact");
+            self.buckets[index].peers.push(contact);
+            self.buckets[index].filled = true;
+        }
+        for contact in last.replacements.peers {
+            let index = self.bucket_for(&contact.id).expect("own ID is never a contact");
+            self.buckets[index].replacements.peers.push(contact);
+        }
+        for index in first..self.buckets.len() {
+            let (capacity, max_per_origin) = (self.capacity(index), self.max_per_origin);
+            self.with_bucket(index, |bucket| bucket.fill_from_replacements(capacity, max_per_origin));
+        }
+    }
+
+    /// The contact bucket `index` would ping before giving its slot to a waiting candidate,
+    /// chosen by the table's `EvictionPolicy`; None if nobody is waiting there
+    fn eviction_candidate(&self, index: usize) -> Option<NodeId<N>> {
+        let bucket = self.buckets.get(index)?;
+        if bucket.replacements.is_empty() {
+            return None;
+        }
+        match self.eviction_policy {
+            EvictionPolicy::LeastReliable => bucket.least_reliable(),
+            EvictionPolicy::LruPing | EvictionPolicy::OldestFirst => bucket.peers.first().map(|p| p.id),
+        }
+    }
+
+    /// Drop `peer` after it failed to answer or left, letting its bucket's freshest
+    /// replacement in
+    fn evict(&mut self, peer: &NodeId<N>) -> bool {
+        let max_per_origin = self.max_per_origin;
+        match self.bucket_for(peer) {
+            Some(index) => self.with_bucket(index, |bucket| bucket.evict(peer, max_per_origin)),
+            None => false,
+        }
+    }
+
+    /// Number of candidates waiting in bucket `bucket`'s replacement cache
+    pub fn replacement_len(&self, bucket: usize) -> usize {
+        self.buckets.get(bucket).map_or(0, |b| b.replacements.len())
+    }
+
+    /// The bucket `peer` waits in as a replacement candidate, if it does
+    pub fn waiting_in(&self, peer: &NodeId<N>) -> Option<usize> {
+        let index = self.bucket_for(peer)?;
+        self.buckets[index].replacements.peers.iter().any(|c| c.id == *peer).then_some(index)
+    }
+
+    /// The contact for `peer`, if it is in a bucket (replacement candidates are not included)
+    pub fn contact(&self, peer: &NodeId<N>) -> Option<&Contact<N>> {
+        let bucket = &self.buckets[self.bucket_for(peer)?];
+        bucket.position(peer).map(|pos| &bucket.peers[pos])
+    }
+
+    /// Count an unanswered RPC against `peer`; returns its failures so far, or None if unknown.
+    /// Once that makes it stale it is evicted if a replacement candidate is waiting.
+    fn record_failure(&mut self, peer: &NodeId<N>) -> Option<u32> {
+        let index = self.bucket_for(peer)?;
+        let bucket = &mut self.buckets[index];
+        let pos = bucket.position(peer)?;
+        let contact = &mut bucket.peers[pos];
+        contact.failed_attempts += 1;
+        let failures = contact.failed_attempts;
+        if failures == self.stale_after {
+            self.notify(|events, owner| events.on_stale(owner, peer));
+        }
+        let (stale_after, max_per_origin) = (self.stale_after, self.max_per_origin);
+        self.with_bucket(index, |bucket| bucket.evict_stale(stale_after, max_per_origin));
+        Some(failures)
+    }
+
+    /// Note how long `peer` just took to answer, if it is in a bucket
+    fn record_rtt(&mut self, peer: &NodeId<N>, rtt: Duration) {
+        let Some(index) = self.bucket_for(peer) else { return };
+        let bucket = &mut self.buckets[index];
+        if let Some(pos) = bucket.position(peer) {
+            bucket.peers[pos].rtt = Some(rtt);
+        }
+    }
+
+    /// Remove a peer from whichever bucket holds it
+    pub fn remove(&mut self, peer: &NodeId<N>) -> bool {
+        match self.bucket_for(peer) {
+            Some(index) => self.with_bucket(index, |bucket| bucket.remove(peer)),
+            None => false,
+        }
+    }
+
+    /// Iterate over every known peer across all buckets
+    pub fn peers(&self) -> impl Iterator<Item = &NodeId<N>> {
+        self.contacts().map(|c| &c.id)
+    }
+
+    /// Iterate over every contact across all buckets
+    pub fn contacts(&self) -> impl Iterator<Item = &Contact<N>> {
+        self.buckets.iter().flat_map(|b| b.peers.iter())
+    }
+
+    /// Iterate over every contact with the index of the bucket holding it
+    pub fn contacts_by_bucket(&self) -> impl Iterator<Item = (usize, &Contact<N>)> {
+        self.buckets.iter().enumerate().flat_map(|(i, b)| b.peers.iter().map(move |c| (i, c)))
+    }
+
+    /// Total number of known peers
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(KBucket::len).sum()
+    }
+
+    /// Whether no peer is known yet
+    pub fn is_empty(&self) -> bool {
+        self.buckets.iter().all(|b| b.peers.is_empty())
+    }
+
+    /// The `index`th contact in bucket order, found by skipping whole buckets by their length
+    pub fn nth_contact(&self, mut index: usize) -> Option<&Contact<N>> {
+        for bucket in &self.buckets {
+            if index < bucket.len() {
+                return bucket.peers.get(index);
+            }
+            index -= bucket.len();
+        }
+        None
+    }
+
+    /// A contact chosen uniformly from every bucket's peers, so a peer in a crowded bucket is
+    /// no less likely than one alone in its bucket
+    pub fn random_contact(&self, rng: &mut impl Rng) -> Option<&Contact<N>> {
+        match self.len() {
+            0 => None,
+            len => self.nth_contact(rng.gen_range(0..len)),
+        }
+    }
+
+    /// Up to `n` distinct contacts chosen uniformly, as by `random_contact`
+    pub fn random_contacts(&self, n: usize, rng: &mut impl Rng) -> Vec<&Contact<N>> {
+        let len = self.len();
+        rand::seq::index::sample(rng, len, n.min(len))
+            .into_iter()
+            .filter_map(|index| self.nth_contact(index))
+            .collect()
+    }
+
+    /// Indices of buckets that have gone at least `interval` without a lookup. Buckets that
+    /// have never held a contact are left out: far ranges of a small network stay empty, and
+    /// a lookup into one each hour would only find that again.
+    pub fn buckets_needing_refresh(&self, now: Instant, interval: Duration) -> Vec<usize> {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.filled || !b.peers.is_empty())
+            .filter(|(_, b)| b.last_refresh.is_none_or(|t| now.saturating_duration_since(t) >= interval))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Share of the keyspace bucket `index` covers: 2^-(b(level + 1)) for a bucket split off
+    /// at a level, and everything left below the split levels for the last bucket
+    pub fn keyspace_share(&self, index: usize) -> f64 {
+        let level = index / self.buckets_per_level();
+        let bits = if index + 1 == self.buckets.len() { level * self.bits_per_bucket } else { (level + 1) * self.bits_per_bucket };
+        0.5f64.powi(bits as i32)
+    }
+
+    /// Random ID in bucket `index`'s range: with b = 1, its XOR distance to our ID has its
+    /// leading 1-bit exactly at bit `index` (counting from the most significant bit), or
+    /// anywhere from there down for the last bucket. In general it shares the bucket's level
+    /// with us and then has the bucket's digit in its distance.
+    pub fn random_id_in_bucket(&self, index: usize, rng: &mut impl Rng) -> NodeId<N> {
+        let b = self.bits_per_bucket;
+        let per_level = self.buckets_per_level();
+        if index == self.buckets.len() - 1 {
+            return NodeId::random_with_prefix(&self.own_id.0, self.levels() * b, rng);
+        }
+        self.random_id_at(index / per_level, index % per_level + 1, rng)
+    }
+
+    /// Random ID sharing our first `level` b-bit digits and then differing from us by
+    /// `digit` (1 to 2^b - 1): the range of that level's bucket for the digit, whether or not
+    /// the table has split that far yet
+    pub fn random_id_at(&self, level: usize, digit: usize, rng: &mut impl Rng) -> NodeId<N> {
+        let b = self.bits_per_bucket;
+        let mut prefix = self.own_id.0;
+        for j in 0..b {
+            let bit = level * b + j;
+            if bit < NodeId::<N>::BITS && digit & (1 << (b - 1 - j)) != 0 {
+                prefix[bit / 8] ^= 0x80 >> (bit % 8);
+            }
+        }
+        NodeId::random_with_prefix(&prefix, ((level + 1) * b).min(NodeId::<N>::BITS), rng)
+    }
+
+    /// Note that a lookup for `target` just covered the bucket it falls in
+    fn mark_refreshed(&mut self, target: &NodeId<N>, now: Instant) {
+        if let Some(index) = self.bucket_for(target) {
+            self.buckets[index].last_refresh = Some(now);
+        }
+    }
+
+    /// Up to `count` known peers closest to `target`, closest first, visiting buckets from
+    /// the target's outward and stopping once `count` are found. Stale contacts and ones not
+    /// yet verified are left out.
+    pub fn closest_k(&self, target: &NodeId<N>, count: usize) -> Vec<NodeId<N>> {
+        self.iter_closest(target)
+            .filter(|p| p.verified && !p.is_stale(self.stale_after))
+            .map(|p| p.id)
+            .take(count)
+            .collect()
+    }
+
+    /// Every contact in increasing XOR distance to `target`, produced lazily: buckets are
+    /// visited from the target's outward, and only the group being walked is held in a heap.
+    ///
+    /// With `c` the prefix length `target` shares with us and b = 1, a peer in bucket `i` is
+    /// at a distance whose leading bit is past `c` for `i == c`, exactly `c` for `i > c`, and
+    /// exactly `i` for `i < c`. So the target's bucket comes first, then every deeper bucket
+    /// together, then the shallower ones one by one. With larger b the same holds level by
+    /// level: the target's bucket, then the rest of its level and everything deeper, then
+    /// each shallower level.
+    pub fn iter_closest(&self, target: &NodeId<N>) -> ClosestContacts<'_, N> {
+        let per_level = self.buckets_per_level();
+        let last = self.buckets.len() - 1;
+        let home = self.bucket_for(target).unwrap_or(last);
+        let level = (self.own_id.common_prefix_len(target) / self.bits_per_bucket).min(self.levels());
+        let rest: Vec<usize> = (level * per_level..=last).filter(|&i| i != home).collect();
+        let groups: Vec<Vec<usize>> = [vec![home], rest]
+            .into_iter()
+            .chain((0..level).rev().map(|l| (l * per_level..(l + 1) * per_level).collect()))
+            .collect();
+        ClosestContacts {
+            table: self,
+            target: *target,
+            groups: groups.into_iter(),
+            heap: BinaryHeap::new(),
+        }
+    }
+}
+
+/// Iterator behind `RoutingTable::iter_closest`
+pub struct ClosestContacts<'a, const N: usize = ID_BYTES> {
+    table: &'a RoutingTable<N>,
+    target: NodeId<N>,
+    groups: std::vec::IntoIter<Vec<usize>>, // bucket indices still to visit, nearest group first
+    heap: BinaryHeap<Reverse<(Distance<N>, usize, usize)>>, // (distance, bucket, position) of the current group
+}
+
+impl<'a, const N: usize> Iterator for ClosestContacts<'a, N> {
+    type Item = &'a Contact<N>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(Reverse((_, bucket, pos))) = self.heap.pop() {
+                return Some(&self.table.buckets[bucket].peers[pos]);
+            }
+            for bucket in self.groups.next()? {
+                for (pos, contact) in self.table.buckets[bucket].peers.iter().enumerate() {
+                    self.heap.push(Reverse((self.target.xor_distance(&contact.id), bucket, pos)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod routing_table_tests {
+    use super::*;
+
+    /// A one-byte-ID table for `own`, shaped by `config`
+    fn table(own: u8, config: &NetworkConfig) -> RoutingTable<1> {
+        RoutingTable::new(NodeId::from_bytes([own]), Instant::now(), config)
+    }
+
+    fn ids(table: &RoutingTable<1>) -> Vec<u8> {
+        table.peers().map(|id| id.0[0]).collect()
+    }
+
+    #[test]
+    fn newcomers_wait_in_the_replacement_cache_and_replace_dead_contacts() {
+        let config = NetworkConfig::builder().k(2).bucket_count(1).build();
+        let mut table = table(0x00, &config);
+        let now = Instant::now();
+        assert_eq!(table.insert(&NodeId::from_bytes([0x80]), 0, now), TrackOutcome::Tracked);
+        assert_eq!(table.insert(&NodeId::from_bytes([0x40]), 0, now), TrackOutcome::Tracked);
+        // a full bucket parks newcomers and names its least recently seen contact for a ping
+        let ping = TrackOutcome::BucketFull { evict_candidate: NodeId::from_bytes([0x80]) };
+        assert_eq!(table.insert(&NodeId::from_bytes([0xc0]), 0, now), ping);
+        assert_eq!(table.insert(&NodeId::from_bytes([0xa0]), 0, now), ping);
+        assert_eq!((ids(&table), table.replacement_len(0)), (vec![0x80, 0x40], 2));
+
+        // the old contacts die one by one; the freshest newcomer takes each freed slot
+        for _ in 0..config.stale_after {
+            table.record_failure(&NodeId::from_bytes([0x80]));
+        }
+        assert_eq!((ids(&table), table.replacement_len(0)), (vec![0x40, 0xa0], 1));
+        for _ in 0..config.stale_after {
+            table.record_failure(&NodeId::from_bytes([0x40]));
+        }
+        assert_eq!((ids(&table), table.replacement_len(0)), (vec![0xa0, 0xc0], 0));
+        // with nobody waiting, a stale contact stays until someone can replace it
+        for _ in 0..config.stale_after {
+            table.record_failure(&NodeId::from_bytes([0xa0]));
+        }
+        assert_eq!(ids(&table), vec![0xa0, 0xc0]);
+    }
+
+    #[test]
+    fn the_replacement_cache_is_bounded_by_the_bucket_size() {
+        let config = NetworkConfig::builder().k(2).bucket_count(1).build();
+        let mut table = table(0x00, &config);
+        for peer in 1..=20u8 {
+            table.insert(&NodeId::from_bytes([peer << 2]), 0, Instant::now());
+        }
+        assert_eq!((table.len(), table.replacement_len(0)), (2, 2));
+        assert!(table.is_well_formed());
+    }
+
+    #[test]
+    fn far_insertions_split_the_table_once() {
+        let config = NetworkConfig::builder().k(2).build();
+        let mut table = table(0x00, &config);
+        assert_eq!(table.buckets.len(), 1);
+        for peer in [0x80, 0x90] {
+            table.insert(&NodeId::from_bytes([peer]), 0, Instant::now());
+        }
+        assert_eq!(table.buckets.len(), 1, "a bucket only splits once it overflows");
+        for peer in 0xa0..=0xffu8 {
+            table.insert(&NodeId::from_bytes([peer]), 0, Instant::now());
+        }
+        // the first split moves the far half into bucket 0, which never splits again
+        assert_eq!(table.buckets.len(), 2);
+        assert_eq!(table.buckets[0].len(), 2);
+        assert!(table.buckets[1].peers.is_empty());
+        assert!(table.is_well_formed());
+    }
+
+    #[test]
+    fn insertions_near_our_own_id_keep_splitting() {
+        let config = NetworkConfig::builder().k(2).build();
+        let mut table = table(0x00, &config);
+        for peer in [0x80, 0x40, 0x20, 0x10, 0x08, 0x04] {
+            table.insert(&NodeId::from_bytes([peer]), 0, Instant::now());
+            assert!(table.is_well_formed(), "contacts misplaced after inserting {peer:#04x}");
+        }
+        // each peer shares one more bit with us, so every overflow splits the last bucket
+        assert_eq!(table.buckets.len(), 5);
+        let per_bucket: Vec<usize> = table.buckets.iter().map(KBucket::len).collect();
+        assert_eq!(per_bucket, [1, 1, 1, 1, 2]);
+        assert_eq!(table.bucket_for(&NodeId::from_bytes([0x80])), Some(0));
+        assert_eq!(table.bucket_for(&NodeId::from_bytes([0x04])), Some(4));
+        assert_eq!(table.len(), 6);
+    }
+
+    #[test]
+    fn splitting_stops_at_the_bucket_count() {
+        let config = NetworkConfig::builder().k(1).bucket_count(3).build();
+        let mut table = table(0x00, &config);
+        for peer in [0x80, 0x40, 0x20, 0x10, 0x08] {
+            table.insert(&NodeId::from_bytes([peer]), 0, Instant::now());
+        }
+        assert_eq!(table.buckets.len(), 3);
+        assert_eq!(table.len(), 3);
+        assert!(table.is_well_formed());
+    }
+
+    #[test]
+    fn only_buckets_that_have_held_a_contact_come_up_for_refresh() {
+        let config = NetworkConfig::builder().k(1).build();
+        let start = Instant::now();
+        let mut table = table(0x00, &config);
+        for peer in [0x80, 0x01, 0x02] {
+            table.insert(&NodeId::from_bytes([peer]), 0, start);
+        }
+        assert!(table.buckets.len() > 2, "making room for 0x02 beside 0x01 splits off empty buckets on the way");
+        let later = Instant::now() + REFRESH_INTERVAL;
+        let filled: Vec<usize> = (0..table.buckets.len()).filter(|&i| !table.buckets[i].peers.is_empty()).collect();
+        assert_eq!(table.buckets_needing_refresh(later, REFRESH_INTERVAL), filled);
+        // a bucket emptied by failures is still refreshed, to find someone to fill it again
+        for _ in 0..config.stale_after {
+            table.record_failure(&NodeId::from_bytes([0x80]));
+        }
+        assert_eq!(table.buckets_needing_refresh(later, REFRESH_INTERVAL), filled);
+    }
+
+    #[test]
+    fn closest_k_matches_a_brute_force_sort_of_the_usable_contacts() {
+        let mut rng = StdRng::seed_from_u64(28);
+        for bits_per_bucket in [1, 4] {
+            let config = NetworkConfig::builder().bits_per_bucket(bits_per_bucket).build();
+            for _ in 0..20 {
+                let own: NodeId = NodeId::random_with(&mut rng);
+                let now = Instant::now();
+                let mut table = RoutingTable::new(own, now, &config);
+                for _ in 0..rng.gen_range(0..300) {
+                    table.insert(&NodeId::random_with(&mut rng), 0, now);
+                }
+                // some contacts went quiet and some were never heard from
+                let known: Vec<NodeId> = table.peers().copied().collect();
+                for id in &known {
+                    match rng.gen_range(0..6) {
+                        0 => {
+                            for _ in 0..config.stale_after {
+                                table.record_failure(id);
+                            }
+                        }
+                        1 => {
+                            let index = table.bucket_for(id).unwrap();
+                            let pos = table.buckets[index].position(id).unwrap();
+                            table.buckets[index].peers[pos].verified = false;
+                        }
+                        _ => {}
+                    }
+                }
+                for _ in 0..10 {
+                    let target: NodeId = NodeId::random_with(&mut rng);
+                    let count = rng.gen_range(0..2 * config.k);
+                    let mut brute: Vec<NodeId> = table
+                        .contacts()
+                        .filter(|c| c.verified && !c.is_stale(config.stale_after))
+                        .map(|c| c.id)
+                        .collect();
+                    brute.sort_by_key(|id| id.xor_distance(&target));
+                    brute.truncate(count);
+                    assert_eq!(table.closest_k(&target, count), brute);
+                }
+            }
+        }
+    }
+}
+
+/// Where a node reads the time from: contacts' last-seen times, bucket refreshes and
+/// storage expiry all come from its clock
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Current instant
+    fn now(&self) -> Instant;
+}
+
+/// Simulated time shared by a network and its nodes. It starts when created and only moves
+/// when the simulation advances it, so TTLs can be exercised without real sleeps.
+#[derive(Clone, Debug)]
+pub struct SimClock {
+    start: Instant,
+    elapsed_nanos: Arc<AtomicU64>,
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed_nanos: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Move simulated time forward for everyone sharing this clock
+    pub fn advance(&self, by: Duration) {
+        self.elapsed_nanos.fetch_add(by.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for SimClock {
+    /// Current simulated instant
+    fn now(&self) -> Instant {
+        self.start + Duration::from_nanos(self.elapsed_nanos.load(Ordering::SeqCst))
+    }
+}
+
+/// Wall-clock time, for a node running outside a simulation
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A stored value, the simulated instant after which it is no longer served, and when it
+/// was last stored or read (for LRU eviction)
+#[derive(Clone, Debug)]
+pub struct StoredValue {
+    value: Vec<u8>,
+    expires_at: Instant,
+    last_access: u64, // the node's access counter at the time; orders accesses within one tick
+}
+
+/// How a node's known peers spread over its buckets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DensityReport<const N: usize = ID_BYTES> {
+    pub peers_per_bucket: Vec<usize>, // index = shared prefix length with the node's ID, 0..=BITS
+    pub closest: Option<Distance<N>>, // distance to the nearest known peer
+}
+
+/// Density reports of every node combined
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkDensityReport<const N: usize = ID_BYTES> {
+    pub nodes: usize,
+    pub peers_per_bucket: Vec<usize>, // summed over all nodes
+    pub closest: Option<Distance<N>>, // smallest nearest-peer distance of any node
+    pub sparsest: Option<Distance<N>>, // largest nearest-peer distance: the loneliest node
+}
+
+/// The shape and health of one node's routing table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingStats<const N: usize = ID_BYTES> {
+    pub total_contacts: usize,
+    pub peers_per_bucket: Vec<usize>,  // occupancy, index = bucket
+    pub stale_contacts: usize,         // contacts past `stale_after` failures
+    pub deepest_bucket: Option<usize>, // highest non-empty bucket index
+    pub closest: Option<Distance<N>>,  // distance to the nearest known peer
+    pub origin_rejections: u64,        // newcomers kept out of a bucket by the per-origin cap
+}
+
+impl<const N: usize> RoutingStats<N> {
+    /// Whether the bucket covering the node's own neighborhood (its last) holds anyone
+    pub fn has_neighbors(&self) -> bool {
+        self.peers_per_bucket.last().is_some_and(|&count| count > 0)
+    }
+}
+
+/// Routing stats of every node combined
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkRoutingStats {
+    pub nodes: usize,
+    pub min_contacts: usize,
+    pub max_contacts: usize,
+    pub mean_contacts: f64,
+    pub stale_contacts: usize,     // summed over all nodes
+    pub without_neighbors: usize, // nodes whose own-neighborhood bucket is empty
+}
+
+/// Who knows whom across a network, without the per-contact detail: every node, and an edge
+/// from each node to each peer in its routing table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopologySnapshot<const N: usize = ID_BYTES> {
+    node_ids: Vec<NodeId<N>>,           // sorted
+    edges: Vec<(NodeId<N>, NodeId<N>)>, // (node, peer it knows)
+}
+
+impl<const N: usize> TopologySnapshot<N> {
+    /// The whole snapshot as a Graphviz digraph, nodes labeled by their first 8 hex digits
+    pub fn to_dot(&self) -> String {
+        Self::dot(&self.node_ids, &self.edges)
+    }
+
+    /// At most `max_edges` edges, for networks too large to draw whole: nodes are taken in
+    /// order of degree (edges in and out), best connected first, and each brings its edges to
+    /// the nodes already taken, until the next one would go over the budget
+    pub fn to_dot_sampled(&self, max_edges: usize) -> String {
+        let mut degree: HashMap<NodeId<N>, usize> = self.node_ids.iter().map(|id| (*id, 0)).collect();
+        for (from, to) in &self.edges {
+            *degree.entry(*from).or_default() += 1;
+            *degree.entry(*to).or_default() += 1;
+        }
+        let mut ranked = self.node_ids.clone();
+        ranked.sort_by_key(|id| Reverse(degree[id]));
+        let mut taken: HashSet<NodeId<N>> = HashSet::new();
+        let mut edges = Vec::new();
+        for id in ranked {
+            let joining: Vec<(NodeId<N>, NodeId<N>)> = self
+                .edges
+                .iter()
+                .filter(|(from, to)| (*from == id && taken.contains(to)) || (*to == id && taken.contains(from)))
+                .copied()
+                .collect();
+            if edges.len() + joining.len() > max_edges {
+                break;
+            }
+            taken.insert(id);
+            edges.extend(joining);
+        }
+        let mut nodes: Vec<NodeId<N>> = taken.into_iter().collect();
+        nodes.sort();
+        Self::dot(&nodes, &edges)
+    }
+
+    fn dot(nodes: &[NodeId<N>], edges: &[(NodeId<N>, NodeId<N>)]) -> String {
+        let mut lines = vec!["digraph topology {".to_string()];
+        for id in nodes {
+            let hex = id.to_string();
+            lines.push(format!("    \"{id}\" [label=\"{}\"];", &hex[..hex.len().min(8)]));
+        }
+        for (from, to) in edges {
+            lines.push(format!("    \"{from}\" -> \"{to}\";"));
+        }
+        lines.push("}".to_string());
+        lines.join("\n")
+    }
+}
+
+/// RPCs a network has forwarded since it was created or its stats were last reset
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NetworkStats {
+    pub total_pings: u64,
+    pub eviction_pings: u64,     // pings a full bucket sent its least recently seen contact, counted apart
+    pub total_find_node_rpcs: u64,
+    pub total_find_value_rpcs: u64,
+    pub total_store_rpcs: u64,
+    pub total_value_hits: u64,   // find_value RPCs answered with the value
+    pub total_value_misses: u64, // find_value RPCs answered without it
+}
+
+/// What one iterative lookup cost
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueryStats {
+    pub hops: usize,          // rounds of up to alpha parallel queries
+    pub nodes_queried: usize, // distinct nodes asked, including any that failed to answer
+}
+
+/// One query of a traced lookup
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueryStep<const N: usize = ID_BYTES> {
+    pub queried: NodeId<N>,
+    pub returned: Vec<NodeId<N>>,    // empty if the node did not answer
+    pub shortlist_size_after: usize, // after merging `returned` (or dropping an unreachable node)
+    pub step: usize,                 // lookup round, from 0; a round queries up to alpha nodes
+    pub sent_at: Instant,            // when the round's queries went out
+    pub answered_at: Instant,        // when this one came back (or timed out)
+}
+
+/// Every query a lookup made, in order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueryTrace<const N: usize = ID_BYTES> {
+    pub steps: Vec<QueryStep<N>>,
+}
+
+/// A node's long-term ed25519 keypair. The node's ID is derived from the public half,
+/// so an identity-backed ID cannot be chosen freely.
+#[derive(Clone, Debug)]
+pub struct NodeIdentity {
+    signing_key: SigningKey,
+}
+
+impl NodeIdentity {
+    /// Generate a fresh keypair
+    pub fn generate(rng: &mut (impl RngCore + CryptoRng)) -> Self {
+        Self { signing_key: SigningKey::generate(rng) }
+    }
+
+    /// The public key peers use to check our ID
+    pub fn public_key(&self) -> VerifyingKey {
+        self.signing_key.verifying_key()
+    }
+
+    /// The ID this identity owns
+    pub fn node_id<const N: usize>(&self) -> NodeId<N> {
+        NodeId::from_public_key(self.public_key().as_bytes())
+    }
+
+    /// Sign version `sequence_number` of the mutable value this identity owns
+    pub fn sign_value(&self, sequence_number: u64, value: Vec<u8>) -> MutableValue {
+        MutableValue::sign(&self.signing_key, sequence_number, value)
+    }
+}
+
+/// A value only the holder of a keypair can write. It is stored under the SHA-1 of the
+/// public key, signed over its sequence number and bytes, and replaced only by a version
+/// with a higher sequence number.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MutableValue {
+    public_key: [u8; 32],
+    sequence_number: u64,
+    value: Vec<u8>,
+    signature: [u8; 64],
+}
+
+impl MutableValue {
+    /// Bytes covered by the signature: the big-endian sequence number, then the value
+    fn signed_bytes(sequence_number: u64, value: &[u8]) -> Vec<u8> {
+        [&sequence_number.to_be_bytes()[..], value].concat()
+    }
+
+    /// Version `sequence_number` of `value`, signed with `signing_key`
+    pub fn sign(signing_key: &SigningKey, sequence_number: u64, value: Vec<u8>) -> Self {
+        let signature = signing_key.sign(&Self::signed_bytes(sequence_number, &value));
+        Self {
+            public_key: signing_key.verifying_key().to_bytes(),
+            sequence_number,
+            value,
+            signature: signature.to_bytes(),
+        }
+    }
+
+    /// Whether the signature was made by `public_key` over this sequence number and value
+    pub fn verify(&self) -> bool {
+        let Ok(key) = VerifyingKey::from_bytes(&self.public_key) else {
+            return false;
+        };
+        let signed = Self::signed_bytes(self.sequence_number, &self.value);
+        key.verify(&signed, &Signature::from_bytes(&self.signature)).is_ok()
+    }
+
+    /// The key ID it is stored under
+    pub fn key_id<const N: usize>(&self) -> NodeId<N> {
+        NodeId::from_public_key(&self.public_key)
+    }
+
+    /// Stored form: public key, big-endian sequence number, signature, then the value
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&self.public_key[..], &self.sequence_number.to_be_bytes(), &self.signature, &self.value].concat()
+    }
+
+    /// Parse the stored form back (the signature is not checked here)
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 104 {
+            return None;
+        }
+        let (public_key, rest) = bytes.split_at(32);
+        let (sequence_number, rest) = rest.split_at(8);
+        let (signature, value) = rest.split_at(64);
+        Some(Self {
+            public_key: public_key.try_into().ok()?,
+            sequence_number: u64::from_be_bytes(sequence_number.try_into().ok()?),
+            value: value.to_vec(),
+            signature: signature.try_into().ok()?,
+        })
+    }
+
+    /// Parse `bytes` stored under `key`, if they are a validly signed value for that key
+    fn stored_under<const N: usize>(key: &NodeId<N>, bytes: &[u8]) -> Option<Self> {
+        Self::from_bytes(bytes).filter(|mv| mv.key_id::<N>() == *key && mv.verify())
+    }
+}
+
+/// What `Node::track_peer` did with a peer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackOutcome<const N: usize = ID_BYTES> {
+    /// The peer is in its bucket now (added or refreshed), or was our own ID
+    Tracked,
+    /// Its bucket is full: the peer waits in the replacement cache, and takes the place of
+    /// `evict_candidate` (the bucket's least-recently-seen peer) if that fails a ping
+    BucketFull { evict_candidate: NodeId<N> },
+    /// It presented a public key that does not own its ID, or its ID lacks the proof of work
+    /// the node requires; nothing was recorded
+    Rejected,
+    /// The table is at its `max_contacts` cap with no replacement candidate left to drop;
+    /// nothing was recorded
+    Refused,
+}
+
+/// A DOT node statement for `id`, named by its full hex and labeled with its short form
+fn dot_node<const N: usize>(id: &NodeId<N>, attrs: &str) -> String {
+    let sep = if attrs.is_empty() { "" } else { ", " };
+    format!("    \"{id}\" [label=\"{}\"{sep}{attrs}];", id.short())
+}
+
+/// A basic node in the DHT
+#[derive(Debug)]
+pub struct Node<const N: usize = ID_BYTES> {
+    id: NodeId<N>,
+    storage: HashMap<NodeId<N>, StoredValue>, // values by key ID, with expiry
+    routing_table: RoutingTable<N>,         // k-buckets split as a tree around our ID, b prefix bits per level
+    alive: bool,                            // liveness flag
+    clock: Arc<dyn Clock>,                  // shared with the owning network, if any
+    hasher: Arc<dyn KeyHasher<N>>,          // maps byte keys to IDs, as the owning network does
+    identity: Option<NodeIdentity>,         // keypair the ID is derived from, if any
+    storage_limit_bytes: Option<usize>,     // cap on `storage_bytes`, if any
+    find_node: FindNodeOptions,             // how find_node answers are shaped
+    siblings: SiblingList<N>,               // the closest peers to our own ID
+    origin: u32,                            // where this node connects from, as peers see it
+    pow_difficulty: Option<u8>,             // proof of work demanded of peers' IDs, if any
+    pow_nonce: Option<u64>,                 // proof of work for our own ID, if mined
+    accesses: u64,                          // storage reads and writes so far
+    forged_find_node: Option<Vec<NodeId<N>>>, // sent as every find_node answer, if misbehaving
+}
+
+/// The `capacity` peers closest to a node's own ID that it has heard from, kept apart from
+/// the buckets (S/Kademlia's sibling list) for deciding which keys the node is responsible for
+#[derive(Debug, Clone)]
+pub struct SiblingList<const N: usize = ID_BYTES> {
+    own_id: NodeId<N>,
+    capacity: usize,
+    ids: Vec<NodeId<N>>, // closest to `own_id` first
+}
+
+impl<const N: usize> SiblingList<N> {
+    pub fn new(own_id: NodeId<N>, capacity: usize) -> Self {
+        Self {
+            own_id,
+            capacity,
+            ids: Vec::with_capacity(capacity + 1),
+        }
+    }
+
+    /// Consider `peer` for the list; returns whether it is a sibling afterwards
+    fn insert(&mut self, peer: &NodeId<N>) -> bool {
+        if *peer == self.own_id {
+            return false;
+        }
+        let distance = self.own_id.xor_distance(peer);
+        match self.ids.binary_search_by_key(&distance, |id| self.own_id.xor_distance(id)) {
+            Ok(_) => true,
+            Err(pos) if pos < self.capacity => {
+                self.ids.insert(pos, *peer);
+                self.ids.truncate(self.capacity);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Drop `peer`, e.g. after it left; returns whether it was a sibling
+    fn remove(&mut self, peer: &NodeId<N>) -> bool {
+        let before = self.ids.len();
+        self.ids.retain(|id| id != peer);
+        self.ids.len() < before
+    }
+
+    pub fn as_slice(&self) -> &[NodeId<N>] {
+        &self.ids
+    }
+}
+
+/// Builder for a `Node` whose capacity or table shape differs from the rest of its network.
+/// Unset parameters come from the network's config (or the defaults, for a standalone node).
+#[derive(Clone, Debug, Default)]
+pub struct NodeBuilder<const N: usize = ID_BYTES> {
+    id: Option<NodeId<N>>,
+    storage_limit: Option<usize>,
+    bucket_count: Option<usize>,
+    k: Option<usize>,
+    origin: Option<u32>,
+    pow_nonce: Option<u64>,
+}
+
+impl<const N: usize> NodeBuilder<N> {
+    pub fn with_id(mut self, id: NodeId<N>) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    pub fn with_storage_limit(mut self, bytes: usize) -> Self {
+        self.storage_limit = Some(bytes);
+        self
+    }
+
+    pub fn with_bucket_count(mut self, bucket_count: usize) -> Self {
+        self.bucket_count = Some(bucket_count);
+        self
+    }
+
+    pub fn with_k(mut self, k: usize) -> Self {
+        self.k = Some(k);
+        self
+    }
+
+    /// Tag the node with the origin its peers see it connecting from
+    pub fn with_origin(mut self, origin: u32) -> Self {
+        self.origin = Some(origin);
+        self
+    }
+
+    /// Give the node the nonce proving work on its ID (see `NodeId::generate_with_pow`)
+    pub fn with_pow_nonce(mut self, nonce: u64) -> Self {
+        self.pow_nonce = Some(nonce);
+        self
+    }
+
+    /// `base` with this builder's overrides applied (panics if k or bucket_count is zero)
+    fn config_for(&self, base: &NetworkConfig) -> NetworkConfig {
+        let config = NetworkConfig {
+            k: self.k.unwrap_or(base.k),
+            bucket_count: self.bucket_count.unwrap_or(base.bucket_count),
+            storage_limit_bytes: self.storage_limit.or(base.storage_limit_bytes),
+            ..*base
+        };
+        assert!(config.k > 0 && config.bucket_count > 0, "invalid {self:?}");
+        config
+    }
+
+    /// A standalone node with its own clock, and a random ID unless one was chosen
+    pub fn build(self) -> Node<N> {
+        let config = self.config_for(&NetworkConfig::default());
+        let mut node = Node::with_id(self.id.unwrap_or_else(NodeId::random), SimClock::new(), &config);
+        node.origin = self.origin.unwrap_or_default();
+        node
+    }
+}
+
+impl<const N: usize> Default for Node<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Node<N> {
+    /// Create a new node with a random ID, default parameters and its own clock
+    pub fn new() -> Self {
+        NodeBuilder::default().build()
+    }
+
+    /// Create a new node with a random ID and default parameters that reads time from `clock`
+    /// (e.g. `SystemClock` for a node running in real time)
+    pub fn with_clock(clock: impl Clock + 'static) -> Self {
+        Self::with_id(NodeId::random(), clock, &NetworkConfig::default())
+    }
+
+    /// Create a new node with a random ID and its own clock that stores at most `bytes`
+    pub fn with_storage_limit(bytes: usize) -> Self {
+        NodeBuilder::default().with_storage_limit(bytes).build()
+    }
+
+    /// Create a new node with a chosen ID that reads time from `clock`
+    pub fn with_id(id: NodeId<N>, clock: impl Clock + 'static, config: &NetworkConfig) -> Self {
+        Self {
+            id,
+            storage: HashMap::new(),
+            routing_table: RoutingTable::new(id, clock.now(), config),
+            alive: true,
+            clock: Arc::new(clock),
+            hasher: Arc::new(config.hash_function),
+            identity: None,
+            storage_limit_bytes: config.storage_limit_bytes,
+            find_node: config.find_node,
+            siblings: SiblingList::new(id, config.sibling_count()),
+            origin: 0,
+            pow_difficulty: config.required_pow(),
+            pow_nonce: None,
+            accesses: 0,
+            forged_find_node: None,
+        }
+    }
+
+    /// Create a node whose ID is derived from `identity`'s public key
+    pub fn with_identity(identity: NodeIdentity, clock: impl Clock + 'static, config: &NetworkConfig) -> Self {
+        let mut node = Self::with_id(identity.node_id(), clock, config);
+        node.identity = Some(identity);
+        node
+    }
+
+    /// Public key backing this node's ID, if it has an identity
+    pub fn public_key(&self) -> Option<VerifyingKey> {
+        self.identity.as_ref().map(NodeIdentity::public_key)
+    }
+
+    /// Stored entries that have not expired yet
+    fn live_entries(&self) -> impl Iterator<Item = (&NodeId<N>, &StoredValue)> {
+        let now = self.clock.now();
+        self.storage.iter().filter(move |(_, stored)| stored.expires_at > now)
+    }
+
+    /// Key IDs of the live entries this node stores
+    pub fn storage_keys(&self) -> impl Iterator<Item = &NodeId<N>> {
+        self.live_entries().map(|(key, _)| key)
+    }
+
+    /// Live entries as (key ID, value) pairs
+    pub fn storage_values(&self) -> impl Iterator<Item = (&NodeId<N>, &Vec<u8>)> {
+        self.live_entries().map(|(key, stored)| (key, &stored.value))
+    }
+
+    /// Number of live entries
+    pub fn storage_size(&self) -> usize {
+        self.live_entries().count()
+    }
+
+    /// Bytes held by live entries: each key ID plus its value
+    pub fn storage_bytes(&self) -> usize {
+        self.live_entries().map(|(_, stored)| N + stored.value.len()).sum()
+    }
+
+    /// Count known peers by the prefix length they share with us, however the routing table
+    /// happens to be split, and find the nearest one
+    pub fn density_report(&self) -> DensityReport<N> {
+        let mut peers_per_bucket = vec![0; NodeId::<N>::BITS + 1];
+        for peer in self.routing_table.peers() {
+            peers_per_bucket[self.id.common_prefix_len(peer)] += 1;
+        }
+        DensityReport {
+            peers_per_bucket,
+            closest: self.routing_table.peers().map(|p| self.id.xor_distance(p)).min(),
+        }
+    }
+
+    /// Occupancy, staleness and reach of this node's routing table
+    pub fn routing_stats(&self) -> RoutingStats<N> {
+        let table = &self.routing_table;
+        let peers_per_bucket: Vec<usize> = table.buckets.iter().map(KBucket::len).collect();
+        RoutingStats {
+            total_contacts: table.len(),
+            stale_contacts: table.contacts().filter(|c| c.is_stale(table.stale_after)).count(),
+            deepest_bucket: peers_per_bucket.iter().rposition(|&count| count > 0),
+            peers_per_bucket,
+            closest: table.peers().map(|p| self.id.xor_distance(p)).min(),
+            origin_rejections: table.origin_rejections,
+        }
+    }
+
+    /// This node's routing table as a Graphviz digraph: the node itself, and an edge to each
+    /// contact labeled with its bucket index. Stale contacts are dashed.
+    pub fn routing_to_dot(&self) -> String {
+        let mut lines = vec!["digraph routing {".to_string(), dot_node(&self.id, "shape=doublecircle")];
+        for contact in self.routing_table.contacts() {
+            let style = if contact.is_stale(self.routing_table.stale_after) { "style=dashed" } else { "" };
+            lines.push(dot_node(&contact.id, style));
+        }
+        lines.extend(self.dot_edges());
+        lines.push("}".to_string());
+        lines.join("\n")
+    }
+
+    /// One DOT edge per contact, from this node, labeled with the contact's bucket index
+    pub fn dot_edges(&self) -> Vec<String> {
+        self.routing_table
+            .contacts_by_bucket()
+            .map(|(bucket, contact)| {
+                let stale = if contact.is_stale(self.routing_table.stale_after) { ", style=dashed" } else { "" };
+                format!("    \"{}\" -> \"{}\" [label=\"{bucket}\"{stale}];", self.id, contact.id)
+            })
+            .collect()
+    }
+
+    /// Save the peers in this node's routing table
+    pub fn export_routing(&self) -> RoutingSnapshot<N> {
+        RoutingSnapshot {
+            own_id: self.id,
+            contacts: self.routing_table.peers().copied().collect(),
+        }
+    }
+
+    /// Take in contacts copied from another node's table under the usual bucket rules, as
+    /// second-hand knowledge: they join unverified, or wait in the replacement cache behind
+    /// first-hand candidates
+    pub fn merge_contacts(&mut self, contacts: &[Contact<N>]) -> MergeSummary {
+        let mut summary = MergeSummary::default();
+        for contact in contacts {
+            match self.routing_table.merge_contact(contact) {
+                MergeOutcome::Inserted => summary.inserted += 1,
+                MergeOutcome::Cached => summary.cached += 1,
+                MergeOutcome::Rejected => summary.rejected += 1,
+            }
+        }
+        summary
+    }
+
+    /// Restore saved peers as unverified contacts: they seed this node's lookups but are not
+    /// handed to others until they answer an RPC. Returns how many were added.
+    pub fn import_routing(&mut self, snapshot: RoutingSnapshot<N>) -> usize {
+        snapshot
+            .contacts
+            .iter()
+            .filter(|peer| self.routing_table.insert_unverified(peer))
+            .count()
+    }
+
+    /// Export live storage entries as portable records
+    pub fn records(&self) -> Vec<StoredRecord<N>> {
+        let now = self.clock.now();
+        self.live_entries()
+            .map(|(key, stored)| StoredRecord {
+                key: *key,
+                value: stored.value.clone(),
+                ttl_remaining: stored.expires_at - now,
+            })
+            .collect()
+    }
+
+    /// Load records exported by `records`, restarting their TTLs from the current time
+    pub fn load_records(&mut self, records: Vec<StoredRecord<N>>) {
+        let now = self.clock.now();
+        for record in records {
+            let stored = StoredValue {
+                value: record.value,
+                expires_at: now + record.ttl_remaining,
+                last_access: self.next_access(),
+            };
+            self.storage.insert(record.key, stored);
+        }
+    }
+
+    /// Drop every entry whose TTL has run out
+    pub fn expire_entries(&mut self) {
+        let now = self.clock.now();
+        self.storage.retain(|_, stored| stored.expires_at > now);
+    }
+
+    /// Update the routing table with a peer we heard from (LRU per bucket, max K, no self);
+    /// peers that find their bucket full wait in its replacement cache, and the caller is told
+    /// which peer to ping before evicting. A peer presenting a public key must own its ID, and
+    /// must present a `pow_nonce` proving the work this node requires on its ID, if any.
+    /// `origin` is where the peer connected from, for the per-origin bucket cap.
+    pub fn track_peer(
+        &mut self,
+        peer: &NodeId<N>,
+        public_key: Option<&VerifyingKey>,
+        origin: u32,
+        pow_nonce: Option<u64>,
+    ) -> TrackOutcome<N> {
+        if public_key.is_some_and(|key| NodeId::from_public_key(key.as_bytes()) != *peer) {
+            return TrackOutcome::Rejected;
+        }
+        if !self.accepts_pow(peer, pow_nonce) {
+            // drop any hearsay entry for it too, which could not be checked when recorded
+            self.remove_peer(peer);
+            return TrackOutcome::Rejected;
+        }
+        let outcome = self.routing_table.insert(peer, origin, self.clock.now());
+        if outcome != TrackOutcome::Refused {
+            self.siblings.insert(peer);
+        }
+        outcome
+    }
+
+    /// Note a peer we only heard of second-hand, e.g. in another node's find_node answer: it
+    /// joins its bucket unverified if there is room, and is never handed out by find_node
+    /// until it answers one of our RPCs (`track_peer` then verifies it). A full bucket is
+    /// left alone, so hearsay never evicts a peer that has answered. Hearsay carries no
+    /// proof-of-work nonce, so that is checked once the peer answers. Returns whether it was
+    /// added.
+    pub fn record_contact(&mut self, peer: &NodeId<N>) -> bool {
+        *peer != self.id && self.routing_table.insert_unverified(peer)
+    }
+
+    /// Whether `nonce` proves the work this node requires of `peer`'s ID (always, if none)
+    fn accepts_pow(&self, peer: &NodeId<N>, nonce: Option<u64>) -> bool {
+        self.pow_difficulty.is_none_or(|difficulty| nonce.is_some_and(|nonce| peer.verify_pow(nonce, difficulty)))
+    }
+
+    /// The closest peers to this node's ID that it has heard from, closest first
+    pub fn siblings(&self) -> &[NodeId<N>] {
+        self.siblings.as_slice()
+    }
+
+    /// Whether this node is among the k closest nodes to `key_id` that it knows of, counting
+    /// its siblings and the closest peers in its routing table
+    pub fn is_responsible_for(&self, key_id: &NodeId<N>) -> bool {
+        let k = self.routing_table.k;
+        let own = self.id.xor_distance(key_id);
+        let mut known: HashSet<NodeId<N>> = self.routing_table.closest_k(key_id, k).into_iter().collect();
+        known.extend(self.siblings());
+        known.iter().filter(|peer| peer.xor_distance(key_id) < own).count() < k
+    }
+
+    /// Indices of this node's buckets that have gone at least `interval` without a lookup
+    pub fn buckets_needing_refresh(&self, now: Instant, interval: Duration) -> Vec<usize> {
+        self.routing_table.buckets_needing_refresh(now, interval)
+    }
+
+    /// Peers in any bucket that this node has not heard from within `threshold`
+    pub fn stale_peers(&self, threshold: Duration) -> Vec<NodeId<N>> {
+        let now = self.clock.now();
+        self.routing_table.buckets.iter().flat_map(|b| b.stale_peers(now, threshold)).collect()
+    }
+
+    /// Estimate the network size from this node's table. With n nodes spread uniformly, the
+    /// i-th closest peer sits about i/n of the keyspace away, so fit n to the distances of
+    /// the k closest known peers (least squares on d_i = i/n).
+    pub fn estimate_size(&self) -> f64 {
+        let closest = self.routing_table.closest_k(&self.id, self.routing_table.k);
+        if closest.is_empty() {
+            return 1.0;
+        }
+        let (sum_sq, sum_id) = closest.iter().enumerate().fold((0.0, 0.0), |(sq, id), (i, peer)| {
+            let rank = (i + 1) as f64;
+            (sq + rank * rank, id + rank * self.id.xor_distance(peer).to_f64())
+        });
+        sum_sq / sum_id
+    }
+
+    /// What looks wrong with this table, judged by what the node itself knows: buckets left
+    /// empty although their share of the keyspace should hold at least one node of a network
+    /// the size this table suggests, contacts past the configured `stale_after` failures or
+    /// silent for a whole REFRESH_INTERVAL, and buckets overdue for refresh. A peer that
+    /// crashed is dropped as soon as an RPC finds it gone, so the contacts it leaves behind
+    /// are the ones nobody has asked since; only silence gives those away.
+    pub fn routing_health(&self) -> HealthReport<N> {
+        let table = &self.routing_table;
+        let now = self.clock.now();
+        let network_size = self.estimate_size();
+        let empty_buckets = (0..table.buckets.len())
+            .filter(|&i| table.buckets[i].peers.is_empty() && network_size * table.keyspace_share(i) >= 1.0)
+            .collect();
+        let silent = |c: &Contact<N>| c.last_seen.is_none_or(|seen| now.saturating_duration_since(seen) >= REFRESH_INTERVAL);
+        HealthReport {
+            node: self.id,
+            empty_buckets,
+            stale_contacts: table.contacts().filter(|c| c.is_stale(table.stale_after) || silent(c)).map(|c| c.id).collect(),
+            overdue_buckets: self.buckets_needing_refresh(now, REFRESH_INTERVAL),
+        }
+    }
+
+    /// The k-buckets this node routes by
+    pub fn routing_table(&self) -> &RoutingTable<N> {
+        &self.routing_table
+    }
+
+    /// What this node has observed of `peer`, if it is in the routing table
+    pub fn contact(&self, peer: &NodeId<N>) -> Option<&Contact<N>> {
+        self.routing_table.contact(peer)
+    }
+
+    /// Forget a peer that failed or left; a replacement candidate may take its place.
+    /// Returns whether it was in a bucket.
+    pub fn remove_peer(&mut self, peer: &NodeId<N>) -> bool {
+        self.siblings.remove(peer);
+        self.routing_table.evict(peer)
+    }
+
+    /// Report this node's routing-table changes to `events` from now on
+    pub fn set_routing_events(&mut self, events: Arc<dyn RoutingEvents<N>>) {
+        self.routing_table.events = Some(events);
+    }
+
+    /// Whether `peer` is in one of this node's buckets (replacement candidates do not count)
+    pub fn has_peer(&self, peer: &NodeId<N>) -> bool {
+        self.routing_table.contact(peer).is_some()
+    }
+
+    /// A peer from the routing table chosen uniformly at random, e.g. to gossip with
+    pub fn random_peer(&self, rng: &mut impl Rng) -> Option<NodeId<N>> {
+        self.routing_table.random_contact(rng).map(|c| c.id)
+    }
+
+    /// Up to `n` distinct peers from the routing table, chosen uniformly at random
+    pub fn random_peers(&self, n: usize, rng: &mut impl Rng) -> Vec<NodeId<N>> {
+        self.routing_table.random_contacts(n, rng).into_iter().map(|c| c.id).collect()
+    }
+
+    // The RPCs below assume the network has already introduced `from` through `track_peer`
+
+    /// RPC: ping - used to check liveness
+    pub fn rpc_ping(&mut self, _from: &NodeId<N>) -> bool {
+        true
+    }
+
+    /// Stamp for a storage read or write, later than every earlier one
+    fn next_access(&mut self) -> u64 {
+        self.accesses += 1;
+        self.accesses
+    }
+
+    /// Evict least-recently-accessed entries (other than `key`) until `needed` more bytes fit
+    /// under the storage limit; false if they cannot fit even with everything else gone
+    fn make_room(&mut self, key: &NodeId<N>, needed: usize) -> bool {
+        let Some(limit) = self.storage_limit_bytes else {
+            return true;
+        };
+        if needed > limit {
+            return false;
+        }
+        self.expire_entries();
+        self.storage.remove(key);
+        let mut used = self.storage_bytes();
+        while used + needed > limit {
+            let oldest = self.storage.iter().min_by_key(|(_, stored)| stored.last_access).map(|(k, _)| *k);
+            let Some(evicted) = oldest.and_then(|k| self.storage.remove(&k)) else {
+                break;
+            };
+            used -= N + evicted.value.len();
+        }
+        true
+    }
+
+    /// RPC: store - store a value under a key ID locally for `ttl`, evicting the least recently
+    /// accessed entries if the storage limit requires it. A signed mutable value for the key
+    /// goes through `rpc_store_mutable`, so republished and synced records keep their
+    /// sequence checks; plain bytes cannot replace a signed value.
+    pub fn rpc_store(
+        &mut self,
+        from: &NodeId<N>,
+        key: NodeId<N>,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<(), KademliaError<N>> {
+        if let Some(mv) = MutableValue::stored_under(&key, &value) {
+            return self.rpc_store_mutable(from, mv, ttl);
+        }
+        if self.live_mutable(&key).is_some() {
+            return Err(KademliaError::ProtocolError(format!("key {key} holds a signed value")));
+        }
+        self.put(key, value, ttl)
+    }
+
+    /// RPC: store several byte-keyed entries in one call, each hashed to its key ID and
+    /// stored as by `rpc_store` for DEFAULT_TTL; entries the node refuses (e.g. over its
+    /// storage limit) are skipped. Returns how many it accepted.
+    pub fn rpc_store_batch(&mut self, from: &NodeId<N>, entries: Vec<(Vec<u8>, Vec<u8>)>) -> usize {
+        let mut accepted = 0;
+        for (key, value) in entries {
+            let key_id = self.hasher.hash_key(&key);
+            if self.rpc_store(from, key_id, value, DEFAULT_TTL).is_ok() {
+                accepted += 1;
+            }
+        }
+        accepted
+    }
+
+    /// RPC: store a signed mutable value under its key ID for `ttl`, after checking the
+    /// signature and that it is newer than the version already stored; re-storing the very
+    /// version already held only extends its TTL (never shortens it)
+    pub fn rpc_store_mutable(&mut self, _from: &NodeId<N>, mv: MutableValue, ttl: Duration) -> Result<(), KademliaError<N>> {
+        if !mv.verify() {
+            return Err(KademliaError::InvalidSignature);
+        }
+        let key = mv.key_id();
+        if let Some(stored) = self.live_mutable(&key) {
+            if stored == mv {
+                let expires_at = self.clock.now() + ttl;
+                if let Some(entry) = self.storage.get_mut(&key) {
+                    entry.expires_at = entry.expires_at.max(expires_at);
+                }
+                return Ok(());
+            }
+            if stored.sequence_number >= mv.sequence_number {
+                return Err(KademliaError::OutdatedSequence {
+                    stored: stored.sequence_number,
+                    offered: mv.sequence_number,
+                });
+            }
+        }
+        self.put(key, mv.to_bytes(), ttl)
+    }
+
+    /// RPC: store an immutable value under `key` for `ttl`, provided `key` is its hash
+    pub fn rpc_store_immutable(
+        &mut self,
+        from: &NodeId<N>,
+        key: NodeId<N>,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<(), KademliaError<N>> {
+        if NodeId::for_content(&value) != key {
+            return Err(KademliaError::HashMismatch);
+        }
+        self.rpc_store(from, key, value, ttl)
+    }
+
+    /// The live signed mutable value under `key`, if that is what it holds
+    fn live_mutable(&self, key: &NodeId<N>) -> Option<MutableValue> {
+        let now = self.clock.now();
+        let stored = self.storage.get(key).filter(|stored| stored.expires_at > now)?;
+        MutableValue::stored_under(key, &stored.value)
+    }
+
+    /// Store `value` under `key` for `ttl`, making room first if the node has a storage limit
+    fn put(&mut self, key: NodeId<N>, value: Vec<u8>, ttl: Duration) -> Result<(), KademliaError<N>> {
+        if !self.make_room(&key, N + value.len()) {
+            return Err(KademliaError::StoreFull(self.id));
+        }
+        let stored = StoredValue {
+            value,
+            expires_at: self.clock.now() + ttl,
+            last_access: self.next_access(),
+        };
+        self.storage.insert(key, stored);
+        Ok(())
+    }
+
+    /// RPC: find_value - try to get the value for a key ID (expired entries are not served)
+    pub fn rpc_find_value(&mut self, _from: &NodeId<N>, key: &NodeId<N>) -> Option<Vec<u8>> {
+        let now = self.clock.now();
+        let access = self.next_access();
+        let stored = self.storage.get_mut(key).filter(|stored| stored.expires_at > now)?;
+        stored.last_access = access;
+        Some(stored.value.clone())
+    }
+
+    /// RPC: find_node - return up to `count` nodes closest to the target id (never more than
+    /// `FindNodeOptions::max_count`, k by default): known peers, plus this node and minus the
+    /// requester as `FindNodeOptions` say
+    pub fn rpc_find_node(&mut self, from: &NodeId<N>, target: &NodeId<N>, count: usize) -> Vec<NodeId<N>> {
+        if let Some(forged) = &self.forged_find_node {
+            return forged.clone();
+        }
+        let options = self.find_node;
+        let count = count.min(options.max_count.unwrap_or(self.routing_table.k));
+        // one spare in case the requester is dropped
+        let mut closest = self.routing_table.closest_k(target, count + usize::from(options.exclude_requester));
+        if options.exclude_requester {
+            closest.retain(|peer| peer != from);
+        }
+        if options.include_self {
+            closest.push(self.id);
+            distance::sort_by_distance(target, &mut closest);
+        }
+        closest.truncate(count);
+        closest
+    }
+
+    /// Misbehave: answer every find_node with `answer` as is, whatever the table holds (for
+    /// checking that lookups survive junk, duplicate or oversized answers)
+    pub fn forge_find_node_answers(&mut self, answer: Vec<NodeId<N>>) {
+        self.forged_find_node = Some(answer);
+    }
+
+    /// RPC: list the key IDs of every live entry (not the values), in ID order
+    pub fn rpc_get_storage_manifest(&self) -> Vec<NodeId<N>> {
+        let mut keys: Vec<NodeId<N>> = self.storage_keys().copied().collect();
+        keys.sort();
+        keys
+    }
+}
+
+#[cfg(test)]
+mod node_tests {
+    use super::*;
+
+    #[test]
+    fn a_full_node_evicts_the_least_recently_read_entry() {
+        // a 20-byte key and a 12-byte value take 32 bytes, so three entries fit in 100
+        let mut node: Node = Node::with_storage_limit(100);
+        let me = node.id;
+        let keys: Vec<NodeId> = (1..=4).map(NodeId::from_u8).collect();
+        for key in &keys[..3] {
+            node.rpc_store(&me, *key, vec![0; 12], DEFAULT_TTL).expect("fits under the limit");
+        }
+        node.rpc_find_value(&me, &keys[0]);
+        node.rpc_store(&me, keys[3], vec![0; 12], DEFAULT_TTL).expect("room is made by evicting");
+        let kept: Vec<bool> = keys.iter().map(|key| node.rpc_find_value(&me, key).is_some()).collect();
+        assert_eq!(kept, [true, false, true, true]);
+        assert_eq!(node.storage_bytes(), 96);
+    }
+
+    #[test]
+    fn a_value_too_big_for_the_limit_is_refused() {
+        let mut node: Node = Node::with_storage_limit(100);
+        let me = node.id;
+        node.rpc_store(&me, NodeId::from_u8(1), vec![0; 12], DEFAULT_TTL).expect("fits");
+        assert_eq!(node.rpc_store(&me, NodeId::from_u8(2), vec![0; 200], DEFAULT_TTL), Err(KademliaError::StoreFull(me)));
+        assert!(node.rpc_find_value(&me, &NodeId::from_u8(1)).is_some(), "a refused store evicts nothing");
+    }
+
+    #[test]
+    fn find_node_answers_sorted_by_distance() {
+        let mut node: Node = Node::with_id(NodeId::ZERO, SimClock::new(), &NetworkConfig::default());
+        let mut rng = StdRng::seed_from_u64(22);
+        for _ in 0..100 {
+            node.track_peer(&NodeId::random_with(&mut rng), None, 0, None);
+        }
+        assert!(node.routing_table.buckets.len() > 1, "the peers should span several buckets");
+        let requester = NodeId::MAX;
+        for _ in 0..20 {
+            let target: NodeId = NodeId::random_with(&mut rng);
+            let answer = node.rpc_find_node(&requester, &target, K);
+            assert_eq!(answer.len(), K);
+            assert!(answer.windows(2).all(|w| target.xor_distance(&w[0]) < target.xor_distance(&w[1])));
+            // the closest of every bucket, not just the target's, counting the node itself
+            let mut everyone: Vec<NodeId> = node.routing_table.peers().copied().chain([node.id]).collect();
+            everyone.sort_by_key(|peer| target.xor_distance(peer));
+            assert_eq!(answer, everyone[..K]);
+        }
+    }
+
+    #[test]
+    fn the_routing_table_draws_as_dot_with_stale_contacts_dashed() {
+        let mut node: Node = Node::with_id(NodeId::ZERO, SimClock::new(), &NetworkConfig::default());
+        let (near, far) = (NodeId::from_u8(1), NodeId::MAX);
+        node.track_peer(&near, None, 0, None);
+        node.track_peer(&far, None, 0, None);
+        for _ in 0..node.routing_table.stale_after {
+            node.routing_table.record_failure(&far);
+        }
+        let dot = node.routing_to_dot();
+        assert!(dot.starts_with("digraph routing {") && dot.ends_with('}'));
+        assert!(dot.contains(&format!("\"{}\" [label=\"{}\", shape=doublecircle];", node.id, node.id.short())));
+        let edges: Vec<&str> = dot.lines().filter(|line| line.contains("->")).collect();
+        assert_eq!(edges.len(), 2);
+        assert!(edges.iter().any(|edge| edge.contains(&near.to_string()) && !edge.contains("dashed")));
+        assert!(edges.iter().any(|edge| edge.contains(&far.to_string()) && edge.contains("style=dashed")));
+    }
+
+    #[test]
+    fn a_mutable_value_only_moves_forward() {
+        let mut node: Node = Node::new();
+        let me = node.id;
+        let owner = NodeIdentity::generate(&mut StdRng::seed_from_u64(35));
+        let key = owner.sign_value(0, vec![]).key_id();
+        let v1 = owner.sign_value(1, b"v1".to_vec());
+        node.rpc_store_mutable(&me, v1.clone(), DEFAULT_TTL).expect("first version");
+        let mut forged = owner.sign_value(2, b"v2".to_vec());
+        forged.value = b"evil".to_vec();
+        assert_eq!(node.rpc_store_mutable(&me, forged, DEFAULT_TTL), Err(KademliaError::InvalidSignature));
+        let outdated = |offered| Err(KademliaError::OutdatedSequence { stored: 1, offered });
+        assert_eq!(node.rpc_store_mutable(&me, owner.sign_value(0, b"v0".to_vec()), DEFAULT_TTL), outdated(0));
+        assert_eq!(node.rpc_store_mutable(&me, owner.sign_value(1, b"other".to_vec()), DEFAULT_TTL), outdated(1));
+        // the version already held may be stored again, e.g. when it is republished
+        node.rpc_store_mutable(&me, v1, DEFAULT_TTL).expect("the same version");
+        node.rpc_store_mutable(&me, owner.sign_value(2, b"v2".to_vec()), DEFAULT_TTL).expect("a newer version");
+        assert_eq!(node.live_mutable(&key).map(|mv| mv.value), Some(b"v2".to_vec()));
+    }
+
+    #[test]
+    fn plain_stores_of_signed_records_keep_the_sequence_checks() {
+        let mut node: Node = Node::new();
+        let me = node.id;
+        let owner = NodeIdentity::generate(&mut StdRng::seed_from_u64(35));
+        let (v1, v2) = (owner.sign_value(1, b"v1".to_vec()), owner.sign_value(2, b"v2".to_vec()));
+        let key = v1.key_id();
+        node.rpc_store(&me, key, v1.to_bytes(), DEFAULT_TTL).expect("a signed record");
+        node.rpc_store(&me, key, v2.to_bytes(), DEFAULT_TTL).expect("an update");
+        assert_eq!(
+            node.rpc_store(&me, key, v1.to_bytes(), DEFAULT_TTL),
+            Err(KademliaError::OutdatedSequence { stored: 2, offered: 1 })
+        );
+        assert!(matches!(node.rpc_store(&me, key, b"plain".to_vec(), DEFAULT_TTL), Err(KademliaError::ProtocolError(_))));
+        assert_eq!(node.live_mutable(&key), Some(v2));
+    }
+
+    #[test]
+    fn responsibility_flips_when_a_closer_sibling_appears() {
+        let mut node: Node<1> = NodeBuilder::default().with_id(NodeId::from_bytes([0x10])).with_k(2).with_bucket_count(8).build();
+        let key_id = NodeId::from_bytes([0x18]);
+        let mut claims = vec![];
+        for peer in [0x80, 0x40, 0x11, 0x19, 0x1a] {
+            node.track_peer(&NodeId::from_bytes([peer]), None, 0, None);
+            claims.push(node.is_responsible_for(&key_id));
+        }
+        // 0x19 is the first peer closer to 0x18 than we are, and 0x1a the second
+        assert_eq!(claims, [true, true, true, true, false]);
+        let siblings: Vec<u8> = node.siblings().iter().map(|id| id.0[0]).collect();
+        assert_eq!(siblings, [0x11, 0x19, 0x1a, 0x40]);
+    }
+
+    #[test]
+    fn hearsay_joins_only_a_bucket_with_room_and_stays_unverified() {
+        let node_at = |id: u8| NodeId::<1>::from_bytes([id]);
+        let mut full: Node<1> = NodeBuilder::default().with_id(node_at(0x00)).with_k(2).with_bucket_count(1).build();
+        for peer in [0x80, 0x40] {
+            full.track_peer(&node_at(peer), None, 0, None);
+        }
+        assert!(!full.record_contact(&node_at(0xc0)));
+        assert!(full.routing_table.contacts().all(|c| c.verified), "hearsay evicted a verified peer");
+
+        let mut roomy: Node<1> = NodeBuilder::default().with_id(node_at(0x00)).with_k(2).with_bucket_count(1).build();
+        assert!(roomy.record_contact(&node_at(0xc0)));
+        assert!(!roomy.contact(&node_at(0xc0)).expect("recorded").verified);
+        roomy.track_peer(&node_at(0x80), None, 0, None);
+        // the unverified entry is the first to be pinged when a verified peer needs room
+        let outcome = roomy.track_peer(&node_at(0x40), None, 0, None);
+        assert_eq!(outcome, TrackOutcome::BucketFull { evict_candidate: node_at(0xc0) });
+    }
+
+    #[test]
+    fn each_eviction_policy_picks_its_own_victim() {
+        // 0x80 is the least recently seen, 0x40 has missed one RPC, and 0xc0 is the newcomer
+        let full_bucket = |policy| {
+            let config = NetworkConfig::builder().k(2).bucket_count(1).eviction_policy(policy).build();
+            let mut node: Node<1> = Node::with_id(NodeId::from_bytes([0x00]), SimClock::new(), &config);
+            for peer in [0x80, 0x40] {
+                node.track_peer(&NodeId::from_bytes([peer]), None, 0, None);
+            }
+            node.routing_table.record_failure(&NodeId::from_bytes([0x40]));
+            let outcome = node.track_peer(&NodeId::from_bytes([0xc0]), None, 0, None);
+            (outcome, node.routing_table.peers().map(|p| p.0[0]).collect::<Vec<u8>>())
+        };
+        let ping = |peer| TrackOutcome::BucketFull { evict_candidate: NodeId::from_bytes([peer]) };
+        assert_eq!(full_bucket(EvictionPolicy::LruPing), (ping(0x80), vec![0x80, 0x40]));
+        assert_eq!(full_bucket(EvictionPolicy::LeastReliable), (ping(0x40), vec![0x80, 0x40]));
+        assert_eq!(full_bucket(EvictionPolicy::OldestFirst), (TrackOutcome::Tracked, vec![0x40, 0xc0]));
+    }
+
+    #[test]
+    fn silent_peers_go_stale_by_time_and_pings_record_round_trips() {
+        let clock = SimClock::new();
+        let mut listener: Node<1> = Node::with_id(NodeId::from_bytes([0x00]), clock.clone(), &NetworkConfig::default());
+        listener.track_peer(&NodeId::from_bytes([0x80]), None, 0, None);
+        clock.advance(Duration::from_secs(600));
+        listener.track_peer(&NodeId::from_bytes([0x40]), None, 0, None);
+        assert_eq!(listener.stale_peers(Duration::from_secs(300)), vec![NodeId::from_bytes([0x80])]);
+        assert_eq!(listener.contact(&NodeId::from_bytes([0x40])).and_then(|c| c.last_seen), Some(clock.now()));
+
+        let mut timed: Network = Network::with_seed(43);
+        timed.config.latency_model = LatencyModel::Uniform(Duration::from_millis(20), Duration::from_millis(80));
+        let (pinger, pinged) = (timed.add_node(), timed.add_node());
+        timed.ping(&pinger, &pinged).expect("alive");
+        let rtt = timed.nodes[&pinger].contact(&pinged).and_then(|c| c.rtt).expect("the ping was answered");
+        assert!((Duration::from_millis(20)..=Duration::from_millis(80)).contains(&rtt), "{rtt:?}");
+    }
+
+    #[test]
+    fn near_buckets_hold_more_than_far_ones_but_answers_stay_at_k() {
+        // two or more shared prefix bits count as near: four contacts there, one farther out
+        let config = NetworkConfig::builder().k(2).bucket_sizes(4, 1, 2).build();
+        let mut deep: Node<1> = Node::with_id(NodeId::from_bytes([0x00]), SimClock::new(), &config);
+        for peer in [0x80, 0xc0, 0x40, 0x60, 0x20, 0x30, 0x28, 0x38, 0x24, 0x10, 0x18] {
+            deep.track_peer(&NodeId::from_bytes([peer]), None, 0, None);
+        }
+        let per_bucket: Vec<usize> = deep.routing_table.buckets.iter().map(KBucket::len).collect();
+        assert_eq!(&per_bucket[..3], &[1, 1, 4], "far buckets should hold one contact, near ones four");
+        assert_eq!(deep.rpc_find_node(&NodeId::from_bytes([0xff]), &NodeId::from_bytes([0x01]), config.k).len(), 2);
+    }
+
+    #[test]
+    fn routing_events_follow_a_bucket_through_its_changes() {
+        let log = Arc::new(RoutingEventLog::default());
+        let config = NetworkConfig::builder().k(2).bucket_count(1).build();
+        let mut watched: Node<1> = Node::with_id(NodeId::from_u8(0x00), SimClock::new(), &config);
+        watched.set_routing_events(log.clone());
+        for peer in [0x80, 0x40, 0x80, 0xc0] {
+            watched.track_peer(&NodeId::from_u8(peer), None, 0, None);
+        }
+        for _ in 0..config.stale_after {
+            watched.routing_table.record_failure(&NodeId::from_u8(0x40));
+        }
+        let script: Vec<(RoutingChange, u8)> = log.events().iter().map(|e| (e.change, e.peer.0[0])).collect();
+        use RoutingChange::*;
+        assert_eq!(script, [(Added, 0x80), (Added, 0x40), (Refreshed, 0x80), (Stale, 0x40), (Evicted, 0x40), (Promoted, 0xc0)]);
+    }
+
+    #[test]
+    fn merged_contacts_follow_the_bucket_rules_and_are_merged_once() {
+        let mut rng = StdRng::seed_from_u64(45);
+        let mut fresh: Node = NodeBuilder::default().with_id(NodeId::random_with(&mut rng)).build();
+        let imported: Vec<Contact<ID_BYTES>> = (0..500).map(|_| Contact::new(NodeId::random_with(&mut rng), Instant::now())).collect();
+        let summary = fresh.merge_contacts(&imported);
+        assert_eq!(summary.inserted + summary.cached + summary.rejected, imported.len());
+        assert!(summary.inserted > 0 && summary.cached > 0, "{summary:?}");
+        assert!(fresh.routing_table.is_well_formed(), "merging should respect the bucket invariants");
+        assert!(fresh.routing_table.contacts().all(|c| !c.verified), "merged contacts are second-hand");
+        assert_eq!(fresh.merge_contacts(&imported[..10]).inserted, 0, "contacts already known should not be merged again");
+    }
+
+    #[test]
+    fn the_contact_cap_drops_replacement_candidates_before_refusing_newcomers() {
+        let config = NetworkConfig::builder().k(2).max_contacts(6).build();
+        let mut capped: Node<1> = Node::with_id(NodeId::from_u8(0x00), SimClock::new(), &config);
+        for peer in [0x80, 0x81, 0x82, 0x83, 0x40, 0x41, 0x42, 0x20, 0x21] {
+            capped.track_peer(&NodeId::from_u8(peer), None, 0, None);
+            assert!(capped.routing_table.contact_count() <= 6, "the cap should hold after every insert");
+            if peer == 0x42 {
+                assert_eq!((capped.routing_table.replacement_len(0), capped.routing_table.replacement_len(1)), (1, 1));
+            }
+        }
+        assert_eq!((capped.routing_table.replacement_len(0), capped.routing_table.replacement_len(1)), (0, 0));
+
+        let newcomer = NodeId::from_u8(0x10);
+        assert_eq!(capped.track_peer(&newcomer, None, 0, None), TrackOutcome::Refused);
+        assert!(capped.contact(&newcomer).is_none(), "with every cache empty, newcomers are refused");
+        assert!(!capped.siblings().contains(&newcomer), "a refused peer is not a sibling either");
+    }
+
+    #[test]
+    fn routing_health_flags_failing_and_silent_contacts_on_the_nodes_own_clock() {
+        let (clock, config) = (SimClock::new(), NetworkConfig::default());
+        let mut checked: Node<1> = Node::with_id(NodeId::from_u8(0x00), clock.clone(), &config);
+        let [failing, silent, fresh] = [0x80, 0x40, 0x20].map(NodeId::from_u8);
+        checked.track_peer(&failing, None, 0, None);
+        checked.track_peer(&silent, None, 0, None);
+        clock.advance(REFRESH_INTERVAL);
+        checked.track_peer(&failing, None, 0, None);
+        checked.track_peer(&fresh, None, 0, None);
+        for _ in 0..config.stale_after {
+            checked.routing_table.record_failure(&failing);
+        }
+        let mut stale = checked.routing_health().stale_contacts;
+        stale.sort();
+        assert_eq!(stale, vec![silent, failing]);
+        assert!(checked.estimate_size() >= 1.0);
+    }
+}
+
+/// Fill an N-byte ID from a digest: truncated for narrower IDs, while wider IDs are filled
+/// by re-hashing the previous digest
+fn id_from_digest<D: Digest, const N: usize>(key: &[u8]) -> NodeId<N> {
+    let mut bytes = [0u8; N];
+    let mut digest = D::digest(key);
+    for chunk in bytes.chunks_mut(digest.len()) {
+        chunk.copy_from_slice(&digest[..chunk.len()]);
+        digest = D::digest(&digest);
+    }
+    NodeId::from_bytes(bytes)
+}
+
+/// Maps application keys into the ID space; every node in a network must agree on it
+pub trait KeyHasher<const N: usize = ID_BYTES>: fmt::Debug + Send + Sync {
+    fn hash_key(&self, key: &[u8]) -> NodeId<N>;
+}
+
+/// SHA-1, as in the original paper (the default)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sha1Hasher;
+
+impl<const N: usize> KeyHasher<N> for Sha1Hasher {
+    fn hash_key(&self, key: &[u8]) -> NodeId<N> {
+        id_from_digest::<Sha1, N>(key)
+    }
+}
+
+/// SHA-256, truncated to the ID width (160 bits by default, the whole digest for 256-bit IDs)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sha256Hasher;
+
+impl<const N: usize> KeyHasher<N> for Sha256Hasher {
+    fn hash_key(&self, key: &[u8]) -> NodeId<N> {
+        id_from_digest::<Sha256, N>(key)
+    }
+}
+
+/// The built-in key hashes a network can be configured with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HashFunction {
+    #[default]
+    Sha1,
+    Sha256,
+    #[cfg(feature = "blake3")]
+    Blake3,
+}
+
+impl<const N: usize> KeyHasher<N> for HashFunction {
+    fn hash_key(&self, key: &[u8]) -> NodeId<N> {
+        match self {
+            HashFunction::Sha1 => Sha1Hasher.hash_key(key),
+            HashFunction::Sha256 => Sha256Hasher.hash_key(key),
+            // BLAKE3 is an extendable-output hash, so any width is read straight from it
+            #[cfg(feature = "blake3")]
+            HashFunction::Blake3 => {
+                let mut bytes = [0u8; N];
+                blake3::Hasher::new().update(key).finalize_xof().fill(&mut bytes);
+                NodeId::from_bytes(bytes)
+            }
+        }
+    }
+}
+
+/// A value an origin node has published and is responsible for keeping alive
+#[derive(Clone, Debug)]
+pub struct PublishedValue {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    ttl: Duration,
+    last_published: Instant,
+}
+
+impl PublishedValue {
+    /// Due once half the TTL has passed, leaving time to re-store before replicas expire
+    pub fn is_due(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_published) >= self.ttl / 2
+    }
+}
+
+/// A value spreading by gossip: who holds it so far, and whom each holder has passed it to
+#[derive(Clone, Debug)]
+pub struct Rumor<const N: usize = ID_BYTES> {
+    key: NodeId<N>,
+    value: Vec<u8>,
+    fanout: usize,                                // untold peers each holder tells per round
+    told: HashMap<NodeId<N>, HashSet<NodeId<N>>>, // holder -> peers it has sent the value to
+}
+
+/// A stored entry in portable form: expiry is kept as the TTL remaining at capture time,
+/// since an `Instant` means nothing outside the process that created it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StoredRecord<const N: usize = ID_BYTES> {
+    key: NodeId<N>,
+    value: Vec<u8>,
+    ttl_remaining: Duration,
+}
+
+/// A node's contacts, saved so it can rejoin after a restart without bootstrapping
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RoutingSnapshot<const N: usize = ID_BYTES> {
+    own_id: NodeId<N>,
+    contacts: Vec<NodeId<N>>, // most recently seen last within each bucket
+}
+
+/// Persistable state of a single node
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeSnapshot<const N: usize = ID_BYTES> {
+    id: NodeId<N>,
+    alive: bool,
+    storage: Vec<StoredRecord<N>>, // a list rather than a map so JSON needs no string keys
+    routing_table: RoutingTable<N>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pow_nonce: Option<u64>,
+}
+
+/// Persistable state of a whole network, used to pause and resume a simulation
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NetworkSnapshot<const N: usize = ID_BYTES> {
+    config: NetworkConfig,
+    nodes: Vec<NodeSnapshot<N>>,
+}
+
+/// How long an RPC takes to come back, in simulated time
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LatencyModel {
+    /// Every RPC answers instantly
+    #[default]
+    Zero,
+    /// Every RPC takes the same time
+    Fixed(Duration),
+    /// Uniformly distributed between the two bounds (inclusive)
+    Uniform(Duration, Duration),
+    /// Normally distributed with this mean and standard deviation in milliseconds, never
+    /// below zero
+    Normal(f64, f64),
+}
+
+impl LatencyModel {
+    /// Draw one round-trip time
+    fn sample(&self, rng: &mut impl Rng) -> Duration {
+        match *self {
+            LatencyModel::Zero => Duration::ZERO,
+            LatencyModel::Fixed(latency) => latency,
+            LatencyModel::Uniform(low, high) if low < high => rng.gen_range(low..=high),
+            LatencyModel::Uniform(low, _) => low,
+            LatencyModel::Normal(mean_ms, std_dev_ms) => {
+                // Box-Muller transform
+                let (u1, u2): (f64, f64) = (rng.gen_range(f64::EPSILON..1.0), rng.r#gen());
+                let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+                Duration::from_secs_f64((mean_ms + std_dev_ms * z).max(0.0) / 1000.0)
+            }
+        }
+    }
+}
+
+/// How a node answers find_node
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FindNodeOptions {
+    /// List the responder itself when it is among the k closest to the target
+    pub include_self: bool,
+    /// Leave the requester out, since it already knows itself
+    pub exclude_requester: bool,
+    /// Most IDs one answer holds, however many the requester asks for (k if unset; raise it
+    /// so a joining node asking for extra contacts gets them)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_count: Option<usize>,
+}
+
+impl FindNodeOptions {
+    /// What a requester keeps of `responder`'s answer: each ID once, never the requester
+    /// itself, the responder only if answers under these options may list it, and no more
+    /// than `k` in all, however many a buggy or malicious responder sent
+    fn sanitize<const N: usize>(
+        &self,
+        requester: &NodeId<N>,
+        responder: &NodeId<N>,
+        answer: Vec<NodeId<N>>,
+        k: usize,
+    ) -> Vec<NodeId<N>> {
+        let mut seen = HashSet::new();
+        answer
+            .into_iter()
+            .filter(|id| id != requester && (self.include_self || id != responder) && seen.insert(*id))
+            .take(k)
+            .collect()
+    }
+}
+
+impl Default for FindNodeOptions {
+    fn default() -> Self {
+        Self {
+            include_self: true,
+            exclude_requester: true,
+            max_count: None,
+        }
+    }
+}
+
+/// Bucket capacities that depend on depth: buckets whose peers share at least `near_depth`
+/// prefix bits with us hold `near` contacts, shallower ones `far`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BucketSizes {
+    pub near: usize,
+    pub far: usize,
+    pub near_depth: usize,
+}
+
+/// Which contact a full bucket gives up for a newcomer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EvictionPolicy {
+    /// Ping the least-recently-seen contact and evict it only if it fails to answer, so
+    /// long-lived peers are kept (the paper's rule)
+    #[default]
+    LruPing,
+    /// Ping the contact that has missed the most RPCs in a row (the least recently seen
+    /// among ties) and evict it only if it fails to answer
+    LeastReliable,
+    /// Evict the least-recently-seen contact right away, without a ping, so the bucket
+    /// always holds the peers heard from most recently
+    OldestFirst,
+}
+
+/// Protocol parameters shared by every node of a network (`PartialEq` but not `Eq`, since
+/// `packet_loss_rate` is an `f64`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NetworkConfig {
+    /// Bucket size, and how many nodes a lookup returns
+    pub k: usize,
+    /// Queries sent per lookup round
+    pub alpha: usize,
+    /// Lookup rounds before giving up
+    pub max_steps: usize,
+    /// Most k-buckets a node's table may split into, capped at the ID width in bits (the
+    /// default allows one per bit)
+    pub bucket_count: usize,
+    /// Whether nodes must prove work on their IDs to join and to be tracked by peers
+    pub require_pow: bool,
+    /// Leading zero bits required of SHA-1(ID || nonce) when `require_pow` is set
+    pub pow_difficulty: u8,
+    /// Bytes each node may store (key ID plus value per entry), if limited
+    pub storage_limit_bytes: Option<usize>,
+    /// How keys map to IDs
+    pub hash_function: HashFunction,
+    /// Consecutive unanswered RPCs after which a contact is stale: no longer returned by
+    /// find_node, and evicted as soon as a replacement is waiting
+    pub stale_after: u32,
+    /// Chance that any RPC is lost in transit, which the sender sees as a timeout
+    pub packet_loss_rate: f64,
+    /// How long each RPC takes to come back
+    pub latency_model: LatencyModel,
+    /// How find_node answers are shaped
+    pub find_node: FindNodeOptions,
+    /// Peers closest to its own ID each node tracks as siblings, if not the default 2k
+    pub sibling_list_size: Option<usize>,
+    /// Prefix bits each routing-table split consumes (the paper's b, 1 to 8): a level of
+    /// the tree has 2^b - 1 buckets, and lookups take about log2(n) / b hops
+    pub bits_per_bucket: usize,
+    /// Contacts sharing an origin tag that one bucket may hold, if capped; the rest wait in
+    /// the replacement cache, so a single origin cannot fill (eclipse) a bucket
+    pub max_per_origin_per_bucket: Option<usize>,
+    /// Which contact a full bucket gives up for a newcomer
+    pub eviction_policy: EvictionPolicy,
+    /// Per-depth bucket capacities, if buckets should not all hold k; find_node answers
+    /// stay capped at k either way
+    pub bucket_sizes: Option<BucketSizes>,
+    /// Contacts plus replacement candidates one routing table may hold in all, if capped;
+    /// the farthest buckets' replacement caches are trimmed first to stay under it
+    pub max_contacts: Option<usize>,
+}
+
+impl Default for NetworkConfig {
+    /// The demo parameters: K, ALPHA and MAX_STEPS, with one bucket per ID bit at any width
+    fn default() -> Self {
+        Self {
+            k: K,
+            alpha: ALPHA,
+            max_steps: MAX_STEPS,
+            bucket_count: usize::MAX,
+            require_pow: false,
+            pow_difficulty: DEFAULT_POW_DIFFICULTY,
+            storage_limit_bytes: None,
+            hash_function: HashFunction::Sha1,
+            stale_after: 3,
+            packet_loss_rate: 0.0,
+            latency_model: LatencyModel::Zero,
+            find_node: FindNodeOptions::default(),
+            sibling_list_size: None,
+            bits_per_bucket: 1,
+            max_per_origin_per_bucket: None,
+            eviction_policy: EvictionPolicy::LruPing,
+            bucket_sizes: None,
+            max_contacts: None,
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// Start from the defaults and override individual parameters
+    pub fn builder() -> NetworkConfigBuilder {
+        NetworkConfigBuilder::default()
+    }
+
+    /// The parameters recommended by the paper for real deployments (k = 20, alpha = 3)
+    pub fn mainnet() -> Self {
+        Self::builder().k(20).alpha(3).build()
+    }
+
+    /// Proof-of-work difficulty nodes demand of their peers' IDs, if `require_pow` is set
+    pub fn required_pow(&self) -> Option<u8> {
+        self.require_pow.then_some(self.pow_difficulty)
+    }
+
+    /// Size of each node's sibling list: `sibling_list_size`, or 2k by default
+    pub fn sibling_count(&self) -> usize {
+        self.sibling_list_size.unwrap_or(2 * self.k)
+    }
+}
+
+/// Builder for `NetworkConfig`; unset parameters keep their default values
+#[derive(Clone, Debug, Default)]
+pub struct NetworkConfigBuilder {
+    config: NetworkConfig,
+}
+
+impl NetworkConfigBuilder {
+    pub fn k(mut self, k: usize) -> Self {
+        self.config.k = k;
+        self
+    }
+
+    pub fn alpha(mut self, alpha: usize) -> Self {
+        self.config.alpha = alpha;
+        self
+    }
+
+    pub fn max_steps(mut self, max_steps: usize) -> Self {
+        self.config.max_steps = max_steps;
+        self
+    }
+
+    pub fn bucket_count(mut self, bucket_count: usize) -> Self {
+        self.config.bucket_count = bucket_count;
+        self
+    }
+
+    pub fn require_pow(mut self, required: bool) -> Self {
+        self.config.require_pow = required;
+        self
+    }
+
+    pub fn pow_difficulty(mut self, difficulty_bits: u8) -> Self {
+        self.config.pow_difficulty = difficulty_bits;
+        self
+    }
+
+    pub fn storage_limit_bytes(mut self, bytes: usize) -> Self {
+        self.config.storage_limit_bytes = Some(bytes);
+        self
+    }
+
+    pub fn hash_function(mut self, func: HashFunction) -> Self {
+        self.config.hash_function = func;
+        self
+    }
+
+    pub fn stale_after(mut self, failures: u32) -> Self {
+        self.config.stale_after = failures;
+        self
+    }
+
+    pub fn packet_loss_rate(mut self, rate: f64) -> Self {
+        self.config.packet_loss_rate = rate;
+        self
+    }
+
+    pub fn latency_model(mut self, model: LatencyModel) -> Self {
+        self.config.latency_model = model;
+        self
+    }
+
+    pub fn find_node(mut self, options: FindNodeOptions) -> Self {
+        self.config.find_node = options;
+        self
+    }
+
+    pub fn sibling_list_size(mut self, size: usize) -> Self {
+        self.config.sibling_list_size = Some(size);
+        self
+    }
+
+    pub fn bits_per_bucket(mut self, bits: usize) -> Self {
+        self.config.bits_per_bucket = bits;
+        self
+    }
+
+    pub fn max_per_origin_per_bucket(mut self, max: usize) -> Self {
+        self.config.max_per_origin_per_bucket = Some(max);
+        self
+    }
+
+    pub fn eviction_policy(mut self, policy: EvictionPolicy) -> Self {
+        self.config.eviction_policy = policy;
+        self
+    }
+
+    /// Buckets `near_depth` or more prefix bits deep hold `near` contacts, the rest `far`
+    pub fn bucket_sizes(mut self, near: usize, far: usize, near_depth: usize) -> Self {
+        self.config.bucket_sizes = Some(BucketSizes { near, far, near_depth });
+        self
+    }
+
+    pub fn max_contacts(mut self, max: usize) -> Self {
+        self.config.max_contacts = Some(max);
+        self
+    }
+
+    /// Finish the config (panics if any parameter is zero, the loss rate is not in 0..=1, or
+    /// the proof-of-work difficulty is over MAX_POW_DIFFICULTY)
+    pub fn build(self) -> NetworkConfig {
+        let c = self.config;
+        assert!(c.k > 0 && c.alpha > 0 && c.max_steps > 0 && c.bucket_count > 0 && c.stale_after > 0, "invalid {c:?}");
+        assert!((0.0..=1.0).contains(&c.packet_loss_rate), "invalid {c:?}");
+        assert!(c.pow_difficulty <= MAX_POW_DIFFICULTY, "invalid {c:?}");
+        assert!((1..=8).contains(&c.bits_per_bucket), "invalid {c:?}");
+        assert!(c.max_per_origin_per_bucket != Some(0) && c.max_contacts != Some(0), "invalid {c:?}");
+        // a split only moves contacts deeper, so deeper buckets must hold at least as many
+        assert!(c.bucket_sizes.is_none_or(|sizes| 0 < sizes.far && sizes.far <= sizes.near), "invalid {c:?}");
+        c
+    }
+}
+
+/// An in-memory network that owns nodes and forwards RPC calls between them
+pub struct Network<const N: usize = ID_BYTES> {
+    nodes: HashMap<NodeId<N>, Node<N>>,
+    hasher: Arc<dyn KeyHasher<N>>, // handed to every node, for byte keys sent to it
+    clock: SimClock,
+    publishers: HashMap<NodeId<N>, Vec<PublishedValue>>, // origin -> values it republishes
+    rng: StdRng,                                         // source of node IDs
+    transport_rng: StdRng,                               // decides packet loss, latencies and gossip
+    config: NetworkConfig,
+    stats: NetworkStats,
+    evicting: bool,         // a ping-before-evict is in flight
+    last_latency: Duration, // round-trip time of the latest RPC
+    rumors: Vec<Rumor<N>>,  // values still spreading by gossip
+    partition: Option<HashSet<(NodeId<N>, NodeId<N>)>>, // (from, to) pairs whose RPCs are dropped
+    routing_events: Option<Arc<dyn RoutingEvents<N>>>,  // observer given to every node, if any
+}
+
+/// A network of 256-bit IDs; keys map to whole SHA-256 digests
+pub type Network256 = Network<32>;
+
+/// Subtrees of the ID space whose every node some lookup has shown, each as (an ID inside
+/// it, its prefix length, its nodes). A lookup's k closest to `t` hold every node sharing
+/// more bits with `t` than the farthest of them does, and two such sibling subtrees make up
+/// their parent. Once a subtree holds k nodes, every key inside it has its k closest there.
+#[derive(Default)]
+struct KnownSubtrees<const N: usize>(Vec<(NodeId<N>, usize, Vec<NodeId<N>>)>);
+
+impl<const N: usize> KnownSubtrees<N> {
+    /// Take in the k closest nodes to `target` a lookup found; returns the prefix length of
+    /// the subtree around `target` they complete (0, the whole ID space, if fewer than k)
+    fn learn(&mut self, target: &NodeId<N>, closest: &[NodeId<N>], k: usize) -> usize {
+        let len = match closest.iter().max_by_key(|c| target.xor_distance(c)) {
+            Some(farthest) if closest.len() >= k => target.common_prefix_len(farthest) + 1,
+            _ => 0,
+        };
+        let nodes = closest.iter().filter(|c| target.common_prefix_len(c) >= len).copied().collect();
+        self.insert(*target, len, nodes);
+        len
+    }
+
+    fn insert(&mut self, prefix: NodeId<N>, len: usize, mut nodes: Vec<NodeId<N>>) {
+        if self.0.iter().any(|(other, other_len, _)| *other_len <= len && other.common_prefix_len(&prefix) >= *other_len) {
+            return;
+        }
+        self.0.retain(|(other, other_len, _)| *other_len < len || other.common_prefix_len(&prefix) < len);
+        let sibling = self.0.iter().position(|(other, other_len, _)| *other_len == len && other.common_prefix_len(&prefix) == len - 1);
+        match sibling {
+            Some(i) => {
+                nodes.extend(self.0.swap_remove(i).2);
+                self.insert(prefix, len - 1, nodes);
+            }
+            None => self.0.push((prefix, len, nodes)),
+        }
+    }
+
+    /// The known subtree holding `id`, if any (at most one does, since nested ones are dropped)
+    fn containing(&self, id: &NodeId<N>) -> Option<&(NodeId<N>, usize, Vec<NodeId<N>>)> {
+        self.0.iter().find(|(prefix, len, _)| prefix.common_prefix_len(id) >= *len)
+    }
+
+    /// `key`'s k closest nodes, if the known subtree around it holds them
+    fn k_closest(&self, key: &NodeId<N>, k: usize) -> Option<Vec<NodeId<N>>> {
+        self.containing(key)
+            .filter(|(_, len, nodes)| nodes.len() >= k || *len == 0)
+            .map(|(_, _, nodes)| distance::k_closest(key, nodes, k))
+    }
+
+    /// Until `key`'s k closest are known, the nearest ID to it that no known subtree holds:
+    /// each step moves to the sibling half of the subtree holding the last ID, which goes
+    /// one level deeper every time since known siblings have already been merged
+    fn gap(&self, key: &NodeId<N>, k: usize) -> Option<NodeId<N>> {
+        if self.k_closest(key, k).is_some() {
+            return None;
+        }
+        let mut id = *key;
+        while let Some((_, len, _)) = self.containing(&id) {
+            id = id.with_bit_flipped(len.checked_sub(1)?);
+        }
+        Some(id)
+    }
+}
+
+impl<const N: usize> Default for Network<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Network<N> {
+    /// Create an empty network that hashes keys with SHA-1, or with SHA-256 when IDs are
+    /// wider than a SHA-1 digest
+    pub fn new() -> Self {
+        let mut config = NetworkConfig::default();
+        if N > ID_BYTES {
+            config.hash_function = HashFunction::Sha256;
+        }
+        Self::with_config(config)
+    }
+
+    /// Create an empty network whose node IDs and transport (lost packets, latencies, gossip
+    /// targets) come from RNGs seeded with `seed`, so the same seed and sequence of
+    /// operations always produce the same IDs and lookups
+    pub fn with_seed(seed: u64) -> Self {
+        let mut network = Self::new();
+        network.rng = StdRng::seed_from_u64(seed);
+        // a stream of its own, so lost packets or gossip do not shift the IDs of later nodes
+        network.transport_rng = StdRng::seed_from_u64(!seed);
+        network
+    }
+
+    /// Create an empty network whose nodes use the parameters in `config`, hashing keys with
+    /// `config.hash_function`
+    pub fn with_config(config: NetworkConfig) -> Self {
+        Self {
+            nodes: HashMap::new(),
+            hasher: Arc::new(config.hash_function),
+            clock: SimClock::new(),
+            publishers: HashMap::new(),
+            rumors: Vec::new(),
+            partition: None,
+            routing_events: None,
+            rng: StdRng::from_entropy(),
+            transport_rng: StdRng::from_entropy(),
+            config,
+            stats: NetworkStats::default(),
+            evicting: false,
+            last_latency: Duration::ZERO,
+        }
+    }
+
+    /// Create an empty network that maps keys to IDs with a custom `hasher` instead of one of
+    /// the built-in hash functions (snapshots of it do not record the hasher)
+    pub fn with_hasher(hasher: impl KeyHasher<N> + 'static) -> Self {
+        let mut network = Self::new();
+        network.hasher = Arc::new(hasher);
+        network
+    }
+
+    /// Create a network of `count` nodes, each joined through a random earlier one
+    pub fn new_with_nodes(count: usize) -> Self {
+        let mut network = Self::new();
+        network.populate(count);
+        network
+    }
+
+    /// `new_with_nodes` with IDs and join order drawn from an RNG seeded with `seed`
+    pub fn new_with_nodes_seeded(count: usize, seed: u64) -> Self {
+        let mut network = Self::with_seed(seed);
+        network.populate(count);
+        network
+    }
+
+    /// Add `count` nodes one at a time, bootstrapping each through a random node added before it
+    pub fn populate(&mut self, count: usize) {
+        let mut joined: Vec<NodeId<N>> = Vec::with_capacity(count);
+        for _ in 0..count {
+            let id = match joined.choose(&mut self.rng).copied() {
+                Some(known_peer) => self.add_and_bootstrap(&known_peer).expect("every node added so far is alive"),
+                None => self.add_node(),
+            };
+            joined.push(id);
+        }
+    }
+
+    /// Current simulated time
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Move simulated time forward by `by` and nothing else: unlike `tick`, entries are not
+    /// expired and no bucket is refreshed, so what the time does to them can be observed
+    pub fn advance(&self, by: Duration) {
+        self.clock.advance(by);
+    }
+
+    /// Advance simulated time by `elapsed`, expire stale entries on every node, spread gossip
+    /// one round, and let every live node refresh the buckets that have gone REFRESH_INTERVAL
+    /// without a lookup
+    pub fn tick(&mut self, elapsed: Duration) {
+        self.clock.advance(elapsed);
+        self.expire_entries();
+        self.gossip_round();
+        let mut live: Vec<NodeId<N>> = self.nodes.values().filter(|node| node.alive).map(|node| node.id).collect();
+        live.sort();
+        for id in live {
+            self.refresh_node(&id);
+        }
+    }
+
+    /// Drop expired entries on every node
+    pub fn expire_entries(&mut self) {
+        for node in self.nodes.values_mut() {
+            node.expire_entries();
+        }
+    }
+
+    /// Capture every node's storage, routing table and liveness
+    pub fn snapshot(&self) -> NetworkSnapshot<N> {
+        let nodes = self
+            .nodes
+            .values()
+            .map(|node| NodeSnapshot {
+                id: node.id,
+                alive: node.alive,
+                storage: node.records(),
+                routing_table: node.routing_table.clone(),
+                pow_nonce: node.pow_nonce,
+            })
+            .collect();
+        NetworkSnapshot {
+            config: self.config,
+            nodes,
+        }
+    }
+
+    /// Rebuild a network from a snapshot taken with `snapshot`; keys are hashed with the hash
+    /// function in its config
+    pub fn from_snapshot(snap: NetworkSnapshot<N>) -> Self {
+        let mut network = Network::with_config(snap.config);
+        for n in snap.nodes {
+            let mut siblings = SiblingList::new(n.id, snap.config.sibling_count());
+            for peer in n.routing_table.peers() {
+                siblings.insert(peer);
+            }
+            let mut node = Node {
+                id: n.id,
+                storage: HashMap::new(),
+                routing_table: n.routing_table,
+                alive: n.alive,
+                clock: Arc::new(network.clock.clone()),
+                hasher: network.hasher.clone(),
+                identity: None,
+                storage_limit_bytes: snap.config.storage_limit_bytes,
+                find_node: snap.config.find_node,
+                siblings,
+                origin: 0,
+                pow_difficulty: snap.config.required_pow(),
+                pow_nonce: n.pow_nonce,
+                accesses: 0,
+                forged_find_node: None,
+            };
+            node.load_records(n.storage);
+            network.register(node);
+        }
+        network
+    }
+
+    /// Write `id`'s routing table to `path` as JSON
+    #[cfg(feature = "serde")]
+    pub fn save_node(&self, id: &NodeId<N>, path: impl AsRef<Path>) -> io::Result<()> {
+        let node = self
+            .nodes
+            .get(id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("unknown node {id}")))?;
+        let json = serde_json::to_string_pretty(&node.export_routing())?;
+        fs::write(path, json)
+    }
+
+    /// Restart a node from a routing table saved with `save_node`: it is added under its saved
+    /// ID if this network does not have it yet, and the saved peers are imported unverified.
+    /// Returns the node's ID.
+    #[cfg(feature = "serde")]
+    pub fn load_node(&mut self, path: impl AsRef<Path>) -> io::Result<NodeId<N>> {
+        let snapshot: RoutingSnapshot<N> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let id = snapshot.own_id;
+        if !self.nodes.contains_key(&id) {
+            self.add_node_with_id(id)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        }
+        if let Some(node) = self.nodes.get_mut(&id) {
+            node.import_routing(snapshot);
+        }
+        Ok(id)
+    }
+
+    /// The node with this ID, dead or alive, if it was ever added and not removed
+    pub fn node(&self, id: &NodeId<N>) -> Option<&Node<N>> {
+        self.nodes.get(id)
+    }
+
+    /// Every node in the network, dead or alive, in no particular order
+    pub fn nodes(&self) -> impl Iterator<Item = &Node<N>> {
+        self.nodes.values()
+    }
+
+    /// The IDs of every node in the network, in no particular order
+    pub fn node_ids(&self) -> impl Iterator<Item = &NodeId<N>> {
+        self.nodes.keys()
+    }
+
+    /// Create and register a new node with a unique ID; returns its NodeId
+    pub fn add_node(&mut self) -> NodeId<N> {
+        let (id, pow_nonce) = self.fresh_id();
+        let mut node = Node::with_id(id, self.clock.clone(), &self.config);
+        node.pow_nonce = pow_nonce;
+        self.register(node)
+    }
+
+    /// Put `node` in the network, handing it the network's key hasher and the network-wide
+    /// routing observer if there is one; returns its ID
+    fn register(&mut self, mut node: Node<N>) -> NodeId<N> {
+        node.hasher = self.hasher.clone();
+        if let Some(events) = &self.routing_events {
+            node.set_routing_events(events.clone());
+        }
+        let id = node.id;
+        self.nodes.insert(id, node);
+        id
+    }
+
+    /// Have every node, including those added later, report its routing-table changes to
+    /// `events`
+    pub fn set_routing_events(&mut self, events: Arc<dyn RoutingEvents<N>>) {
+        for node in self.nodes.values_mut() {
+            node.set_routing_events(events.clone());
+        }
+        self.routing_events = Some(events);
+    }
+
+    /// An unused random ID, with a nonce proving work on it if this network requires that
+    fn fresh_id(&mut self) -> (NodeId<N>, Option<u64>) {
+        loop {
+            let (id, nonce) = match self.config.required_pow() {
+                Some(difficulty) => {
+                    let (id, nonce) = NodeId::generate_with_pow(difficulty, &mut self.rng);
+                    (id, Some(nonce))
+                }
+                None => (NodeId::random_with(&mut self.rng), None),
+            };
+            if !self.nodes.contains_key(&id) {
+                return (id, nonce);
+            }
+        }
+    }
+
+    /// Register a node under a caller-chosen ID; rejected if the ID is taken or this network
+    /// requires proof of work (use `add_node_with_config` to hand over a mined nonce)
+    pub fn add_node_with_id(&mut self, id: NodeId<N>) -> Result<NodeId<N>, KademliaError<N>> {
+        self.add_node_with_config(NodeBuilder::default().with_id(id))
+    }
+
+    /// Register a node under the ID derived from a raw public key (`NodeId::from_public_key`),
+    /// so one key cannot be registered twice; rejected like `add_node_with_id` otherwise
+    pub fn add_node_with_pubkey(&mut self, pubkey: &[u8]) -> Result<NodeId<N>, KademliaError<N>> {
+        self.add_node_with_id(NodeId::from_public_key(pubkey))
+    }
+
+    /// Register a node built with its own parameters (e.g. a smaller storage limit) on this
+    /// network's clock. A chosen ID is checked as by `add_node_with_id`; otherwise a fresh
+    /// one is generated as by `add_node`.
+    pub fn add_node_with_config(&mut self, builder: NodeBuilder<N>) -> Result<NodeId<N>, KademliaError<N>> {
+        let config = builder.config_for(&self.config);
+        let (id, pow_nonce) = match builder.id {
+            Some(id) if self.nodes.contains_key(&id) => {
+                return Err(KademliaError::DuplicateNodeId(id));
+            }
+            Some(id) if !self.has_required_pow(&id, builder.pow_nonce) => {
+                return Err(KademliaError::ProtocolError(format!("ID {id} lacks the required proof of work")));
+            }
+            Some(id) => (id, builder.pow_nonce),
+            None => self.fresh_id(),
+        };
+        let mut node = Node::with_id(id, self.clock.clone(), &config);
+        node.origin = builder.origin.unwrap_or_default();
+        node.pow_nonce = pow_nonce;
+        Ok(self.register(node))
+    }
+
+    /// Whether `nonce` proves the work this network requires on `id` (always, if none)
+    fn has_required_pow(&self, id: &NodeId<N>, nonce: Option<u64>) -> bool {
+        self.config.required_pow().is_none_or(|difficulty| nonce.is_some_and(|nonce| id.verify_pow(nonce, difficulty)))
+    }
+
+    /// Create and register a node with a fresh ed25519 identity; its ID is the key's hash
+    pub fn add_identified_node(&mut self) -> NodeId<N> {
+        loop {
+            let identity = NodeIdentity::generate(&mut self.rng);
+            let mut node = Node::with_identity(identity, self.clock.clone(), &self.config);
+            if !self.nodes.contains_key(&node.id) {
+                node.pow_nonce = self.config.required_pow().map(|difficulty| node.id.mine_pow_nonce(difficulty));
+                return self.register(node);
+            }
+        }
+    }
+
+    /// Hand `to` a contact learned out of band (e.g. from a bootstrap list): `peer`,
+    /// claiming the public key `key`. The contact is only recorded if the ID matches the key.
+    pub fn introduce(&mut self, to: &NodeId<N>, peer: &NodeId<N>, key: &VerifyingKey) -> Result<(), KademliaError<N>> {
+        let (origin, pow_nonce) = self.nodes.get(peer).map_or((0, None), |p| (p.origin, p.pow_nonce));
+        let node = self.nodes.get_mut(to).ok_or(KademliaError::NodeNotFound(*to))?;
+        match node.track_peer(peer, Some(key), origin, pow_nonce) {
+            TrackOutcome::Rejected => {
+                Err(KademliaError::ProtocolError(format!("{peer} is not derived from its public key or lacks the required proof of work")))
+            }
+            TrackOutcome::BucketFull { evict_candidate } => {
+                self.ping_before_evict(to, &evict_candidate);
+                Ok(())
+            }
+            TrackOutcome::Refused => Err(KademliaError::ProtocolError(format!("{to} has no room left for {peer}"))),
+            TrackOutcome::Tracked => Ok(()),
+        }
+    }
+
+    /// Seed `to`'s routing table with every contact `from` knows, e.g. to warm up a new node
+    /// from a trusted neighbor. The copies are second-hand, so they join unverified.
+    pub fn clone_routing(&mut self, from: &NodeId<N>, to: &NodeId<N>) -> Result<MergeSummary, KademliaError<N>> {
+        let source = self.nodes.get(from).ok_or(KademliaError::NodeNotFound(*from))?;
+        let contacts: Vec<Contact<N>> = source.routing_table.contacts().cloned().collect();
+        let node = self.nodes.get_mut(to).ok_or(KademliaError::NodeNotFound(*to))?;
+        Ok(node.merge_contacts(&contacts))
+    }
+
+    /// `join` for callers that do not need the report
+    pub fn bootstrap_node(&mut self, new_id: NodeId<N>, known_peer: NodeId<N>) -> Result<(), KademliaError<N>> {
+        self.join(&new_id, &known_peer).map(|_| ())
+    }
+
+    /// Join `new_node` to the network through `bootstrap`: learn the peer, look up our own ID
+    /// to meet our neighbors, then look up a random ID in every bucket range (each of the
+    /// 2^b - 1 digits of each b-bit level) that starts short of the prefix shared with the
+    /// nearest neighbor found, so the table covers the whole keyspace and not just our
+    /// neighborhood
+    pub fn join(&mut self, new_node: &NodeId<N>, bootstrap: &NodeId<N>) -> Result<JoinReport, KademliaError<N>> {
+        let (new_id, known_peer) = (*new_node, *bootstrap);
+        let known_before = self.nodes.get(&new_id).map_or(0, |n| n.routing_table.len());
+        // contact the known peer so it learns about us (and vice versa)
+        self.ping(&new_id, &known_peer)?;
+        let peer_key = self.nodes.get(&known_peer).and_then(Node::public_key);
+        let (peer_origin, peer_nonce) = self.nodes.get(&known_peer).map_or((0, None), |p| (p.origin, p.pow_nonce));
+        if let Some(node) = self.nodes.get_mut(&new_id)
+            && let TrackOutcome::BucketFull { evict_candidate } =
+                node.track_peer(&known_peer, peer_key.as_ref(), peer_origin, peer_nonce)
+        {
+            self.ping_before_evict(&new_id, &evict_candidate);
+        }
+        // run a find_node towards our own ID to discover neighbors, asking for twice the usual
+        // number of contacts to fill the fresh table faster where responders' `max_count` allows
+        let neighbors = self.iterative_find_node_requesting(&new_id, &new_id, 2 * self.config.k);
+        // go by shared prefix length rather than by bucket, since our table has barely split yet
+        let nearest = neighbors
+            .iter()
+            .filter(|n| **n != new_id)
+            .map(|n| new_id.common_prefix_len(n))
+            .max()
+            .unwrap_or(0);
+        let Some(b) = self.nodes.get(&new_id).map(|n| n.routing_table.bits_per_bucket) else {
+            return Err(KademliaError::NodeNotFound(new_id));
+        };
+        // a digit's range starts at the first bit it sets, b - 1 - ilog2(digit) into its level
+        let ranges: Vec<(usize, usize)> = (0..nearest.div_ceil(b))
+            .flat_map(|level| (1..1usize << b).map(move |digit| (level, digit)))
+            .filter(|&(level, digit)| level * b + (b - 1 - digit.ilog2() as usize) < nearest)
+            .collect();
+        for &(level, digit) in &ranges {
+            let Some(node) = self.nodes.get(&new_id) else { break };
+            let target = node.routing_table.random_id_at(level, digit, &mut self.rng);
+            let _ = self.iterative_find_node(&new_id, &target);
+        }
+        let known_after = self.nodes.get(&new_id).map_or(0, |n| n.routing_table.len());
+        Ok(JoinReport {
+            contacts_learned: known_after.saturating_sub(known_before),
+            lookups: 1 + ranges.len(),
+        })
+    }
+
+    /// Add a new node and bootstrap it via a known peer. If the known peer cannot be
+    /// reached the new node is removed again and the error returned.
+    pub fn add_and_bootstrap(&mut self, known_peer: &NodeId<N>) -> Result<NodeId<N>, KademliaError<N>> {
+        let id = self.add_node();
+        if let Err(err) = self.bootstrap_node(id, *known_peer) {
+            self.nodes.remove(&id);
+            return Err(err);
+        }
+        // the join may have overflowed buckets: the newcomer's own, and those of the nodes it
+        // met that had no room for it; give whoever waits there a chance at dead slots
+        self.evict_stale_peers(&id);
+        let mut waiting: Vec<(NodeId<N>, usize)> = self
+            .nodes
+            .values()
+            .filter_map(|node| node.routing_table.waiting_in(&id).map(|index| (node.id, index)))
+            .collect();
+        waiting.sort();
+        for (owner, index) in waiting {
+            self.evict_stale_in(&owner, vec![index]);
+        }
+        Ok(id)
+    }
+
+    /// Run ping-before-evict on every bucket of `id` that has replacement candidates
+    /// waiting; returns how many stale peers were replaced
+    fn evict_stale_peers(&mut self, id: &NodeId<N>) -> usize {
+        let Some(node) = self.nodes.get(id) else {
+            return 0;
+        };
+        let pending: Vec<usize> = node
+            .routing_table
+            .buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| !b.replacements.is_empty())
+            .map(|(i, _)| i)
+            .collect();
+        self.evict_stale_in(id, pending)
+    }
+
+    /// Run ping-before-evict on buckets `pending` of `id`; returns how many contacts failed
+    /// to answer and were replaced
+    fn evict_stale_in(&mut self, id: &NodeId<N>, pending: Vec<usize>) -> usize {
+        let mut evicted = 0;
+        for index in pending {
+            let Some(node) = self.nodes.get(id) else { break };
+            if let Some(candidate) = node.routing_table.eviction_candidate(index)
+                && self.ping_before_evict(id, &candidate)
+            {
+                evicted += 1;
+            }
+        }
+        evicted
+    }
+
+    /// Run eviction checks for every node in the network
+    pub fn run_eviction_checks(&mut self) {
+        let ids: Vec<NodeId<N>> = self.nodes.keys().copied().collect();
+        for id in ids {
+            self.evict_stale_peers(&id);
+        }
+    }
+
+    /// Mark a node as dead (simulate failure)
+    pub fn kill_node(&mut self, id: &NodeId<N>) -> bool {
+        if let Some(node) = self.nodes.get_mut(id) {
+            node.alive = false;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Cut `group_a` off from `group_b`: RPCs between any node of one and any node of the
+    /// other fail with `Partitioned` (and count as missed answers) until `heal_partition`.
+    /// Each group keeps talking among itself; further calls add more cuts.
+    pub fn partition(&mut self, group_a: Vec<NodeId<N>>, group_b: Vec<NodeId<N>>) {
+        let blocked = self.partition.get_or_insert_with(HashSet::new);
+        for a in &group_a {
+            for b in &group_b {
+                blocked.insert((*a, *b));
+                blocked.insert((*b, *a));
+            }
+        }
+    }
+
+    /// Lift every partition, so all nodes can reach each other again
+    pub fn heal_partition(&mut self) {
+        self.partition = None;
+    }
+
+    /// Gracefully take a node out of the network: it leaves the simulation, every other node
+    /// forgets it and its publications stop being republished
+    pub fn remove_node(&mut self, id: &NodeId<N>) -> Option<Node<N>> {
+        let node = self.nodes.remove(id)?;
+        self.evict_peer_from_all(id);
+        self.publishers.remove(id);
+        Some(node)
+    }
+
+    /// Crash a node: it disappears without notice, so peers only find out when their RPCs
+    /// to it fail with `NodeNotFound`
+    pub fn fail_node(&mut self, id: &NodeId<N>) -> Option<Node<N>> {
+        self.nodes.remove(id)
+    }
+
+    /// Crash a random `fraction` (0.0 to 1.0) of the nodes; returns the IDs that failed
+    pub fn simulate_churn(&mut self, fraction: f64, rng: &mut impl Rng) -> Vec<NodeId<N>> {
+        // sort first so a seeded RNG picks the same victims regardless of HashMap order
+        let mut ids: Vec<NodeId<N>> = self.nodes.keys().copied().collect();
+        ids.sort();
+        let count = (ids.len() as f64 * fraction.clamp(0.0, 1.0)).round() as usize;
+        let failed: Vec<NodeId<N>> = ids.choose_multiple(rng, count).copied().collect();
+        for id in &failed {
+            self.fail_node(id);
+        }
+        failed
+    }
+
+    /// Combine every node's density report to spot sparse regions of the keyspace
+    pub fn density_report(&self) -> NetworkDensityReport<N> {
+        let mut total = NetworkDensityReport {
+            nodes: self.nodes.len(),
+            peers_per_bucket: vec![0; NodeId::<N>::BITS + 1],
+            closest: None,
+            sparsest: None,
+        };
+        for node in self.nodes.values() {
+            let report = node.density_report();
+            for (sum, count) in total.peers_per_bucket.iter_mut().zip(&report.peers_per_bucket) {
+                *sum += count;
+            }
+            if let Some(d) = report.closest {
+                total.closest = Some(total.closest.map_or(d, |c| c.min(d)));
+                total.sparsest = Some(total.sparsest.map_or(d, |s| s.max(d)));
+            }
+        }
+        total
+    }
+
+    /// Routing stats of every node, summarized: contacts per node (min/max/mean), stale
+    /// contacts, and nodes that know no one in their own neighborhood
+    pub fn routing_stats_all(&self) -> NetworkRoutingStats {
+        let all: Vec<RoutingStats<N>> = self.nodes.values().map(Node::routing_stats).collect();
+        let contacts = || all.iter().map(|stats| stats.total_contacts);
+        NetworkRoutingStats {
+            nodes: all.len(),
+            min_contacts: contacts().min().unwrap_or(0),
+            max_contacts: contacts().max().unwrap_or(0),
+            mean_contacts: if all.is_empty() { 0.0 } else { contacts().sum::<usize>() as f64 / all.len() as f64 },
+            stale_contacts: all.iter().map(|stats| stats.stale_contacts).sum(),
+            without_neighbors: all.iter().filter(|stats| !stats.has_neighbors()).count(),
+        }
+    }
+
+    /// Every node's routing table in one Graphviz digraph: an edge from each node to each of
+    /// its contacts, labeled with the bucket index. Stale contacts are dashed, dead nodes gray.
+    pub fn topology_to_dot(&self) -> String {
+        let mut nodes: Vec<&Node<N>> = self.nodes.values().collect();
+        nodes.sort_by_key(|node| node.id);
+        let mut lines = vec!["digraph topology {".to_string()];
+        for node in &nodes {
+            lines.push(dot_node(&node.id, if node.alive { "" } else { "style=filled, fillcolor=gray" }));
+        }
+        for node in &nodes {
+            lines.extend(node.dot_edges());
+        }
+        lines.push("}".to_string());
+        lines.join("\n")
+    }
+
+    /// Every node and the peers each knows, e.g. to draw with `TopologySnapshot::to_dot`
+    pub fn export_topology(&self) -> TopologySnapshot<N> {
+        let mut node_ids: Vec<NodeId<N>> = self.nodes.keys().copied().collect();
+        node_ids.sort();
+        let edges = node_ids
+            .iter()
+            .flat_map(|id| self.nodes[id].routing_table.peers().map(move |peer| (*id, *peer)))
+            .collect();
+        TopologySnapshot { node_ids, edges }
+    }
+
+    /// Approximate bytes all routing tables take up together (`RoutingTable::approx_bytes`)
+    pub fn routing_bytes(&self) -> usize {
+        self.nodes.values().map(|node| node.routing_table.approx_bytes()).sum()
+    }
+
+    /// Live entries stored across all nodes (replicas counted separately)
+    pub fn total_stored_entries(&self) -> usize {
+        self.nodes.values().map(Node::storage_size).sum()
+    }
+
+    /// Every node, dead or alive, holding a live copy of `key`, closest to the key ID first
+    pub fn find_all_holders(&self, key: &[u8]) -> Vec<NodeId<N>> {
+        let key_id = self.key_to_id(key);
+        let mut holders: Vec<NodeId<N>> = self
+            .nodes
+            .values()
+            .filter(|node| node.storage_keys().any(|k| *k == key_id))
+            .map(|node| node.id)
+            .collect();
+        distance::sort_by_distance(&key_id, &mut holders);
+        holders
+    }
+
+    /// The live node responsible for `key`: the one closest to its ID, found by checking
+    /// every node rather than by a lookup
+    pub fn closest_node_to_key(&self, key: &[u8]) -> Option<NodeId<N>> {
+        let key_id = self.key_to_id(key);
+        self.nodes.values().filter(|node| node.alive).map(|node| node.id).min_by_key(|id| key_id.xor_distance(id))
+    }
+
+    /// The `k` live nodes closest to `key`'s ID, closest first: where `iterative_store`
+    /// should put it, by ground truth rather than by a lookup
+    pub fn top_k_closest_to_key(&self, key: &[u8], k: usize) -> Vec<NodeId<N>> {
+        let key_id = self.key_to_id(key);
+        let live: Vec<NodeId<N>> = self.nodes.values().filter(|node| node.alive).map(|node| node.id).collect();
+        distance::k_closest(&key_id, &live, k)
+    }
+
+    /// Remove a peer from every node's peer list
+    fn evict_peer_from_all(&mut self, peer: &NodeId<N>) {
+        for node in self.nodes.values_mut() {
+            node.remove_peer(peer);
+        }
+    }
+
+    /// React to a failed RPC from `from` to `to`. A peer that timed out may come back, so
+    /// `from` only counts the failure against its contact; one that is gone (or misbehaved)
+    /// is evicted everywhere.
+    fn note_failure(&mut self, from: &NodeId<N>, to: &NodeId<N>, err: &KademliaError<N>) {
+        if matches!(err, KademliaError::Timeout(_) | KademliaError::Partitioned(_)) {
+            if let Some(node) = self.nodes.get_mut(from) {
+                node.routing_table.record_failure(to);
+            }
+        } else {
+            self.evict_peer_from_all(to);
+        }
+    }
+
+    /// Compute a key's ID with this network's hasher
+    pub fn key_to_id(&self, key: &[u8]) -> NodeId<N> {
+        self.hasher.hash_key(key)
+    }
+
+    /// Compute the ID `key` would have in a network using `func`
+    pub fn key_to_id_with(key: &[u8], func: HashFunction) -> NodeId<N> {
+        func.hash_key(key)
+    }
+
+    /// Snapshot known peers of a node across all buckets (to avoid borrow issues during iteration)
+    fn snapshot_peers(&self, id: &NodeId<N>) -> Vec<NodeId<N>> {
+        self.nodes
+            .get(id)
+            .map(|n| n.routing_table.peers().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Tell `id` about peers a lookup of its learned of second-hand (`Node::record_contact`)
+    fn record_contacts(&mut self, id: &NodeId<N>, peers: &[NodeId<N>]) {
+        if let Some(node) = self.nodes.get_mut(id) {
+            for peer in peers {
+                node.record_contact(peer);
+            }
+        }
+    }
+
+    /// Return up to k closest nodes from `candidates` to `target` (by XOR)
+    pub fn closest_k(&self, target: &NodeId<N>, candidates: &[NodeId<N>]) -> Vec<NodeId<N>> {
+        distance::k_closest(target, candidates, self.config.k)
+    }
+
+    /// Deliver one RPC from `from` to `to`, failing if either end is unknown or `to` is dead.
+    /// The round trip takes a latency drawn from the config's model: it is kept in
+    /// `last_latency` for lookups to account for, and as the sender's RTT for `to`.
+    fn deliver<T>(
+        &mut self,
+        from: &NodeId<N>,
+        to: &NodeId<N>,
+        rpc: impl FnOnce(&mut Node<N>) -> T,
+    ) -> Result<T, KademliaError<N>> {
+        let sender = self.nodes.get(from).ok_or_else(|| KademliaError::ProtocolError(format!("unknown sender {from}")))?;
+        let (sender_key, sender_origin, sender_nonce) = (sender.public_key(), sender.origin, sender.pow_nonce);
+        if !self.nodes.contains_key(to) {
+            return Err(KademliaError::NodeNotFound(*to));
+        }
+        if self.partition.as_ref().is_some_and(|blocked| blocked.contains(&(*from, *to))) {
+            return Err(KademliaError::Partitioned(*to));
+        }
+        let latency = match self.config.latency_model {
+            LatencyModel::Zero => Duration::ZERO,
+            model => model.sample(&mut self.transport_rng),
+        };
+        self.last_latency = latency;
+        let loss_rate = self.config.packet_loss_rate;
+        if loss_rate > 0.0 && self.transport_rng.gen_bool(loss_rate) {
+            return Err(KademliaError::Timeout(*to));
+        }
+        let target = self.nodes.get_mut(to).ok_or(KademliaError::NodeNotFound(*to))?;
+        if !target.alive { return Err(KademliaError::Timeout(*to)); }
+        // every RPC introduces its sender, who must own the ID it claims
+        let mut full_buckets = Vec::new();
+        match target.track_peer(from, sender_key.as_ref(), sender_origin, sender_nonce) {
+            TrackOutcome::Rejected => {
+                return Err(KademliaError::ProtocolError(format!("sender {from} does not own its ID or lacks the required proof of work")));
+            }
+            TrackOutcome::BucketFull { evict_candidate } => full_buckets.push((*to, evict_candidate)),
+            // a target with no room for the sender still answers it
+            TrackOutcome::Tracked | TrackOutcome::Refused => {}
+        }
+        let reply = rpc(target);
+        let (target_key, target_origin, target_nonce) = (target.public_key(), target.origin, target.pow_nonce);
+        // the reply shows the target is alive, so the sender learns (or refreshes) it in turn
+        if let Some(sender) = self.nodes.get_mut(from) {
+            match sender.track_peer(to, target_key.as_ref(), target_origin, target_nonce) {
+                TrackOutcome::Rejected => {
+                    return Err(KademliaError::ProtocolError(format!("{to} does not own its ID or lacks the required proof of work")));
+                }
+                TrackOutcome::BucketFull { evict_candidate } => full_buckets.push((*from, evict_candidate)),
+                TrackOutcome::Tracked | TrackOutcome::Refused => {}
+            }
+            sender.routing_table.record_rtt(to, latency);
+        }
+        for (owner, evict_candidate) in full_buckets {
+            self.ping_before_evict(&owner, &evict_candidate);
+        }
+        self.last_latency = latency;
+        Ok(reply)
+    }
+
+    /// A newcomer waits for a slot in one of `owner`'s full buckets: ping `candidate`, the
+    /// contact the bucket's `EvictionPolicy` names, and evict it (letting the freshest
+    /// newcomer in) only if it does not answer, since long-lived peers are the likeliest to
+    /// stay. Every eviction check, whether an RPC just overflowed the bucket or
+    /// `run_eviction_checks` sweeps it later, comes through here. Pings sent for this do not
+    /// start further checks of their own; newcomers they turn up wait for the next sweep.
+    /// Returns whether `candidate` was evicted.
+    fn ping_before_evict(&mut self, owner: &NodeId<N>, candidate: &NodeId<N>) -> bool {
+        if self.evicting {
+            return false;
+        }
+        self.evicting = true;
+        let answered = self.eviction_ping(owner, candidate) == Ok(true);
+        self.evicting = false;
+        !answered && self.nodes.get_mut(owner).is_some_and(|node| node.routing_table.evict(candidate))
+    }
+
+    /// RPCs forwarded so far
+    pub fn stats(&self) -> &NetworkStats {
+        &self.stats
+    }
+
+    /// Start counting RPCs from zero again
+    pub fn reset_stats(&mut self) {
+        self.stats = NetworkStats::default();
+    }
+
+    /// RPC forwarding: ping from one node to another
+    pub fn ping(&mut self, from: &NodeId<N>, to: &NodeId<N>) -> Result<bool, KademliaError<N>> {
+        self.stats.total_pings += 1;
+        self.send_ping(from, to)
+    }
+
+    /// A ping checking whether a full bucket's least recently seen contact is still up
+    /// before it gives up its slot; counted in `eviction_pings`, not `total_pings`
+    fn eviction_ping(&mut self, from: &NodeId<N>, to: &NodeId<N>) -> Result<bool, KademliaError<N>> {
+        self.stats.eviction_pings += 1;
+        self.send_ping(from, to)
+    }
+
+    fn send_ping(&mut self, from: &NodeId<N>, to: &NodeId<N>) -> Result<bool, KademliaError<N>> {
+        let result = self.deliver(from, to, |node| node.rpc_ping(from));
+        #[cfg(feature = "tracing")]
+        tracing::debug!(from = %from, to = %to, result = ?result, "ping");
+        result
+    }
+
+    /// `ping` over a link that also drops `loss_rate` of packets (on top of the network's own
+    /// `packet_loss_rate`); a dropped ping times out like one to a dead node
+    pub fn ping_with_loss(&mut self, from: &NodeId<N>, to: &NodeId<N>, loss_rate: f64) -> Result<bool, KademliaError<N>> {
+        if self.transport_rng.gen_bool(loss_rate.clamp(0.0, 1.0)) {
+            self.stats.total_pings += 1;
+            return Err(KademliaError::Timeout(*to));
+        }
+        self.ping(from, to)
+    }
+
+    /// RPC forwarding: store a value under a key ID on a target node for `ttl`
+    pub fn store(
+        &mut self,
+        from: &NodeId<N>,
+        to: &NodeId<N>,
+        key: NodeId<N>,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<(), KademliaError<N>> {
+        self.stats.total_store_rpcs += 1;
+        let result = self.deliver(from, to, |node| node.rpc_store(from, key, value, ttl)).and_then(|stored| stored);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(from = %from, to = %to, key = %key, result = ?result, "store");
+        result
+    }
+
+    /// RPC forwarding: store several byte-keyed entries on a target node in one RPC; returns
+    /// how many entries the target accepted
+    pub fn store_batch(
+        &mut self,
+        from: &NodeId<N>,
+        to: &NodeId<N>,
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<usize, KademliaError<N>> {
+        self.stats.total_store_rpcs += 1;
+        let result = self.deliver(from, to, |node| node.rpc_store_batch(from, entries));
+        #[cfg(feature = "tracing")]
+        tracing::debug!(from = %from, to = %to, result = ?result, "store_batch");
+        result
+    }
+
+    /// RPC forwarding: find_value for a key ID on a target node
+    pub fn find_value(&mut self, from: &NodeId<N>, to: &NodeId<N>, key: &NodeId<N>) -> Result<Vec<u8>, KademliaError<N>> {
+        self.stats.total_find_value_rpcs += 1;
+        let result = self
+            .deliver(from, to, |node| node.rpc_find_value(from, key))
+            .and_then(|value| value.ok_or(KademliaError::KeyNotFound));
+        match &result {
+            Ok(_) => self.stats.total_value_hits += 1,
+            Err(KademliaError::KeyNotFound) => self.stats.total_value_misses += 1,
+            Err(_) => {}
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(from = %from, to = %to, key = %key, result = ?result.as_ref().map(Vec::len), "find_value");
+        result
+    }
+
+    /// RPC forwarding: find_node on a target node, asking for up to `count` nodes
+    pub fn find_node(
+        &mut self,
+        from: &NodeId<N>,
+        to: &NodeId<N>,
+        target_id: &NodeId<N>,
+        count: usize,
+    ) -> Result<Vec<NodeId<N>>, KademliaError<N>> {
+        self.stats.total_find_node_rpcs += 1;
+        let result = self.deliver(from, to, |node| node.rpc_find_node(from, target_id, count));
+        #[cfg(feature = "tracing")]
+        tracing::debug!(from = %from, to = %to, target = %target_id, returned = ?result.as_ref().map(Vec::len), "find_node");
+        result
+    }
+
+    /// Iterative find_node: start from `start`, walk the network to find k closest to `target`
+    /// (done once each of the k closest found so far has been queried, or after MAX_STEPS rounds)
+    pub fn iterative_find_node(&mut self, start: &NodeId<N>, target: &NodeId<N>) -> Vec<NodeId<N>> {
+        self.traced_iterative_find_node(start, target).0
+    }
+
+    /// `iterative_find_node` that also records each query it made, for replaying a lookup.
+    /// A lookup for an existing node's ID ends after the round in which that node answers.
+    pub fn traced_iterative_find_node(&mut self, start: &NodeId<N>, target: &NodeId<N>) -> (Vec<NodeId<N>>, QueryTrace<N>) {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("iterative_find_node", start = %start, target = %target).entered();
+        Router::new(self.config).traced_iterative_find_node(self, *start, *target)
+    }
+
+    /// `iterative_find_node` asking each node for `count` contacts rather than k; the lookup
+    /// still returns the k closest
+    pub fn iterative_find_node_requesting(&mut self, start: &NodeId<N>, target: &NodeId<N>, count: usize) -> Vec<NodeId<N>> {
+        Router::new(self.config).requesting(count).iterative_find_node(self, *start, *target)
+    }
+
+    /// Iterative find_value for a byte key, hashed to its ID with this network's hasher
+    pub fn iterative_find_value(&mut self, start: &NodeId<N>, key: &[u8]) -> Result<Vec<u8>, KademliaError<N>> {
+        let key_id = self.key_to_id(key);
+        self.find_value_by_id(start, &key_id)
+    }
+
+    /// Every node among the `k` closest to `key` that answers find_value with a copy, closest
+    /// first. Unlike `iterative_find_value` the lookup does not stop at the first copy: it
+    /// finds the closest nodes, then asks each of them in one parallel round. Unlike
+    /// `find_all_holders` it only learns what `start` could learn through RPCs.
+    pub fn find_all_value_holders(&mut self, start: &NodeId<N>, key: &[u8]) -> Vec<NodeId<N>> {
+        let key_id = self.key_to_id(key);
+        let mut slowest = Duration::ZERO;
+        let mut holders = Vec::new();
+        for target in self.iterative_find_node(start, &key_id) {
+            let result = self.find_value(start, &target, &key_id);
+            slowest = slowest.max(self.last_latency);
+            match result {
+                Ok(_) => holders.push(target),
+                Err(KademliaError::KeyNotFound) => {}
+                Err(err) => self.note_failure(start, &target, &err),
+            }
+        }
+        self.clock.advance(slowest);
+        holders
+    }
+
+    /// How many of the `k` closest nodes to `key` hold a copy, as seen from `start`
+    pub fn replication_count(&mut self, start: &NodeId<N>, key: &[u8]) -> usize {
+        self.find_all_value_holders(start, key).len()
+    }
+
+    /// Iterative find_value for a key ID the caller already has (e.g. a content hash):
+    /// like find_node but stop if a value is found (`KeyNotFound` once the lookup runs out
+    /// of closer nodes to ask). A found value is also cached for CACHE_TTL at the closest
+    /// queried node that did not have it, as the paper suggests for popular keys.
+    pub fn find_value_by_id(&mut self, start: &NodeId<N>, key_id: &NodeId<N>) -> Result<Vec<u8>, KademliaError<N>> {
+        self.find_value_with_stats(start, key_id).0
+    }
+
+    /// Iterative find_value for a byte key that also reports what the lookup cost
+    pub fn verbose_iterative_find_value(&mut self, start: &NodeId<N>, key: &[u8]) -> (Option<Vec<u8>>, QueryStats) {
+        let key_id = self.key_to_id(key);
+        let (result, stats) = self.find_value_with_stats(start, &key_id);
+        (result.ok(), stats)
+    }
+
+    /// The lookup behind `find_value_by_id`, counting its rounds and the nodes it asked
+    pub fn find_value_with_stats(
+        &mut self,
+        start: &NodeId<N>,
+        key_id: &NodeId<N>,
+    ) -> (Result<Vec<u8>, KademliaError<N>>, QueryStats) {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("iterative_find_value", start = %start, key = %key_id).entered();
+        let mut stats = QueryStats::default();
+        let mut closest_non_holder: Option<NodeId<N>> = None;
+        let mut queried: HashSet<NodeId<N>> = HashSet::new();
+        let mut previously_closest: Vec<NodeId<N>> = Vec::new();
+        let mut shortlist = ClosestQueue::new(*key_id, self.config.k);
+        let mut seen: HashSet<NodeId<N>> = HashSet::new();
+        for peer in self.snapshot_peers(start).into_iter().chain([*start]) {
+            if seen.insert(peer) {
+                shortlist.push(peer);
+            }
+        }
+
+        for _step in 0..self.config.max_steps {
+            let batch = shortlist.next_round(&queried, &mut previously_closest, self.config.alpha);
+            if batch.is_empty() { break; }
+            stats.hops += 1;
+
+            // as in find_node, the round lasts as long as its slowest query
+            let mut slowest = Duration::ZERO;
+            for n in batch {
+                queried.insert(n);
+                stats.nodes_queried += 1;
+                let result = self.find_value(start, &n, key_id);
+                let mut elapsed = self.last_latency; // this node's find_value, then find_node
+                match result {
+                    Ok(value) => {
+                        self.clock.advance(elapsed);
+                        if let Some(cache) = closest_non_holder {
+                            let _ = self.store(start, &cache, *key_id, value.clone(), CACHE_TTL);
+                        }
+                        return (Ok(value), stats);
+                    }
+                    Err(KademliaError::KeyNotFound) => {
+                        let closer = closest_non_holder
+                            .is_none_or(|c| key_id.xor_distance(&n) < key_id.xor_distance(&c));
+                        if n != *start && closer {
+                            closest_non_holder = Some(n);
+                        }
+                    }
+                    Err(err) => {
+                        // Unreachable peers are dropped from this lookup
+                        self.note_failure(start, &n, &err);
+                        shortlist.remove(&n);
+                        slowest = slowest.max(elapsed);
+                        continue;
+                    }
+                }
+                let neighbors = self.find_node(start, &n, key_id, self.config.k);
+                elapsed += self.last_latency;
+                slowest = slowest.max(elapsed);
+                if let Ok(neighbors) = neighbors {
+                    let neighbors = self.config.find_node.sanitize(start, &n, neighbors, self.config.k);
+                    self.record_contacts(start, &neighbors);
+                    for m in neighbors {
+                        if seen.insert(m) {
+                            shortlist.push(m);
+                        }
+                    }
+                }
+            }
+            self.clock.advance(slowest);
+        }
+        (Err(KademliaError::KeyNotFound), stats)
+    }
+
+    /// Iterative store of a byte key, hashed to its ID with this network's hasher
+    pub fn iterative_store(
+        &mut self,
+        start: &NodeId<N>,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Vec<Result<(), KademliaError<N>>> {
+        let key_id = self.key_to_id(&key);
+        self.store_at_id(start, key_id, value, ttl)
+    }
+
+    /// Iterative store under a key ID the caller already has: route to the k closest nodes
+    /// to `key_id` and store there for `ttl`. Returns one result per target so callers can
+    /// see which of them rejected the store.
+    pub fn store_at_id(
+        &mut self,
+        start: &NodeId<N>,
+        key_id: NodeId<N>,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Vec<Result<(), KademliaError<N>>> {
+        self.store_on_closest(start, &key_id, |network, target| {
+            network.store(start, target, key_id, value.clone(), ttl)
+        })
+        .into_iter()
+        .map(|(_, result)| result)
+        .collect()
+    }
+
+    /// Iterative store of many byte keys at once, for DEFAULT_TTL. Keys close together share
+    /// their closest nodes, so the keys are taken in ID order and each lookup's answer is
+    /// kept in `KnownSubtrees`. A key looks up its own k closest only when no known subtree
+    /// with k nodes holds it. When the next key lies near that one, further lookups fill in
+    /// the subtree around the next key until it holds k nodes, so the keys after it need no
+    /// lookups of their own. Each node then gets all its entries in one `store_batch`, sent
+    /// together in a single round. Returns how many copies the nodes accepted.
+    pub fn iterative_store_batch(&mut self, start: &NodeId<N>, entries: Vec<(Vec<u8>, Vec<u8>)>) -> usize {
+        let k = self.config.k;
+        let mut keyed: Vec<(NodeId<N>, Vec<u8>, Vec<u8>)> =
+            entries.into_iter().map(|(key, value)| (self.key_to_id(&key), key, value)).collect();
+        keyed.sort_by_key(|(key_id, _, _)| *key_id);
+        let mut known = KnownSubtrees::default();
+        let mut by_target: HashMap<NodeId<N>, Vec<_>> = HashMap::new();
+        for (i, (key_id, key, value)) in keyed.iter().enumerate() {
+            let closest = known.k_closest(key_id, k).unwrap_or_else(|| {
+                let closest = self.iterative_find_node(start, key_id);
+                let len = known.learn(key_id, &closest, k);
+                if let Some((next, _, _)) = keyed.get(i + 1)
+                    && key_id.common_prefix_len(next) + 1 >= len
+                {
+                    while let Some(gap) = known.gap(next, k) {
+                        let found = self.iterative_find_node(start, &gap);
+                        known.learn(&gap, &found, k);
+                    }
+                }
+                closest
+            });
+            for target in closest {
+                by_target.entry(target).or_default().push((key.clone(), value.clone()));
+            }
+        }
+        let mut targets: Vec<NodeId<N>> = by_target.keys().copied().collect();
+        targets.sort();
+        let mut slowest = Duration::ZERO;
+        let mut delivered = 0;
+        for target in targets {
+            let batch = by_target.remove(&target).unwrap_or_default();
+            let result = self.store_batch(start, &target, batch);
+            slowest = slowest.max(self.last_latency);
+            match result {
+                Ok(accepted) => delivered += accepted,
+                Err(err) => self.note_failure(start, &target, &err),
+            }
+        }
+        self.clock.advance(slowest);
+        delivered
+    }
+
+    /// Iterative store of a byte key for DEFAULT_TTL that reports which nodes accepted it
+    pub fn iterative_store_confirmed(&mut self, start: &NodeId<N>, key: Vec<u8>, value: Vec<u8>) -> Vec<NodeId<N>> {
+        let key_id = self.key_to_id(&key);
+        self.store_on_closest(start, &key_id, |network, target| {
+            network.store(start, target, key_id, value.clone(), DEFAULT_TTL)
+        })
+        .into_iter()
+        .filter_map(|(target, result)| result.ok().map(|()| target))
+        .collect()
+    }
+
+    /// `iterative_store_confirmed` that fails with `InsufficientReplicas` unless at least
+    /// `min_replicas` nodes accepted the store (those that did keep it either way)
+    pub fn iterative_store_with_replication(
+        &mut self,
+        start: &NodeId<N>,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        min_replicas: usize,
+    ) -> Result<Vec<NodeId<N>>, KademliaError<N>> {
+        let stored = self.iterative_store_confirmed(start, key, value);
+        if stored.len() < min_replicas {
+            return Err(KademliaError::InsufficientReplicas {
+                stored: stored.len(),
+                required: min_replicas,
+            });
+        }
+        Ok(stored)
+    }
+
+    /// Route to the k closest nodes to `key_id` and send each of them the store `rpc`,
+    /// returning each target with its result. A lookup never returns its own start, so when
+    /// `start` is responsible for the key (`Node::is_responsible_for`) it keeps one of the k
+    /// copies itself, in place of the farthest node found.
+    pub fn store_on_closest(
+        &mut self,
+        start: &NodeId<N>,
+        key_id: &NodeId<N>,
+        mut rpc: impl FnMut(&mut Self, &NodeId<N>) -> Result<(), KademliaError<N>>,
+    ) -> Vec<(NodeId<N>, Result<(), KademliaError<N>>)> {
+        let mut closest = self.iterative_find_node(start, key_id);
+        if self.nodes.get(start).is_some_and(|node| node.is_responsible_for(key_id)) && !closest.contains(start) {
+            closest.push(*start);
+            closest = self.closest_k(key_id, &closest);
+        }
+        // the stores go out together, so they take as long as the slowest
+        let mut slowest = Duration::ZERO;
+        let results = closest
+            .into_iter()
+            .map(|target| {
+                let result = rpc(self, &target);
+                slowest = slowest.max(self.last_latency);
+                if let Err(
+                    err @ (KademliaError::Timeout(_) | KademliaError::Partitioned(_) | KademliaError::NodeNotFound(_)),
+                ) = &result
+                {
+                    self.note_failure(start, &target, err);
+                }
+                (target, result)
+            })
+            .collect();
+        self.clock.advance(slowest);
+        results
+    }
+
+    /// RPC forwarding: store a signed mutable value on a target node for `ttl`
+    pub fn store_mutable(
+        &mut self,
+        from: &NodeId<N>,
+        to: &NodeId<N>,
+        mv: MutableValue,
+        ttl: Duration,
+    ) -> Result<(), KademliaError<N>> {
+        self.stats.total_store_rpcs += 1;
+        self.deliver(from, to, |node| node.rpc_store_mutable(from, mv, ttl))?
+    }
+
+    /// Iterative store of a mutable value: route to the k closest nodes to its key ID (the
+    /// SHA-1 of its public key) and store it there for `ttl`. Each target checks the signature
+    /// and sequence number itself, so the results show which of them refused it and why.
+    pub fn iterative_store_mutable(
+        &mut self,
+        start: &NodeId<N>,
+        mv: MutableValue,
+        ttl: Duration,
+    ) -> Vec<Result<(), KademliaError<N>>> {
+        let key_id = mv.key_id();
+        self.store_on_closest(start, &key_id, |network, target| {
+            network.store_mutable(start, target, mv.clone(), ttl)
+        })
+        .into_iter()
+        .map(|(_, result)| result)
+        .collect()
+    }
+
+    /// RPC forwarding: store an immutable value under its hash `key` on a target node
+    pub fn store_immutable(
+        &mut self,
+        from: &NodeId<N>,
+        to: &NodeId<N>,
+        key: NodeId<N>,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<(), KademliaError<N>> {
+        self.stats.total_store_rpcs += 1;
+        self.deliver(from, to, |node| node.rpc_store_immutable(from, key, value, ttl))?
+    }
+
+    /// Iterative store of an immutable, content-addressed value: its key is its SHA-1 hash,
+    /// which every target checks before storing it for DEFAULT_TTL. Returns the key.
+    pub fn iterative_store_immutable(&mut self, start: &NodeId<N>, value: Vec<u8>) -> NodeId<N> {
+        let key_id = NodeId::for_content(&value);
+        self.store_on_closest(start, &key_id, |network, target| {
+            network.store_immutable(start, target, key_id, value.clone(), DEFAULT_TTL)
+        });
+        key_id
+    }
+
+    /// Find an immutable value by its key: ask the k closest nodes to the key one at a time,
+    /// closest first, skipping any answer that does not hash to the key, and return the first
+    /// that does
+    pub fn iterative_find_immutable_value(&mut self, start: &NodeId<N>, key_id: &NodeId<N>) -> Option<Vec<u8>> {
+        let closest = self.iterative_find_node(start, key_id);
+        closest
+            .iter()
+            .filter_map(|holder| self.find_value(start, holder, key_id).ok())
+            .find(|value| NodeId::for_content(value) == *key_id)
+    }
+
+    /// Find the newest validly signed version of the mutable value owned by `public_key`
+    /// among the k closest nodes to its key ID. Unlike `iterative_find_value` this asks all
+    /// of them, since a node that missed an update still holds an older version.
+    pub fn iterative_find_mutable_value(
+        &mut self,
+        start: &NodeId<N>,
+        public_key: &[u8; 32],
+    ) -> Result<MutableValue, KademliaError<N>> {
+        let key_id = NodeId::from_public_key(public_key);
+        let closest = self.iterative_find_node(start, &key_id);
+        closest
+            .iter()
+            .filter_map(|holder| self.find_value(start, holder, &key_id).ok())
+            .filter_map(|bytes| MutableValue::stored_under(&key_id, &bytes))
+            .max_by_key(|mv| mv.sequence_number)
+            .ok_or(KademliaError::KeyNotFound)
+    }
+
+    /// Store a value directly on every node in the network (dead ones time out), e.g. for
+    /// configuration or well-known bootstrap data; returns how many nodes stored it
+    pub fn flood_store(&mut self, start: &NodeId<N>, key: Vec<u8>, value: Vec<u8>) -> usize {
+        let key_id = self.key_to_id(&key);
+        let mut targets: Vec<NodeId<N>> = self.nodes.keys().copied().collect();
+        targets.sort();
+        targets
+            .iter()
+            .filter(|target| self.store(start, target, key_id, value.clone(), DEFAULT_TTL).is_ok())
+            .count()
+    }
+
+    /// Start an epidemic broadcast of a value: `start` stores it and sends it to `fanout`
+    /// random peers from its routing table. On every later `tick` each holder passes it on to
+    /// `fanout` more of its peers it has not told yet, until every holder has told all its
+    /// peers, so it reaches everyone in about log(n) rounds without flooding from one node.
+    pub fn gossip_store(&mut self, start: &NodeId<N>, key: Vec<u8>, value: Vec<u8>, fanout: usize) {
+        let key_id = self.key_to_id(&key);
+        let Some(origin) = self.nodes.get_mut(start).filter(|node| node.alive) else {
+            return;
+        };
+        if origin.rpc_store(start, key_id, value.clone(), DEFAULT_TTL).is_err() {
+            return;
+        }
+        let mut rumor = Rumor {
+            key: key_id,
+            value,
+            fanout,
+            told: HashMap::from([(*start, HashSet::new())]),
+        };
+        if self.spread(&mut rumor) {
+            self.rumors.push(rumor);
+        }
+    }
+
+    /// One round of gossip for every value still spreading; returns how many nodes newly
+    /// received a value
+    pub fn gossip_round(&mut self) -> usize {
+        let mut rumors = std::mem::take(&mut self.rumors);
+        let before: usize = rumors.iter().map(|rumor| rumor.told.len()).sum();
+        rumors.retain_mut(|rumor| self.spread(rumor));
+        let after: usize = rumors.iter().map(|rumor| rumor.told.len()).sum();
+        self.rumors.append(&mut rumors);
+        after.saturating_sub(before)
+    }
+
+    /// Have every current holder of `rumor` send it to up to `fanout` random peers it has not
+    /// told yet (those reached now pass it on from the next round); returns whether any holder
+    /// still has peers left to tell
+    fn spread(&mut self, rumor: &mut Rumor<N>) -> bool {
+        let mut holders: Vec<NodeId<N>> = rumor.told.keys().copied().collect();
+        holders.sort();
+        let mut untold_left = false;
+        for holder in holders {
+            if !self.nodes.get(&holder).is_some_and(|node| node.alive) {
+                continue;
+            }
+            let told = &rumor.told[&holder];
+            let untold: Vec<NodeId<N>> =
+                self.snapshot_peers(&holder).into_iter().filter(|peer| !told.contains(peer)).collect();
+            let targets: Vec<NodeId<N>> = untold.choose_multiple(&mut self.transport_rng, rumor.fanout).copied().collect();
+            untold_left |= untold.len() > targets.len();
+            for target in targets {
+                rumor.told.get_mut(&holder).expect("holder is tracked").insert(target);
+                let stored = self.store(&holder, &target, rumor.key, rumor.value.clone(), DEFAULT_TTL).is_ok();
+                if stored && !rumor.told.contains_key(&target) {
+                    rumor.told.insert(target, HashSet::new());
+                    untold_left = true;
+                }
+            }
+        }
+        untold_left
+    }
+
+    /// Publish a value from `origin` now and keep it registered for republication
+    pub fn register_publisher(&mut self, origin: NodeId<N>, key: Vec<u8>, value: Vec<u8>, ttl: Duration) {
+        self.iterative_store(&origin, key.clone(), value.clone(), ttl);
+        let published = PublishedValue {
+            key,
+            value,
+            ttl,
+            last_published: self.now(),
+        };
+        let values = self.publishers.entry(origin).or_default();
+        values.retain(|p| p.key != published.key);
+        values.push(published);
+    }
+
+    /// Live origins holding at least one publication past half its TTL
+    pub fn republish_due(&self, now: Instant) -> Vec<NodeId<N>> {
+        self.publishers
+            .iter()
+            .filter(|(origin, _)| self.nodes.get(origin).is_some_and(|n| n.alive))
+            .filter(|(_, values)| values.iter().any(|p| p.is_due(now)))
+            .map(|(origin, _)| *origin)
+            .collect()
+    }
+
+    /// Re-store `origin`'s publication of `key` now, due or not; returns its TTL, or None if
+    /// `origin` is gone, dead or no longer publishes `key`
+    pub fn republish(&mut self, origin: &NodeId<N>, key: &[u8]) -> Option<Duration> {
+        if !self.nodes.get(origin).is_some_and(|n| n.alive) {
+            return None;
+        }
+        let now = self.now();
+        let published = self.publishers.get_mut(origin)?.iter_mut().find(|p| p.key == key)?;
+        published.last_published = now;
+        let (value, ttl) = (published.value.clone(), published.ttl);
+        self.iterative_store(origin, key.to_vec(), value, ttl);
+        Some(ttl)
+    }
+
+    /// Re-store every due publication from its origin and reset its publish time
+    pub fn do_republish(&mut self, now: Instant) {
+        for origin in self.republish_due(now) {
+            let due: Vec<PublishedValue> = self.publishers[&origin]
+                .iter()
+                .filter(|p| p.is_due(now))
+                .cloned()
+                .collect();
+            for p in &due {
+                self.iterative_store(&origin, p.key.clone(), p.value.clone(), p.ttl);
+            }
+            if let Some(values) = self.publishers.get_mut(&origin) {
+                for p in values.iter_mut().filter(|p| p.is_due(now)) {
+                    p.last_published = now;
+                }
+            }
+        }
+    }
+
+    /// RPC forwarding: ask `to` which key IDs it stores
+    pub fn storage_manifest(&mut self, from: &NodeId<N>, to: &NodeId<N>) -> Result<Vec<NodeId<N>>, KademliaError<N>> {
+        self.deliver(from, to, |node| node.rpc_get_storage_manifest())
+    }
+
+    /// Repair the gaps between two nodes' storage: `a` fetches `b`'s manifest, then each side
+    /// stores on the other every live entry the other lacks, with the TTL it has left. Signed
+    /// mutable records are offered even when the other side has the key, so a newer version
+    /// replaces an older one. Every exchange is an RPC, so lost packets, dead nodes and
+    /// partitions get in the way as they would anywhere else. Returns how many entries were
+    /// copied, or the error if `b` could not be reached.
+    pub fn anti_entropy_sync(&mut self, a: &NodeId<N>, b: &NodeId<N>) -> Result<usize, KademliaError<N>> {
+        let theirs: HashSet<NodeId<N>> = self.storage_manifest(a, b)?.into_iter().collect();
+        let ours = self.nodes.get(a).map(Node::records).unwrap_or_default();
+        let our_keys: HashSet<NodeId<N>> = ours.iter().map(|record| record.key).collect();
+        let wanted = |record: &StoredRecord<N>, keys: &HashSet<NodeId<N>>| {
+            !keys.contains(&record.key) || MutableValue::stored_under(&record.key, &record.value).is_some()
+        };
+        let for_b: Vec<StoredRecord<N>> = ours.into_iter().filter(|record| wanted(record, &theirs)).collect();
+        // `b` picks out what `a` lacks in an RPC of its own, which can be lost like any other
+        let for_a: Vec<StoredRecord<N>> = self
+            .deliver(a, b, |node| node.records().into_iter().filter(|record| wanted(record, &our_keys)).collect())
+            .unwrap_or_default();
+        let mut copied = 0;
+        for (from, to, record) in for_b.into_iter().map(|r| (a, b, r)).chain(for_a.into_iter().map(|r| (b, a, r))) {
+            if self.store(from, to, record.key, record.value, record.ttl_remaining).is_ok() {
+                copied += 1;
+            }
+        }
+        Ok(copied)
+    }
+
+    /// Run `anti_entropy_sync` once for every pair of live nodes where one knows the other;
+    /// returns how many entries were copied in total
+    pub fn full_anti_entropy(&mut self) -> usize {
+        let alive = |net: &Self, id: &NodeId<N>| net.nodes.get(id).is_some_and(|n| n.alive);
+        let mut pairs: Vec<(NodeId<N>, NodeId<N>)> = self
+            .nodes
+            .values()
+            .filter(|node| node.alive)
+            .flat_map(|node| node.routing_table.peers().map(|peer| (node.id.min(*peer), node.id.max(*peer))))
+            .filter(|(a, b)| alive(self, a) && alive(self, b))
+            .collect();
+        pairs.sort();
+        pairs.dedup();
+        pairs.iter().filter_map(|(a, b)| self.anti_entropy_sync(a, b).ok()).sum()
+    }
+
+    /// Refresh every bucket of `node_id` that has gone REFRESH_INTERVAL without a lookup,
+    /// by looking up a random ID in its range; returns how many buckets were refreshed
+    pub fn refresh_node(&mut self, node_id: &NodeId<N>) -> usize {
+        let Some(node) = self.nodes.get(node_id) else {
+            return 0;
+        };
+        let stale = node.buckets_needing_refresh(self.now(), REFRESH_INTERVAL);
+        for &index in &stale {
+            self.refresh_bucket(node_id, index);
+        }
+        stale.len()
+    }
+
+    /// Look up a random ID in bucket `index` of `node_id` and mark the bucket refreshed;
+    /// false if there is no such node or bucket
+    pub fn refresh_bucket(&mut self, node_id: &NodeId<N>, index: usize) -> bool {
+        let Some(node) = self.nodes.get(node_id) else {
+            return false;
+        };
+        if index >= node.routing_table.buckets.len() {
+            return false;
+        }
+        let target = node.routing_table.random_id_in_bucket(index, &mut self.rng);
+        let _ = self.iterative_find_node(node_id, &target);
+        let now = self.now();
+        if let Some(node) = self.nodes.get_mut(node_id) {
+            node.routing_table.buckets[index].last_refresh = Some(now);
+        }
+        true
+    }
+
+    /// Estimate the network size from `from`'s point of view (`Node::estimate_size`); 0 for
+    /// an unknown node
+    pub fn estimate_size(&self, from: &NodeId<N>) -> f64 {
+        self.nodes.get(from).map_or(0.0, Node::estimate_size)
+    }
+
+    /// `Node::routing_health` for every live node; returns the `worst` reports with the most
+    /// problems, worst first
+    pub fn health_sweep(&self, worst: usize) -> Vec<HealthReport<N>> {
+        let live = self.nodes.values().filter(|node| node.alive);
+        let mut reports: Vec<HealthReport<N>> = live.map(Node::routing_health).collect();
+        reports.sort_by(|a, b| b.problems().cmp(&a.problems()).then(a.node.cmp(&b.node)));
+        reports.truncate(worst);
+        reports
+    }
+}
+
+#[cfg(test)]
+mod network_tests {
+    use super::*;
+
+    /// A seeded network of `count` nodes, each joined through a random earlier one, and its
+    /// node IDs in ascending order
+    fn seeded(count: usize, seed: u64) -> (Network, Vec<NodeId>) {
+        let net: Network = Network::new_with_nodes_seeded(count, seed);
+        let mut ids: Vec<NodeId> = net.nodes.keys().copied().collect();
+        ids.sort();
+        (net, ids)
+    }
+
+    #[test]
+    fn raw_ids_and_byte_keys_meet_only_when_the_hashes_agree() {
+        let (mut net, ids) = seeded(20, 13);
+        let content_id = net.key_to_id(b"by-id");
+        net.store_at_id(&ids[0], content_id, b"addressed directly".to_vec(), DEFAULT_TTL);
+        assert_eq!(net.iterative_find_value(&ids[1], b"by-id"), Ok(b"addressed directly".to_vec()));
+        let other = net.key_to_id(b"other");
+        assert_eq!(net.find_value_by_id(&ids[1], &other), Err(KademliaError::KeyNotFound));
+
+        net.iterative_store(&ids[2], b"by-key".to_vec(), b"hashed".to_vec(), DEFAULT_TTL);
+        let hashed = net.key_to_id(b"by-key");
+        assert_eq!(net.find_value_by_id(&ids[3], &hashed), Ok(b"hashed".to_vec()));
+        // the key's bytes taken as an ID, without hashing, point elsewhere
+        let mut raw = [0; ID_BYTES];
+        raw[..6].copy_from_slice(b"by-key");
+        assert_eq!(net.find_value_by_id(&ids[3], &NodeId::from_bytes(raw)), Err(KademliaError::KeyNotFound));
+    }
+
+    #[test]
+    fn a_value_is_lost_with_its_only_holder_but_routing_survives() {
+        let (mut net, ids) = seeded(30, 15);
+        let key_id = net.key_to_id(b"lonely");
+        let holder = ids[5];
+        net.store(&ids[0], &holder, key_id, b"one copy".to_vec(), DEFAULT_TTL).expect("holder is alive");
+        assert_eq!(net.find_all_holders(b"lonely"), vec![holder]);
+        assert!(net.fail_node(&holder).is_some());
+        // a crash leaves contacts behind, unlike a graceful departure
+        assert!(net.nodes.values().any(|node| node.has_peer(&holder)));
+        assert_eq!(net.iterative_find_value(&ids[0], b"lonely"), Err(KademliaError::KeyNotFound));
+
+        let failed = net.simulate_churn(0.2, &mut StdRng::seed_from_u64(1));
+        assert_eq!(failed.len(), 6);
+        let survivors: Vec<NodeId> = ids.iter().copied().filter(|id| net.nodes.contains_key(id)).collect();
+        for start in &survivors {
+            let found = net.iterative_find_node(start, &key_id);
+            assert!(!found.is_empty() && found.iter().all(|id| net.nodes.contains_key(id)), "a lookup returned a crashed node");
+        }
+        net.iterative_store(&survivors[0], b"after".to_vec(), b"churn".to_vec(), DEFAULT_TTL);
+        assert_eq!(net.iterative_find_value(survivors.last().unwrap(), b"after"), Ok(b"churn".to_vec()));
+    }
+
+    #[test]
+    fn a_removed_node_is_forgotten_at_once() {
+        let (mut net, ids) = seeded(30, 15);
+        let leaving = ids[3];
+        assert!(net.nodes.values().any(|node| node.has_peer(&leaving)));
+        assert_eq!(net.remove_node(&leaving).map(|node| node.id), Some(leaving));
+        assert!(net.nodes.values().all(|node| !node.has_peer(&leaving)));
+        assert!(net.remove_node(&leaving).is_none());
+    }
+
+    #[test]
+    fn a_found_value_is_cached_at_the_closest_node_without_it() {
+        let mut net: Network = Network::new();
+        let key_id = NodeId::ZERO;
+        let [holder, closest, asker] = [0x40, 0x10, 0xf0].map(|b| net.add_node_with_id(NodeId::from_u8(b)).expect("distinct IDs"));
+        net.ping(&asker, &closest).expect("alive");
+        net.ping(&asker, &holder).expect("alive");
+        net.store(&holder, &holder, key_id, b"cached".to_vec(), DEFAULT_TTL).expect("alive");
+        assert!(!net.nodes[&closest].storage.contains_key(&key_id));
+
+        assert_eq!(net.find_value_by_id(&asker, &key_id), Ok(b"cached".to_vec()));
+        let copy = &net.nodes[&closest].storage[&key_id];
+        assert_eq!(copy.value, b"cached");
+        assert_eq!(copy.expires_at, net.now() + CACHE_TTL, "a cached copy lives for CACHE_TTL only");
+        assert!(!net.nodes[&asker].storage.contains_key(&key_id), "the asker itself is not a cache");
+    }
+
+    /// Grow a 100-node network from one seed node, store `value` under `key` and look it up
+    /// from the last joiner; returns how many nodes a self-lookup finds, or None if the value
+    /// was lost
+    fn converges<const N: usize>(network: &mut Network<N>, key: &[u8], value: &[u8]) -> Option<usize> {
+        let seed = network.add_node();
+        let members: Vec<NodeId<N>> = (0..99).filter_map(|_| network.add_and_bootstrap(&seed).ok()).collect();
+        let last = *members.last()?;
+        network.iterative_store(&seed, key.to_vec(), value.to_vec(), DEFAULT_TTL);
+        let found = network.iterative_find_value(&last, key).ok()?;
+        (found == value).then(|| network.iterative_find_node(&last, &last).len())
+    }
+
+    #[test]
+    fn the_same_topology_converges_at_160_and_256_bits() {
+        let narrow = converges(&mut Network::<ID_BYTES>::with_seed(42), b"hello", b"world");
+        let wide = converges(&mut Network256::with_seed(42), b"hello", b"world");
+        assert_eq!((narrow, wide), (Some(K), Some(K)));
+    }
+
+    #[test]
+    fn wide_networks_hash_keys_to_whole_sha256_digests() {
+        let net = Network256::new();
+        assert_eq!(net.key_to_id(b"hello").0.as_slice(), Sha256::digest(b"hello").as_slice());
+    }
+
+    #[test]
+    fn each_hash_function_maps_a_key_to_a_different_id() {
+        let funcs = [HashFunction::Sha1, HashFunction::Sha256, #[cfg(feature = "blake3")] HashFunction::Blake3];
+        let ids: Vec<NodeId> = funcs.into_iter().map(|func| Network::key_to_id_with(b"hello", func)).collect();
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), ids.len());
+        assert_eq!(ids[0].0.as_slice(), Sha1::digest(b"hello").as_slice());
+        assert_eq!(ids[1].0.as_slice(), &Sha256::digest(b"hello")[..ID_BYTES]);
+    }
+
+    #[test]
+    fn a_network_sharing_one_hash_function_round_trips_values() {
+        let mut net: Network = Network::with_config(NetworkConfig::builder().hash_function(HashFunction::Sha256).build());
+        net.rng = StdRng::seed_from_u64(20);
+        net.populate(20);
+        let mut ids: Vec<NodeId> = net.nodes.keys().copied().collect();
+        ids.sort();
+        assert_eq!(net.key_to_id(b"hello"), Network::key_to_id_with(b"hello", HashFunction::Sha256));
+        net.iterative_store(&ids[0], b"hello".to_vec(), b"world".to_vec(), DEFAULT_TTL);
+        assert_eq!(net.iterative_find_value(&ids[19], b"hello"), Ok(b"world".to_vec()));
+        let holders = net.find_all_holders(b"hello");
+        assert!(holders.iter().all(|id| net.top_k_closest_to_key(b"hello", K).contains(id)), "stored by the SHA-256 key ID");
+    }
+
+    #[test]
+    fn contacts_record_when_they_answered_and_how_often_they_failed() {
+        let mut net: Network = Network::new();
+        let [a, b, c] = [0x10, 0x80, 0xc0].map(|byte| net.add_node_with_id(NodeId::from_u8(byte)).expect("distinct IDs"));
+        net.ping(&a, &b).expect("alive");
+        net.ping(&a, &c).expect("alive");
+        let first = net.now();
+        assert_eq!(net.nodes[&a].contact(&b).and_then(|contact| contact.last_seen), Some(first));
+        net.advance(Duration::from_secs(60));
+        net.ping(&b, &a).expect("alive");
+        let contact = net.nodes[&a].contact(&b).expect("b answered");
+        assert_eq!((contact.last_seen, contact.failed_attempts), (Some(first + Duration::from_secs(60)), 0));
+
+        net.kill_node(&c);
+        net.iterative_find_node(&a, &NodeId::from_u8(0xc1));
+        assert_eq!(net.nodes[&a].contact(&c).map(|contact| contact.failed_attempts), Some(1));
+        // an answer forgives earlier failures
+        net.nodes.get_mut(&c).expect("c exists").alive = true;
+        net.ping(&a, &c).expect("alive again");
+        assert_eq!(net.nodes[&a].contact(&c).map(|contact| contact.failed_attempts), Some(0));
+    }
+
+    #[test]
+    fn a_traced_lookup_closes_in_on_the_target_round_by_round() {
+        let (mut net, _) = seeded(100, 24);
+        let key_id = net.key_to_id(b"traced");
+        let far = *net.nodes.keys().max_by_key(|id| key_id.xor_distance(id)).expect("nodes");
+        net.iterative_store(&far, b"traced".to_vec(), b"value".to_vec(), DEFAULT_TTL);
+        let (closest, trace) = net.traced_iterative_find_node(&far, &key_id);
+        assert_eq!(closest, net.iterative_find_node(&far, &key_id), "tracing should not change the result");
+
+        let rounds = trace.steps.last().expect("the lookup queried someone").step + 1;
+        assert!(rounds <= net.config.max_steps);
+        let mut best = far.xor_distance(&key_id);
+        let mut per_round = vec![best];
+        for step in &trace.steps {
+            assert!(step.shortlist_size_after <= net.config.k);
+            best = step.returned.iter().map(|id| id.xor_distance(&key_id)).fold(best, Distance::min);
+            if per_round.len() == step.step + 1 {
+                per_round.push(best);
+            }
+            per_round[step.step + 1] = best;
+        }
+        assert!(per_round.windows(2).all(|w| w[1] <= w[0]), "the closest known node moved away");
+        assert!(per_round[1] < per_round[0], "the first round found nobody closer");
+        assert_eq!(Some(&closest[0].xor_distance(&key_id)), per_round.last());
+        let holders = net.find_all_holders(b"traced");
+        assert!(closest.iter().all(|id| holders.contains(id)));
+    }
+
+    #[test]
+    fn a_store_lands_on_the_closest_nodes_to_the_key() {
+        let (mut net, ids) = seeded(20, 25);
+        net.iterative_store(&ids[0], b"placed".to_vec(), b"here".to_vec(), DEFAULT_TTL);
+        let expected = net.top_k_closest_to_key(b"placed", net.config.k);
+        let holders = net.find_all_holders(b"placed");
+        assert_eq!(holders.len(), net.config.k);
+        assert!(holders.iter().all(|h| expected.contains(h)), "{holders:?} strayed outside {expected:?}");
+
+        let key_id = net.key_to_id(b"placed");
+        let mut by_distance = ids.clone();
+        by_distance.sort_by_key(|id| id.xor_distance(&key_id));
+        assert_eq!(net.closest_node_to_key(b"placed"), Some(by_distance[0]));
+        assert_eq!(net.top_k_closest_to_key(b"placed", 3), by_distance[..3]);
+        assert_eq!(net.top_k_closest_to_key(b"placed", 100).len(), 20);
+        assert_eq!(Network::<ID_BYTES>::new().closest_node_to_key(b"placed"), None);
+    }
+
+    #[test]
+    fn a_ready_made_network_is_bootstrapped() {
+        let (mut net, ids) = seeded(50, 26);
+        assert_eq!(ids.len(), 50);
+        assert!(net.nodes.values().all(|node| node.routing_table.peers().next().is_some()), "a node has no peers");
+        let mut rng = StdRng::seed_from_u64(26);
+        let (writer, reader) = (*ids.choose(&mut rng).expect("nodes"), *ids.choose(&mut rng).expect("nodes"));
+        net.iterative_store(&writer, b"ready".to_vec(), b"made".to_vec(), DEFAULT_TTL);
+        assert_eq!(net.iterative_find_value(&reader, b"ready"), Ok(b"made".to_vec()));
+        // the same seed grows the same network
+        let (again, again_ids) = seeded(50, 26);
+        assert_eq!(again_ids, ids);
+        let known = |net: &Network| net.snapshot_peers(&ids[0]).into_iter().collect::<HashSet<_>>();
+        assert_eq!(known(&again), known(&seeded(50, 26).0));
+        assert_eq!(Network::<ID_BYTES>::new_with_nodes(5).nodes.len(), 5);
+    }
+
+    #[test]
+    fn a_refresh_pass_reconnects_an_isolated_node() {
+        let (mut net, ids) = seeded(40, 262);
+        for gone in &ids[..10] {
+            net.fail_node(gone);
+        }
+        // the newcomer has heard of a single survivor and nothing else
+        let isolated = net.add_node();
+        net.ping(&isolated, &ids[20]).expect("the survivor is up");
+        assert_eq!(net.snapshot_peers(&isolated), [ids[20]]);
+        // an hour on, tick has every live node refresh its stale buckets
+        assert_eq!(net.nodes[&isolated].buckets_needing_refresh(net.now(), REFRESH_INTERVAL), Vec::<usize>::new());
+        net.tick(REFRESH_INTERVAL);
+        let peers = net.snapshot_peers(&isolated);
+        assert!(peers.len() > 5, "only {} peers after a refresh", peers.len());
+        assert!(peers.iter().all(|peer| net.nodes.get(peer).is_some_and(|node| node.alive)));
+    }
+
+    #[test]
+    fn k_and_alpha_come_from_the_network_config() {
+        let run = |k: usize, alpha: usize| {
+            let mut net: Network = Network::with_config(NetworkConfig::builder().k(k).alpha(alpha).build());
+            net.rng = StdRng::seed_from_u64(27);
+            net.populate(60);
+            let (asker, target) = (NodeId::MAX, NodeId::ZERO);
+            let first = *net.nodes.keys().min().expect("nodes");
+            let node = net.nodes.get_mut(&first).expect("nodes");
+            let answer = node.rpc_find_node(&asker, &target, k).len();
+            let fullest = node.routing_table.buckets.iter().map(KBucket::len).max().unwrap_or(0);
+            (answer, node.routing_table.capacity(0), fullest)
+        };
+        let (wide, narrow) = (run(20, 3), run(8, 1));
+        assert_eq!((wide.0, wide.1), (20, 20));
+        assert_eq!(narrow, (8, 8, 8));
+        assert!(wide.2 > narrow.2, "a k = 20 bucket should hold more than a k = 8 one");
+    }
+
+    #[test]
+    #[should_panic(expected = "pow_difficulty: 33")]
+    fn proof_of_work_harder_than_the_cap_is_refused() {
+        let _ = NetworkConfig::builder().pow_difficulty(MAX_POW_DIFFICULTY).build();
+        NetworkConfig::builder().require_pow(true).pow_difficulty(MAX_POW_DIFFICULTY + 1).build();
+    }
+
+    #[test]
+    fn a_crashed_node_stops_being_handed_out_once_it_goes_stale() {
+        let mut net: Network = Network::new_with_nodes_seeded(20, 3);
+        let crashed = *net.nodes.keys().min().expect("nodes");
+        net.kill_node(&crashed);
+        let mut survivors: Vec<NodeId> = net.nodes.keys().copied().filter(|id| *id != crashed).collect();
+        survivors.sort();
+        let advertising = |net: &mut Network| {
+            survivors
+                .iter()
+                .filter(|id| net.nodes.get_mut(*id).is_some_and(|n| n.rpc_find_node(id, &crashed, K).contains(&crashed)))
+                .count()
+        };
+        assert!(advertising(&mut net) > 0);
+        for round in 1..=net.config.stale_after {
+            for id in &survivors {
+                net.iterative_find_node(id, &crashed);
+            }
+            if round < net.config.stale_after {
+                assert!(advertising(&mut net) > 0, "stale after only {round} timeouts");
+            }
+        }
+        assert_eq!(advertising(&mut net), 0);
+    }
+
+    #[test]
+    fn only_nodes_built_with_a_small_limit_refuse_large_values() {
+        let mut net: Network = Network::with_seed(21);
+        let roomy: Vec<NodeId> = (0..4).map(|_| net.add_node()).collect();
+        let cramped: Vec<NodeId> = (0..2)
+            .map(|_| net.add_node_with_config(NodeBuilder::default().with_storage_limit(64)))
+            .collect::<Result<_, _>>()
+            .expect("fresh IDs are accepted");
+        for id in roomy[1..].iter().chain(&cramped) {
+            net.bootstrap_node(*id, roomy[0]).expect("the seed is up");
+        }
+        let refused: HashSet<NodeId> = (0..3u8)
+            .flat_map(|i| net.iterative_store(&roomy[0], vec![b'k', i], vec![i; 100], DEFAULT_TTL))
+            .filter_map(|result| match result {
+                Err(KademliaError::StoreFull(id)) => Some(id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(refused, cramped.iter().copied().collect());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn a_node_restarts_from_its_saved_routing_table() {
+        let (mut net, ids) = seeded(30, 31);
+        let (writer, reader) = (ids[0], ids[29]);
+        net.iterative_store(&writer, b"hello".to_vec(), b"world".to_vec(), DEFAULT_TTL);
+        let saved = net.snapshot_peers(&reader).into_iter().collect::<HashSet<_>>();
+        let path = std::env::temp_dir().join(format!("kademlia-test-{}.json", reader.short()));
+        net.save_node(&reader, &path).expect("temp dir is writable");
+        let empty = RoutingTable::new(reader, net.now(), &net.config);
+        net.nodes.get_mut(&reader).expect("reader exists").routing_table = empty;
+        let loaded = net.load_node(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(loaded.expect("saved table loads"), reader);
+        assert_eq!(net.snapshot_peers(&reader).into_iter().collect::<HashSet<_>>(), saved);
+        // restored contacts come back unverified until a lookup hears from them
+        let verified = |net: &Network| net.nodes[&reader].routing_table.contacts().filter(|c| c.verified).count();
+        assert_eq!(verified(&net), 0);
+        assert_eq!(net.iterative_find_value(&reader, b"hello"), Ok(b"world".to_vec()));
+        assert!(verified(&net) > 0);
+    }
+
+    #[test]
+    fn the_topology_draws_every_contact_and_grays_out_dead_nodes() {
+        let (mut net, ids) = seeded(10, 32);
+        net.kill_node(&ids[0]);
+        let dot = net.topology_to_dot();
+        let contacts: usize = net.nodes.values().map(|node| node.routing_table.len()).sum();
+        assert_eq!(dot.lines().filter(|line| line.contains("->")).count(), contacts);
+        let gray: Vec<&str> = dot.lines().filter(|line| line.contains("fillcolor=gray")).collect();
+        assert_eq!(gray.len(), 1);
+        assert!(gray[0].contains(&ids[0].to_string()));
+    }
+
+    #[test]
+    fn only_the_owner_can_update_a_mutable_value_across_the_network() {
+        let (mut net, ids) = seeded(30, 35);
+        let (writer, reader) = (ids[0], ids[29]);
+        let owner = NodeIdentity::generate(&mut StdRng::seed_from_u64(5));
+        let first_error = |results: Vec<Result<(), KademliaError>>| results.into_iter().find_map(Result::err);
+        assert_eq!(first_error(net.iterative_store_mutable(&writer, owner.sign_value(1, b"v1".to_vec()), DEFAULT_TTL)), None);
+        let mut forged = owner.sign_value(2, b"v2".to_vec());
+        forged.value = b"evil".to_vec();
+        assert_eq!(first_error(net.iterative_store_mutable(&writer, forged, DEFAULT_TTL)), Some(KademliaError::InvalidSignature));
+        assert!(matches!(
+            first_error(net.iterative_store_mutable(&writer, owner.sign_value(0, b"v0".to_vec()), DEFAULT_TTL)),
+            Some(KademliaError::OutdatedSequence { stored: 1, offered: 0 })
+        ));
+        assert_eq!(first_error(net.iterative_store_mutable(&writer, owner.sign_value(2, b"v2".to_vec()), DEFAULT_TTL)), None);
+        let found = net.iterative_find_mutable_value(&reader, &owner.public_key().to_bytes());
+        assert_eq!(found.map(|mv| (mv.sequence_number, mv.value)), Ok((2, b"v2".to_vec())));
+    }
+
+    #[test]
+    fn anti_entropy_spreads_a_newer_mutable_version() {
+        let (mut net, ids) = seeded(30, 35);
+        let owner = NodeIdentity::generate(&mut StdRng::seed_from_u64(5));
+        let v1 = owner.sign_value(1, b"v1".to_vec());
+        let key = v1.key_id();
+        net.iterative_store_mutable(&ids[0], v1, DEFAULT_TTL);
+        let holders: Vec<NodeId> = ids.iter().copied().filter(|id| net.nodes[id].live_mutable(&key).is_some()).collect();
+        let first = net.nodes.get_mut(&holders[0]).expect("a holder");
+        first.rpc_store_mutable(&ids[0], owner.sign_value(2, b"v2".to_vec()), DEFAULT_TTL).expect("newer");
+        for holder in &holders[1..] {
+            net.anti_entropy_sync(&holders[0], holder).expect("holders are up");
+        }
+        let versions: Vec<u64> = holders.iter().filter_map(|id| net.nodes[id].live_mutable(&key)).map(|mv| mv.sequence_number).collect();
+        assert_eq!(versions, vec![2; holders.len()]);
+    }
+
+    #[test]
+    fn a_responsible_writer_keeps_one_of_the_copies() {
+        let (mut net, ids) = seeded(40, 352);
+        let key_id = NodeId::random_with(&mut StdRng::seed_from_u64(352));
+        let ranked = net.closest_k(&key_id, &ids);
+        let (inside, outside) = (ranked[0], *ids.iter().find(|id| !ranked.contains(id)).expect("more than k nodes"));
+        assert!(net.nodes[&inside].is_responsible_for(&key_id));
+        assert!(!net.nodes[&outside].is_responsible_for(&key_id));
+        for writer in [inside, outside] {
+            let value = writer.0.to_vec();
+            let results = net.store_at_id(&writer, key_id, value.clone(), DEFAULT_TTL);
+            assert_eq!(results.len(), K);
+            let holds = net.nodes[&writer].storage_keys().any(|key| *key == key_id);
+            assert_eq!(holds, writer == inside, "{} the writer", if holds { "stored at" } else { "skipped" });
+        }
+    }
+
+    #[test]
+    fn an_immutable_lookup_skips_holders_with_tampered_bytes() {
+        let (mut net, ids) = seeded(30, 36);
+        let (writer, reader) = (ids[0], ids[29]);
+        let content = b"immutable bytes".to_vec();
+        let key_id = net.iterative_store_immutable(&writer, content.clone());
+        assert_eq!(key_id, NodeId::for_content(&content));
+        let holders = net.iterative_find_node(&reader, &key_id);
+        assert_eq!(
+            net.store_immutable(&writer, &holders[0], key_id, b"other bytes".to_vec(), DEFAULT_TTL),
+            Err(KademliaError::HashMismatch)
+        );
+        // the closest holders serve forged bytes; the lookup moves on to an honest one
+        for holder in &holders[..3] {
+            let stored = net.nodes.get_mut(holder).and_then(|node| node.storage.get_mut(&key_id)).expect("a holder");
+            stored.value = b"forged".to_vec();
+        }
+        assert_eq!(net.iterative_find_immutable_value(&reader, &key_id), Some(content));
+        for holder in &holders {
+            if let Some(stored) = net.nodes.get_mut(holder).and_then(|node| node.storage.get_mut(&key_id)) {
+                stored.value = b"forged".to_vec();
+            }
+        }
+        assert_eq!(net.iterative_find_immutable_value(&reader, &key_id), None);
+    }
+
+    /// Mean hops for 200 lookups between random nodes of a seeded `count`-node network with
+    /// `bits` bits per bucket
+    fn mean_hops(bits: usize, count: usize) -> f64 {
+        let mut net: Network = Network::with_config(NetworkConfig::builder().bits_per_bucket(bits).build());
+        net.rng = StdRng::seed_from_u64(36);
+        net.populate(count);
+        let mut ids: Vec<NodeId> = net.nodes.keys().copied().collect();
+        ids.sort();
+        let lookups: Vec<(NodeId, NodeId)> =
+            (0..200).map(|_| (*ids.choose(&mut net.rng).expect("nodes"), *ids.choose(&mut net.rng).expect("nodes"))).collect();
+        // a lookup for a node's own ID stops once that node answers
+        let hops: usize = lookups
+            .iter()
+            .map(|(start, target)| net.traced_iterative_find_node(start, target).1.steps.last().map_or(0, |s| s.step + 1))
+            .sum();
+        hops as f64 / lookups.len() as f64
+    }
+
+    #[test]
+    fn wider_bucket_levels_take_fewer_hops() {
+        let (binary, nibble) = (mean_hops(1, 150), mean_hops(4, 150));
+        assert!(nibble < binary, "b = 4 took {nibble:.2} hops on average, b = 1 {binary:.2}");
+    }
+
+    /// The same comparison at 2000 nodes, which takes minutes to build without optimizations
+    /// (`cargo test --release -- --ignored`)
+    #[test]
+    #[ignore]
+    fn wider_bucket_levels_take_fewer_hops_at_2000_nodes() {
+        let (binary, nibble) = (mean_hops(1, 2000), mean_hops(4, 2000));
+        assert!(nibble < binary, "b = 4 took {nibble:.2} hops on average, b = 1 {binary:.2}");
+    }
+
+    #[test]
+    fn a_join_looks_up_every_digit_of_the_far_levels() {
+        let mut net: Network = Network::with_config(NetworkConfig::builder().bits_per_bucket(4).build());
+        net.rng = StdRng::seed_from_u64(362);
+        net.populate(150);
+        let seed = *net.nodes.keys().min().expect("nodes");
+        let joiner = net.add_node();
+        let report = net.join(&joiner, &seed).expect("the seed is up");
+        // 150 nodes share about log16(150) < 2 digits with their nearest neighbor, so the
+        // 15 first-level ranges are all looked up and each yields contacts
+        assert!(report.lookups > 15, "only {} lookups", report.lookups);
+        let table = &net.nodes[&joiner].routing_table;
+        let digits: HashSet<usize> = table.peers().map(|peer| table.digit(peer, 0)).filter(|&d| d != 0).collect();
+        assert_eq!(digits.len(), 15);
+    }
+
+    #[test]
+    fn a_lookup_ends_only_once_the_closest_it_returns_were_queried() {
+        let mut net: Network = Network::with_config(NetworkConfig::builder().k(8).build());
+        net.rng = StdRng::seed_from_u64(37);
+        net.populate(60);
+        let mut members: Vec<NodeId> = net.nodes.keys().copied().collect();
+        members.sort();
+        for start in &members[..10] {
+            let target = NodeId::random_with(&mut net.rng);
+            let (closest, trace) = net.traced_iterative_find_node(start, &target);
+            let queried: HashSet<NodeId> = trace.steps.iter().map(|step| step.queried).collect();
+            assert!(closest.iter().all(|n| queried.contains(n)), "a returned node was never queried");
+            assert_eq!(closest, distance::k_closest(&target, &members, 8), "a closer node was missed");
+        }
+    }
+
+    #[test]
+    fn time_moves_only_when_the_network_advances_it() {
+        let (mut net, ids) = seeded(10, 372);
+        let node = ids[3];
+        let due = |net: &Network| net.nodes[&node].buckets_needing_refresh(net.now(), REFRESH_INTERVAL).len();
+        let start = net.now();
+        assert_eq!(due(&net), 0);
+        net.advance(REFRESH_INTERVAL);
+        assert_eq!(net.now() - start, REFRESH_INTERVAL);
+        assert_eq!(due(&net), net.nodes[&node].routing_table.buckets.len());
+        // contacts are stamped with the shared clock's time
+        net.ping(&node, &ids[4]).expect("both are up");
+        assert_eq!(net.nodes[&node].contact(&ids[4]).and_then(|c| c.last_seen), Some(net.now()));
+        // a node on the system clock ages in real time instead
+        let real: Node = Node::with_clock(SystemClock);
+        assert_eq!(real.routing_table.buckets_needing_refresh(SystemClock.now(), REFRESH_INTERVAL), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn confirmed_stores_name_the_nodes_that_took_the_value() {
+        let mut net: Network = Network::with_config(NetworkConfig::builder().k(3).build());
+        net.rng = StdRng::seed_from_u64(38);
+        net.populate(11);
+        let entry = *net.nodes.keys().min().expect("nodes");
+        let mut confirmed = net.iterative_store_confirmed(&entry, b"durable".to_vec(), b"x".to_vec());
+        confirmed.sort();
+        let mut expected = net.top_k_closest_to_key(b"durable", 3);
+        expected.sort();
+        assert_eq!(confirmed, expected);
+        // with the closest holder down, nobody can confirm a fourth copy
+        let closest = net.top_k_closest_to_key(b"durable", 1)[0];
+        net.kill_node(&closest);
+        let Err(KademliaError::InsufficientReplicas { stored, required: 4 }) =
+            net.iterative_store_with_replication(&entry, b"durable".to_vec(), b"y".to_vec(), 4)
+        else {
+            panic!("four replicas of a k = 3 store");
+        };
+        assert!((1..=3).contains(&stored));
+        let confirmed = net.iterative_store_with_replication(&entry, b"durable".to_vec(), b"z".to_vec(), 1).expect("one copy");
+        assert!(!confirmed.contains(&closest) && confirmed.len() <= 3);
+    }
+
+    #[test]
+    fn one_origin_cannot_flood_a_bucket() {
+        let mut net: Network = Network::with_config(NetworkConfig::builder().max_per_origin_per_bucket(2).build());
+        net.rng = StdRng::seed_from_u64(382);
+        let victim = net.add_node();
+        let flood: Vec<NodeId> = (0..20)
+            .map(|_| net.add_node_with_config(NodeBuilder::default().with_origin(7)).expect("fresh ID"))
+            .collect();
+        let honest: Vec<NodeId> = (1..=4)
+            .map(|origin| net.add_node_with_config(NodeBuilder::default().with_origin(origin)).expect("fresh ID"))
+            .collect();
+        for peer in flood.iter().chain(&honest) {
+            net.ping(peer, &victim).expect("the victim is up");
+        }
+        let node = &net.nodes[&victim];
+        let table = &node.routing_table;
+        let per_bucket = table.buckets.iter().map(|b| b.peers.iter().filter(|c| c.origin == 7).count());
+        assert!(per_bucket.max().is_some_and(|most| most <= 2));
+        assert!(honest.iter().all(|h| table.contact(h).is_some()), "an honest peer was kept out");
+        let flooding = table.contacts().filter(|c| c.origin == 7).count() as u64;
+        assert_eq!(flooding + node.routing_stats().origin_rejections, 20);
+    }
+
+    #[test]
+    fn lookups_mostly_survive_half_of_all_rpcs_being_lost() {
+        let (mut net, ids) = seeded(50, 13);
+        net.iterative_store(&ids[0], b"hello".to_vec(), b"world".to_vec(), DEFAULT_TTL);
+        let answered = (0..200).filter(|_| net.ping_with_loss(&ids[1], &ids[2], 0.3).is_ok()).count();
+        assert!((110..=170).contains(&answered), "{answered}/200 pings answered at 30% loss");
+        net.config.packet_loss_rate = 0.5;
+        let found = (0..100).filter(|i| net.iterative_find_value(&ids[i % ids.len()], b"hello").is_ok()).count();
+        assert!(found >= 50, "only {found}/100 lookups found the value at 50% loss");
+    }
+
+    #[test]
+    fn lost_packets_come_from_the_seeded_transport_and_leave_ids_alone() {
+        let run = |loss_rate: f64| {
+            let mut net: Network = Network::with_seed(32);
+            net.config.packet_loss_rate = loss_rate;
+            let (a, b) = (net.add_node(), net.add_node());
+            let answers: Vec<bool> = (0..50).map(|_| net.ping(&a, &b).is_ok()).collect();
+            (answers, net.add_node())
+        };
+        let (answers, next_id) = run(0.5);
+        assert_eq!(run(0.5), (answers.clone(), next_id));
+        assert!(answers.contains(&true) && answers.contains(&false));
+        assert_eq!(run(0.0).1, next_id);
+    }
+
+    #[test]
+    fn concurrent_rounds_finish_lookups_sooner_than_one_query_at_a_time() {
+        let (mut net, ids) = seeded(50, 17);
+        net.config.latency_model = LatencyModel::Uniform(Duration::from_millis(20), Duration::from_millis(80));
+        let (mut elapsed, mut one_by_one) = (Duration::ZERO, Duration::ZERO);
+        for (from, target) in ids.iter().zip(ids.iter().rev()).take(20) {
+            let started = net.now();
+            let (_, trace) = net.traced_iterative_find_node(from, target);
+            elapsed += net.now() - started;
+            for step in &trace.steps {
+                assert!((20..=80).contains(&(step.answered_at - step.sent_at).as_millis()), "{step:?}");
+            }
+            one_by_one += trace.steps.iter().map(|step| step.answered_at - step.sent_at).sum::<Duration>();
+        }
+        assert!(elapsed < one_by_one, "{elapsed:?} with concurrent rounds, {one_by_one:?} one at a time");
+    }
+
+    #[test]
+    fn latencies_come_from_the_seeded_transport() {
+        let rtts = || {
+            let mut net: Network = Network::with_seed(33);
+            net.config.latency_model = LatencyModel::Uniform(Duration::from_millis(20), Duration::from_millis(80));
+            let (a, b) = (net.add_node(), net.add_node());
+            (0..10)
+                .map(|_| {
+                    net.ping(&a, &b).expect("alive");
+                    net.nodes[&a].contact(&b).and_then(|c| c.rtt).expect("answered")
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(rtts(), rtts());
+    }
+
+    #[test]
+    fn flooding_and_gossip_both_reach_every_node() {
+        let (mut net, ids) = seeded(100, 39);
+        assert_eq!(net.flood_store(&ids[0], b"config".to_vec(), b"v1".to_vec()), ids.len());
+        let mut id_stream = net.rng.clone();
+        net.gossip_store(&ids[0], b"gossip".to_vec(), b"spread".to_vec(), 3);
+        let gossip_id = net.key_to_id(b"gossip");
+        let holders = |net: &Network| net.nodes.values().filter(|node| node.storage.contains_key(&gossip_id)).count();
+        let mut ticks = 0;
+        while holders(&net) < ids.len() && ticks < 20 {
+            net.tick(Duration::from_secs(1));
+            ticks += 1;
+        }
+        assert_eq!(holders(&net), ids.len(), "gossip did not reach every node");
+        assert!(ticks <= 2 * (ids.len() as f64).log2().ceil() as usize, "gossip took {ticks} ticks");
+        // gossip targets come from the transport RNG, leaving the ID stream where it was
+        assert_eq!(net.add_node(), NodeId::random_with(&mut id_stream));
+    }
+
+    #[test]
+    fn iter_closest_agrees_with_sorting_every_contact() {
+        let (binary, _) = seeded(100, 39);
+        let mut wide: Network = Network::with_config(NetworkConfig::builder().bits_per_bucket(3).build());
+        wide.rng = StdRng::seed_from_u64(39);
+        wide.populate(60);
+        let mut rng = StdRng::seed_from_u64(2);
+        for net in [&binary, &wide] {
+            for node in net.nodes.values() {
+                let table = &node.routing_table;
+                for _ in 0..10 {
+                    let target = NodeId::random_with(&mut rng);
+                    let mut sorted: Vec<NodeId> = table.peers().copied().collect();
+                    sorted.sort_by_key(|id| id.xor_distance(&target));
+                    let walked: Vec<NodeId> = table.iter_closest(&target).map(|c| c.id).collect();
+                    assert_eq!(walked, sorted, "iter_closest out of order");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_network_requiring_work_admits_and_tracks_only_proven_ids() {
+        let mut net: Network = Network::with_config(NetworkConfig::builder().require_pow(true).pow_difficulty(8).build());
+        net.rng = StdRng::seed_from_u64(40);
+        let member = net.add_node();
+        let member_nonce = net.nodes[&member].pow_nonce.expect("add_node mines a nonce");
+        assert!(verify_pow(&member, member_nonce, 8));
+
+        let mut rng = StdRng::seed_from_u64(41);
+        let (mined, nonce) = NodeId::generate_with_pow(8, &mut rng);
+        assert!(net.add_node_with_id(mined).is_err(), "an ID without its nonce was admitted");
+        assert!(matches!(net.add_node_with_id(NodeId::random_with(&mut rng)), Err(KademliaError::ProtocolError(_))));
+        let joined = net.add_node_with_config(NodeBuilder::default().with_id(mined).with_pow_nonce(nonce)).expect("proven ID");
+        assert!(net.ping(&joined, &member).expect("both alive"));
+        assert!(net.nodes[&member].contact(&joined).is_some());
+
+        // members refuse a peer that cannot back its ID with a nonce, however they hear of it
+        let stranger: NodeId = NodeId::random_with(&mut rng);
+        let node = net.nodes.get_mut(&member).expect("member");
+        assert_eq!(node.track_peer(&stranger, None, 0, None), TrackOutcome::Rejected);
+        assert_eq!(node.track_peer(&stranger, None, 0, Some(nonce)), TrackOutcome::Rejected);
+        assert!(node.record_contact(&stranger), "hearsay is recorded unchecked");
+        assert_eq!(node.track_peer(&stranger, None, 0, None), TrackOutcome::Rejected);
+        assert!(node.contact(&stranger).is_none(), "the unproven hearsay entry stayed");
+
+        // a node from a network without the requirement cannot reach the members either
+        let mut open: Network = Network::with_seed(42);
+        let outsider = open.add_node();
+        net.register(open.remove_node(&outsider).expect("outsider"));
+        assert!(matches!(net.ping(&outsider, &member), Err(KademliaError::ProtocolError(_))));
+        assert!(matches!(net.ping(&member, &outsider), Err(KademliaError::ProtocolError(_))));
+
+        // the nonces survive a snapshot
+        let restored = Network::from_snapshot(net.snapshot());
+        assert_eq!(restored.nodes[&joined].pow_nonce, Some(nonce));
+    }
+
+    #[test]
+    fn random_peers_are_drawn_uniformly_across_contacts() {
+        let (net, _) = seeded(100, 39);
+        // bucket sizes differ, yet every contact should come up close to equally often
+        let sampler = net.nodes.values().max_by_key(|node| node.routing_table.len()).expect("nodes");
+        let mut rng = StdRng::seed_from_u64(40);
+        let contacts = sampler.routing_table.len();
+        let mut hits: HashMap<NodeId, usize> = HashMap::new();
+        for _ in 0..200 * contacts {
+            *hits.entry(sampler.random_peer(&mut rng).expect("has peers")).or_default() += 1;
+        }
+        let (fewest, most) = (hits.values().min().copied().unwrap_or(0), hits.values().max().copied().unwrap_or(0));
+        assert_eq!(hits.len(), contacts, "some contact was never sampled");
+        assert!(fewest > 120 && most < 280, "sampling is not uniform: {fewest}..{most} of 200");
+        let picked = sampler.random_peers(8, &mut rng);
+        assert_eq!(picked.iter().collect::<HashSet<_>>().len(), 8.min(contacts), "random_peers repeated a peer");
+    }
+
+    #[test]
+    fn an_exported_topology_draws_every_node_and_samples_within_budget() {
+        let (net, ids) = seeded(10, 41);
+        let topology = net.export_topology();
+        assert_eq!(topology.node_ids, ids);
+        let contacts: usize = net.nodes.values().map(|node| node.routing_table.len()).sum();
+        assert_eq!(topology.edges.len(), contacts);
+        let declared = |dot: &str| dot.lines().filter(|line| line.contains("[label=") && !line.contains("->")).count();
+        let dot = topology.to_dot();
+        assert_eq!(declared(&dot), 10);
+        assert_eq!(dot.lines().filter(|line| line.contains("->")).count(), contacts);
+        let sampled = topology.to_dot_sampled(20);
+        assert!(sampled.lines().filter(|line| line.contains("->")).count() <= 20);
+        assert!((1..10).contains(&declared(&sampled)), "sampling kept {} nodes", declared(&sampled));
+    }
+
+    #[test]
+    fn anti_entropy_travels_as_rpcs() {
+        let (mut net, ids) = seeded(4, 30);
+        let (a, b) = (ids[0], ids[1]);
+        net.store(&a, &a, NodeId::from_u8(1), b"ours".to_vec(), DEFAULT_TTL).expect("a is up");
+        net.store(&b, &b, NodeId::from_u8(2), b"theirs".to_vec(), DEFAULT_TTL).expect("b is up");
+        let before = net.stats;
+        assert_eq!(net.anti_entropy_sync(&a, &b), Ok(2));
+        assert_eq!(net.stats.total_store_rpcs - before.total_store_rpcs, 2);
+        assert_eq!(net.nodes[&b].storage_keys().count(), 2);
+
+        // a lost or dead peer gets nothing, and hands nothing over
+        net.store(&b, &b, NodeId::from_u8(3), b"late".to_vec(), DEFAULT_TTL).expect("b is up");
+        net.config.packet_loss_rate = 1.0;
+        assert_eq!(net.anti_entropy_sync(&a, &b), Err(KademliaError::Timeout(b)));
+        net.config.packet_loss_rate = 0.0;
+        net.kill_node(&b);
+        assert_eq!(net.anti_entropy_sync(&a, &b), Err(KademliaError::Timeout(b)));
+        assert_eq!(net.nodes[&a].storage_keys().count(), 2, "a read a dead node's storage");
+    }
+
+    #[test]
+    fn a_healed_partition_catches_up_through_anti_entropy() {
+        let (mut net, ids) = seeded(4, 8);
+        let (left, right) = ids.split_at(2);
+        net.partition(left.to_vec(), right.to_vec());
+        assert_eq!(net.ping(&left[0], &right[0]), Err(KademliaError::Partitioned(right[0])));
+        assert!(net.ping(&left[0], &left[1]).is_ok(), "a partition cut a link within one side");
+        net.iterative_store(&left[0], b"hello".to_vec(), b"world".to_vec(), DEFAULT_TTL);
+        let holds = |net: &Network, side: &[NodeId]| net.find_all_holders(b"hello").iter().filter(|id| side.contains(id)).count();
+        assert_eq!((holds(&net, left), holds(&net, right)), (2, 0), "a store crossed the partition");
+        net.heal_partition();
+        assert!(net.full_anti_entropy() >= right.len());
+        assert_eq!(holds(&net, right), right.len(), "the right half did not catch up");
+    }
+
+    /// Share of the contacts in live nodes' tables that point at dead nodes, after a seeded
+    /// network under `policy` goes through rounds of crashes, joins and lookups with 20% of
+    /// RPCs lost (so live contacts miss answers too)
+    fn dead_contact_ratio(policy: EvictionPolicy) -> f64 {
+        let mut net: Network = Network::with_config(NetworkConfig::builder().eviction_policy(policy).build());
+        net.rng = StdRng::seed_from_u64(42);
+        net.transport_rng = StdRng::seed_from_u64(42);
+        net.populate(80);
+        net.config.packet_loss_rate = 0.2;
+        let mut dead: HashSet<NodeId> = HashSet::new();
+        for _ in 0..6 {
+            let mut live: Vec<NodeId> = net.nodes.keys().filter(|id| !dead.contains(id)).copied().collect();
+            live.sort();
+            for id in live.choose_multiple(&mut net.rng, live.len() / 10).copied().collect::<Vec<_>>() {
+                net.kill_node(&id);
+                dead.insert(id);
+            }
+            live.retain(|id| !dead.contains(id));
+            for _ in 0..8 {
+                let known = *live.choose(&mut net.rng).expect("live nodes remain");
+                if let Ok(id) = net.add_and_bootstrap(&known) {
+                    live.push(id);
+                }
+            }
+            for _ in 0..40 {
+                let start = *live.choose(&mut net.rng).expect("live nodes remain");
+                let target = NodeId::random_with(&mut net.rng);
+                net.iterative_find_node(&start, &target);
+            }
+        }
+        let contacts: Vec<bool> = net
+            .nodes
+            .values()
+            .filter(|node| node.alive)
+            .flat_map(|node| node.routing_table.peers().map(|peer| dead.contains(peer)))
+            .collect();
+        contacts.iter().filter(|&&is_dead| is_dead).count() as f64 / contacts.len() as f64
+    }
+    #[test]
+    fn evicting_without_a_ping_keeps_more_dead_contacts() {
+        let (pinging, oldest_first) = (dead_contact_ratio(EvictionPolicy::LruPing), dead_contact_ratio(EvictionPolicy::OldestFirst));
+        assert!(oldest_first > pinging, "{:.1}% dead with OldestFirst, {:.1}% with LruPing", 100.0 * oldest_first, 100.0 * pinging);
+    }
+
+    #[test]
+    fn a_node_placed_on_a_keys_hash_holds_that_key() {
+        let mut net: Network = Network::new();
+        let exact = net.key_to_id(b"pinned");
+        let spread: Vec<NodeId> = (1..=8).map(|i| NodeId::from_u8(i * 0x1f)).collect();
+        assert!(spread[1].0[0] == 0x3e && spread[1].0[1..].iter().all(|&b| b == 0));
+        for id in spread.iter().chain([&exact]) {
+            assert_eq!(net.add_node_with_id(*id), Ok(*id));
+        }
+        for id in spread[1..].iter().chain([&exact]) {
+            net.bootstrap_node(*id, spread[0]).expect("the first node is alive");
+        }
+        net.iterative_store(&spread[3], b"pinned".to_vec(), b"here".to_vec(), DEFAULT_TTL);
+        assert!(net.find_all_holders(b"pinned").contains(&exact), "the key's own node missed the store");
+        assert_eq!(net.add_node_with_id(spread[0]), Err(KademliaError::DuplicateNodeId(spread[0])));
+    }
+
+    #[test]
+    fn routing_events_account_for_every_contact_through_churn() {
+        let log = Arc::new(RoutingEventLog::default());
+        let mut net: Network = Network::with_seed(44);
+        net.set_routing_events(log.clone());
+        net.populate(40);
+        let mut rng = StdRng::seed_from_u64(44);
+        let failed = net.simulate_churn(0.2, &mut rng);
+        assert_eq!(failed.len(), 8);
+        for id in &failed {
+            net.evict_peer_from_all(id);
+        }
+        assert!(log.counts().get(&RoutingChange::Evicted).copied().unwrap_or(0) > 0);
+        // every contact still in a table arrived through an event that no eviction has undone
+        let events = log.events();
+        for node in net.nodes.values() {
+            let net_added: i64 = events
+                .iter()
+                .filter(|e| e.owner == node.id)
+                .map(|e| match e.change {
+                    RoutingChange::Added | RoutingChange::Promoted => 1,
+                    RoutingChange::Evicted => -1,
+                    RoutingChange::Refreshed | RoutingChange::Stale => 0,
+                })
+                .sum();
+            assert_eq!(net_added, node.routing_table.len() as i64, "events disagree with {}'s table", node.id.short());
+        }
+    }
+
+    #[test]
+    fn a_batch_reaches_each_keys_closest_nodes_for_fewer_rpcs() {
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..100).map(|i| (format!("batch-{i}").into_bytes(), vec![i as u8; 16])).collect();
+        let cost = |batched: bool| {
+            let mut net: Network = Network::with_config(NetworkConfig::default());
+            net.rng = StdRng::seed_from_u64(45);
+            net.populate(60);
+            let mut ids: Vec<NodeId> = net.nodes.keys().copied().collect();
+            ids.sort();
+            net.config.latency_model = LatencyModel::Fixed(Duration::from_millis(50));
+            net.reset_stats();
+            let started = net.now();
+            if batched {
+                assert_eq!(net.iterative_store_batch(&ids[0], entries.clone()), entries.len() * K);
+            } else {
+                for (key, value) in &entries {
+                    net.iterative_store(&ids[0], key.clone(), value.clone(), DEFAULT_TTL);
+                }
+            }
+            for (key, _) in &entries {
+                let mut holders = net.find_all_holders(key);
+                holders.sort();
+                let mut closest = net.top_k_closest_to_key(key, K);
+                closest.sort();
+                assert_eq!(holders, closest, "batched: {batched}");
+            }
+            (net.stats().total_store_rpcs, net.stats().total_find_node_rpcs, net.now() - started)
+        };
+        let (one_by_one, batched) = (cost(false), cost(true));
+        assert!(batched.0 <= 60 && one_by_one.0 == 100 * K as u64, "{one_by_one:?} vs {batched:?}");
+        assert!(batched.1 * 8 < one_by_one.1, "grouping saved too few lookups: {one_by_one:?} vs {batched:?}");
+        assert!(batched.2 * 4 < one_by_one.2, "{one_by_one:?} vs {batched:?}");
+    }
+
+    #[test]
+    fn a_batch_counts_only_the_entries_its_targets_accept() {
+        let mut net: Network = Network::with_config(NetworkConfig::builder().storage_limit_bytes(256).build());
+        net.rng = StdRng::seed_from_u64(46);
+        let [a, b, c] = [net.add_node(), net.add_node(), net.add_node()];
+        let entries = vec![(b"fits".to_vec(), vec![0; 16]), (b"too big".to_vec(), vec![0; 300])];
+        assert_eq!(net.store_batch(&a, &b, entries.clone()), Ok(1));
+        net.kill_node(&c);
+        assert_eq!(net.store_batch(&a, &c, entries.clone()), Err(KademliaError::Timeout(c)));
+        // a and b take the entry that fits, and neither takes the other
+        assert_eq!(net.iterative_store_batch(&a, entries), 2);
+    }
+
+    #[test]
+    fn a_new_node_can_start_from_a_neighbors_table() {
+        let (mut net, ids) = seeded(50, 452);
+        let newcomer = net.add_node();
+        let cloned = net.clone_routing(&ids[0], &newcomer).expect("both nodes exist");
+        assert!(cloned.inserted > 0, "{cloned:?}");
+        assert!(net.nodes[&newcomer].routing_table.is_well_formed());
+        let source = &net.nodes[&ids[0]];
+        assert_eq!(cloned.inserted + cloned.cached + cloned.rejected, source.routing_table.len());
+        assert!(net.nodes[&newcomer].routing_table.peers().all(|peer| source.has_peer(peer)));
+    }
+
+    #[test]
+    fn a_replication_audit_counts_every_holder_past_the_first() {
+        let mut net: Network = Network::with_config(NetworkConfig::builder().k(5).build());
+        net.rng = StdRng::seed_from_u64(46);
+        net.populate(40);
+        let auditor = *net.nodes.keys().min().expect("nodes");
+        net.iterative_store(&auditor, b"audited".to_vec(), b"value".to_vec(), DEFAULT_TTL);
+        let mut holders = net.find_all_value_holders(&auditor, b"audited");
+        holders.sort();
+        let mut truth = net.find_all_holders(b"audited");
+        truth.sort();
+        assert_eq!(holders, truth, "the lookup stopped short of some holders");
+        assert_eq!(net.replication_count(&auditor, b"audited"), 5);
+        for holder in holders.iter().filter(|h| **h != auditor).take(2) {
+            net.fail_node(holder);
+        }
+        assert_eq!(net.replication_count(&auditor, b"audited"), 3, "two crashed holders should leave three copies");
+    }
+
+    #[test]
+    fn a_contact_cap_shrinks_routing_memory() {
+        let bytes = |config: NetworkConfig| {
+            let mut net: Network = Network::with_config(config);
+            net.rng = StdRng::seed_from_u64(46);
+            net.populate(200);
+            net.routing_bytes()
+        };
+        assert!(bytes(NetworkConfig::builder().max_contacts(30).build()) < bytes(NetworkConfig::default()));
+    }
+
+    #[test]
+    fn junk_answers_are_sanitized_before_they_reach_the_table() {
+        // one node answers every find_node with 100 junk IDs, duplicates, the requester's own ID
+        // and itself among them
+        let (mut net, ids) = seeded(30, 47);
+        let (requester, liar) = (ids[0], ids[1]);
+        let mut junk: Vec<NodeId> = (0..48).map(|_| NodeId::random_with(&mut net.rng)).collect();
+        junk.extend(junk.clone());
+        junk.extend([requester, requester, liar, liar]);
+        net.nodes.get_mut(&liar).expect("liar").forge_find_node_answers(junk.clone());
+
+        let raw = net.find_node(&requester, &liar, &liar, net.config.k).expect("liar answers");
+        assert_eq!(raw.len(), 100);
+        let kept = net.config.find_node.sanitize(&requester, &liar, raw, net.config.k);
+        assert!(kept.len() <= net.config.k);
+        assert!(!kept.contains(&requester));
+        assert_eq!(kept.iter().collect::<HashSet<_>>().len(), kept.len(), "duplicates should be dropped");
+
+        let found = net.iterative_find_node(&requester, &liar);
+        assert!(found.len() <= net.config.k);
+        assert!(!found.iter().any(|id| junk[..48].contains(id)), "junk IDs never answer");
+        let table = &net.nodes[&requester].routing_table;
+        assert!(table.is_well_formed());
+        assert!(junk.iter().filter(|id| table.contact(id).is_some()).count() <= net.config.k, "junk should not flood the table");
+    }
+
+    #[test]
+    fn a_router_over_a_mem_transport_matches_the_network_lookups() {
+        // two copies of one seeded network: the same lookups pick the same nodes at the same RPC cost
+        let twin = || {
+            let (mut net, ids) = seeded(60, 48);
+            net.reset_stats();
+            (net, ids)
+        };
+        let ((mut by_network, starts), (mut by_router, _)) = (twin(), twin());
+        let router = Router::new(by_router.config);
+        for (i, start) in starts.iter().take(20).enumerate() {
+            let target = NodeId::from_u8(i as u8 * 13);
+            let routed = router.iterative_find_node(&mut MemTransport::new(&mut by_router), *start, target);
+            assert_eq!(by_network.iterative_find_node(start, &target), routed);
+        }
+        assert_eq!(by_network.stats().total_find_node_rpcs, by_router.stats().total_find_node_rpcs);
+    }
+
+    #[test]
+    fn nodes_joining_through_one_node_can_store_and_find_between_any_pair() {
+        let mut joined: Network = Network::with_seed(49);
+        let first = joined.add_node();
+        let mut reports = Vec::new();
+        for _ in 1..200 {
+            let id = joined.add_node();
+            reports.push(joined.join(&id, &first).expect("the first node is alive"));
+        }
+        assert!(reports.iter().all(|report| report.contacts_learned > 0 && report.lookups > 0));
+
+        let mut members: Vec<NodeId> = joined.nodes.keys().copied().collect();
+        members.sort();
+        for i in 0..20 {
+            let (writer, reader) = (*members.choose(&mut joined.rng).expect("nodes"), *members.choose(&mut joined.rng).expect("nodes"));
+            let key = format!("joined-{i}").into_bytes();
+            joined.iterative_store(&writer, key.clone(), vec![i], DEFAULT_TTL);
+            assert_eq!(joined.iterative_find_value(&reader, &key), Ok(vec![i]), "values should be found between any two joined nodes");
+        }
+    }
+
+    #[test]
+    fn the_four_rpcs_go_through_a_mem_transport_as_a_trait_object() {
+        let (mut net, ids) = seeded(60, 48);
+        let (keeper, asker, bystander) = (ids[0], ids[1], ids[2]);
+        let transport: &mut dyn RpcTransport = &mut MemTransport::new(&mut net);
+        assert_eq!(transport.ping(asker, keeper), Ok(true));
+        transport.store(asker, keeper, b"routed".to_vec(), b"value".to_vec()).expect("keeper is alive");
+        assert_eq!(transport.find_value(asker, keeper, b"routed", K), Ok(FindValueResult::Value(b"value".to_vec())));
+        let Ok(FindValueResult::Nodes(contacts)) = transport.find_value(asker, bystander, b"routed", 3) else {
+            panic!("the bystander has no copy, so it should answer with contacts");
+        };
+        assert_eq!(contacts.len(), 3, "a miss answers with as many contacts as were asked for");
+        assert!(!transport.find_node(asker, bystander, keeper, K).expect("bystander is alive").is_empty());
+        assert_eq!((net.stats().total_value_hits, net.stats().total_value_misses), (1, 1), "a miss is still one find_value RPC");
+    }
+
+    #[test]
+    fn a_health_sweep_flags_contacts_of_crashed_nodes_until_a_refresh_clears_them() {
+        // 30% of nodes crash and the rest carry on with lookups for 20 minutes
+        let (mut swept, mut everyone) = seeded(120, 49);
+        let mut churn_rng = StdRng::seed_from_u64(49);
+        everyone.shuffle(&mut churn_rng);
+        let (crashed, survivors) = everyone.split_at(everyone.len() * 3 / 10);
+        let crashed: HashSet<NodeId> = crashed.iter().copied().collect();
+        for id in &crashed {
+            swept.kill_node(id);
+        }
+        for minute in 0..20 {
+            swept.advance(Duration::from_secs(60));
+            for start in survivors.iter().skip(minute % 4).step_by(4) {
+                let target = NodeId::random_with(&mut churn_rng);
+                let _ = swept.iterative_find_node(start, &target);
+            }
+        }
+        let dead_contacts = |net: &Network| -> usize {
+            let live = net.nodes.values().filter(|node| node.alive);
+            live.map(|node| node.routing_table.peers().filter(|peer| crashed.contains(peer)).count()).sum()
+        };
+
+        // then everyone goes quiet until the crashed nodes have been silent a whole REFRESH_INTERVAL
+        swept.advance(REFRESH_INTERVAL - Duration::from_secs(20 * 60));
+        let sweep = swept.health_sweep(usize::MAX);
+        assert_eq!(sweep.len(), survivors.len());
+        assert!(sweep.windows(2).all(|w| w[0].problems() >= w[1].problems()), "worst first");
+        let flagged: usize = sweep.iter().map(|report| report.stale_contacts.iter().filter(|peer| crashed.contains(peer)).count()).sum();
+        let dead_before = dead_contacts(&swept);
+        assert_eq!(flagged, dead_before, "every contact of a crashed node should be flagged as silent");
+        swept.tick(REFRESH_INTERVAL);
+        assert!(dead_contacts(&swept) < dead_before, "refreshing should clear contacts of crashed nodes");
+    }
+
+    #[test]
+    fn find_node_answers_are_clamped_to_the_responders_cap() {
+        let capped_answers = FindNodeOptions { max_count: Some(5), ..FindNodeOptions::default() };
+        let mut counted: Network = Network::with_config(NetworkConfig::builder().find_node(capped_answers).build());
+        counted.rng = StdRng::seed_from_u64(50);
+        counted.populate(100);
+        let mut ids: Vec<NodeId> = counted.nodes.keys().copied().collect();
+        ids.sort();
+        let (asker, responder) = (ids[0], ids[1]);
+        let sizes: Vec<usize> = [3, 8, 100]
+            .iter()
+            .map(|&count| counted.find_node(&asker, &responder, &NodeId::random(), count).expect("responder is alive").len())
+            .collect();
+        assert_eq!(sizes, vec![3, 5, 5], "answers should hold what was asked for, up to the cap of 5");
+    }
+
+    #[test]
+    fn lookups_find_the_closest_nodes_whatever_count_they_ask_for() {
+        // asking for 3 still finds the closest 3, though nobody mentions the rest once the
+        // closest answer
+        let (mut net, ids) = seeded(100, 50);
+        let mut rng = StdRng::seed_from_u64(50);
+        let mut exact = [0; 3];
+        for _ in 0..20 {
+            let target = NodeId::random_with(&mut rng);
+            let mut truth = ids.clone();
+            distance::sort_by_distance(&target, &mut truth);
+            truth.truncate(K);
+            for (slot, count) in [3, K, 2 * K].into_iter().enumerate() {
+                let found = net.iterative_find_node_requesting(&ids[0], &target, count);
+                exact[slot] += usize::from(truth[..count.min(K)].iter().all(|id| found.contains(id)));
+            }
+        }
+        assert!(exact.iter().all(|&hits| hits >= 18), "{exact:?} of 20");
+    }
+
+    #[test]
+    fn find_node_options_decide_whether_the_requester_and_responder_are_listed() {
+        // in a chain A -> B -> C, B's answer to A about C lists A itself back unless the
+        // requester is excluded, and lists B only if the responder includes itself
+        let answer = |options: FindNodeOptions| {
+            let mut chain: Network = Network::with_seed(31);
+            chain.config.find_node = options;
+            let [a, b, c] = [chain.add_node(), chain.add_node(), chain.add_node()];
+            let _ = chain.ping(&b, &c);
+            let answer = chain.find_node(&a, &b, &c, K).expect("B is alive");
+            (answer.contains(&a), answer.contains(&b), answer.contains(&c))
+        };
+        let bare = FindNodeOptions { include_self: false, exclude_requester: false, ..FindNodeOptions::default() };
+        assert_eq!(answer(bare), (true, false, true));
+        assert_eq!(answer(FindNodeOptions::default()), (false, true, true));
+    }
+
+    #[test]
+    fn a_join_checks_only_the_buckets_it_touched() {
+        let mut net: Network = Network::with_config(NetworkConfig::builder().k(2).build());
+        net.rng = StdRng::seed_from_u64(6);
+        net.populate(30);
+        let mut ids: Vec<NodeId> = net.nodes.keys().copied().collect();
+        ids.sort();
+        // a node with candidates waiting behind an incumbent that has since crashed
+        let (owner, index) = ids
+            .iter()
+            .find_map(|id| {
+                let table = &net.nodes[id].routing_table;
+                (0..table.buckets.len()).find(|&i| table.replacement_len(i) > 0).map(|i| (*id, i))
+            })
+            .expect("some bucket overflowed");
+        let incumbent = net.nodes[&owner].routing_table.buckets[index].peers[0].id;
+        net.kill_node(&incumbent);
+
+        let far = ids.iter().copied().find(|id| *id != owner && *id != incumbent && !net.nodes[id].has_peer(&incumbent)).expect("a node that never met it");
+        let newcomer = net.add_and_bootstrap(&far).expect("bootstrap peer is alive");
+        assert_ne!(net.nodes[&owner].routing_table.waiting_in(&newcomer), Some(index));
+        assert!(net.nodes[&owner].has_peer(&incumbent), "a bucket the join never touched is not checked");
+        net.run_eviction_checks();
+        assert!(!net.nodes[&owner].has_peer(&incumbent));
+    }
+
+    #[test]
+    fn values_stored_among_bootstrapped_nodes_survive_churn() {
+        let mut net: Network = Network::with_seed(16);
+        let seed = net.add_node();
+        let joined: Vec<NodeId> = (1..20).map(|_| net.add_and_bootstrap(&seed).expect("the seed is alive")).collect();
+        assert!(joined.iter().all(|id| net.nodes[id].routing_table.len() > 1), "each joiner should meet more than the seed");
+        net.iterative_store(&joined[0], b"before churn".to_vec(), b"kept".to_vec(), DEFAULT_TTL);
+        for id in joined.iter().step_by(4) {
+            net.kill_node(id);
+        }
+        let mut live: Vec<NodeId> = net.nodes.values().filter(|node| node.alive).map(|node| node.id).collect();
+        live.sort();
+        for start in &live {
+            assert_eq!(net.iterative_find_value(start, b"before churn"), Ok(b"kept".to_vec()), "from {start}");
+        }
+    }
+
+    #[test]
+    fn pings_before_an_eviction_are_counted_apart() {
+        let mut net: Network = Network::with_config(NetworkConfig::builder().k(1).bucket_count(1).build());
+        let [a, b, c] = [0x00, 0x80, 0x40].map(|byte| net.add_node_with_id(NodeId::from_u8(byte)).expect("fresh ID"));
+        net.ping(&a, &b).expect("b is up");
+        // a's only slot is taken, so c's answer makes a check on b first
+        net.ping(&a, &c).expect("c is up");
+        assert_eq!((net.stats().total_pings, net.stats().eviction_pings), (2, 1));
+        assert!(net.nodes[&a].has_peer(&b), "b answered, so it keeps its slot");
+    }
+
+    #[test]
+    fn every_rpc_error_has_a_way_to_happen() {
+        let mut net: Network = Network::with_config(NetworkConfig::builder().k(3).storage_limit_bytes(256).build());
+        net.rng = StdRng::seed_from_u64(10);
+        let [a, b, c, d] = [net.add_node(), net.add_node(), net.add_node(), net.add_node()];
+        let (stranger, key) = (NodeId::from_u8(0x5a), NodeId::from_u8(0x77));
+        let errors = [
+            net.ping(&a, &stranger).map(|_| ()),
+            net.ping(&stranger, &a).map(|_| ()),
+            net.store(&a, &b, key, vec![0; 300], DEFAULT_TTL),
+            net.find_value(&a, &b, &key).map(|_| ()),
+            net.add_node_with_id(a).map(|_| ()),
+            {
+                net.partition(vec![a], vec![c]);
+                net.ping(&a, &c).map(|_| ())
+            },
+            {
+                net.kill_node(&d);
+                net.ping(&a, &d).map(|_| ())
+            },
+        ];
+        let owner = NodeIdentity::generate(&mut StdRng::seed_from_u64(10));
+        net.store_mutable(&a, &b, owner.sign_value(2, b"v2".to_vec()), DEFAULT_TTL).expect("first version");
+        let mut forged = owner.sign_value(3, b"v3".to_vec());
+        forged.value = b"evil".to_vec();
+        let signed = [
+            net.store_mutable(&a, &b, forged, DEFAULT_TTL),
+            net.store_mutable(&a, &b, owner.sign_value(1, b"v1".to_vec()), DEFAULT_TTL),
+            net.store_immutable(&a, &b, key, b"bytes".to_vec(), DEFAULT_TTL),
+        ];
+        // with k = 3, and one of the others dead and one cut off, no store reaches four nodes
+        let Err(KademliaError::InsufficientReplicas { stored, required: 4 }) =
+            net.iterative_store_with_replication(&a, b"key".to_vec(), b"v".to_vec(), 4)
+        else {
+            panic!("four replicas among four nodes, two of them unreachable");
+        };
+        assert!(stored < 4);
+        let messages: Vec<String> = errors.into_iter().chain(signed).map(|result| result.expect_err("each call fails").to_string()).collect();
+        let expected = [
+            format!("node {stranger} not found"),
+            format!("protocol error: unknown sender {stranger}"),
+            format!("node {b} has no room to store"),
+            "key not found".to_string(),
+            format!("ID {a} is already in use"),
+            format!("node {c} is cut off by a partition"),
+            format!("node {d} timed out"),
+            "invalid signature".to_string(),
+            "sequence number 1 is not newer than the stored 2".to_string(),
+            "value does not hash to its key".to_string(),
+        ];
+        assert_eq!(messages, expected);
+    }
+
+    #[test]
+    fn density_counts_peers_by_shared_prefix_whatever_the_table_layout() {
+        let (binary, _) = seeded(80, 25);
+        let mut wide: Network = Network::with_config(NetworkConfig::builder().bits_per_bucket(4).build());
+        wide.rng = StdRng::seed_from_u64(25);
+        wide.populate(80);
+        for net in [&binary, &wide] {
+            let mut summed = vec![0; NodeId::<ID_BYTES>::BITS + 1];
+            for node in net.nodes.values() {
+                let report = node.density_report();
+                let mut expected = vec![0; NodeId::<ID_BYTES>::BITS + 1];
+                for peer in node.routing_table.peers() {
+                    expected[node.id.common_prefix_len(peer)] += 1;
+                }
+                assert_eq!(report.peers_per_bucket, expected);
+                summed.iter_mut().zip(&expected).for_each(|(sum, count)| *sum += count);
+            }
+            let total = net.density_report();
+            assert_eq!((total.nodes, total.peers_per_bucket), (80, summed));
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_rpcs_leave_full_buckets_to_into_network() {
+        let mut net: Network = Network::with_config(NetworkConfig::builder().k(1).bucket_count(1).build());
+        let [a, b, c] = [0x00, 0x80, 0x40].map(|byte| net.add_node_with_id(NodeId::from_u8(byte)).expect("fresh ID"));
+        net.ping(&a, &b).expect("b is up");
+        net.nodes.get_mut(&c).expect("c was added").record_contact(&a);
+        net.kill_node(&b);
+        net.reset_stats();
+        let sim = SimNetwork::from_network(net);
+        // c's query fills a's only slot; nobody pings b while the lookup holds the locks
+        sim.parallel_iterative_find_node(&c, &NodeId::from_u8(0x01));
+        let net = sim.into_network();
+        assert_eq!(net.stats().eviction_pings, 1);
+        assert!(net.nodes[&a].has_peer(&c) && !net.nodes[&a].has_peer(&b), "b is down, so c takes its slot");
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn a_parallel_lookup_finds_the_same_closest_nodes_as_a_sequential_one() {
+        let target = NodeId::random_with(&mut StdRng::seed_from_u64(21));
+        let (mut net, ids) = seeded(60, 21);
+        let sequential = net.iterative_find_node(&ids[0], &target);
+        let (same, _) = seeded(60, 21);
+        let sim = SimNetwork::from_network(same);
+        assert_eq!(sim.parallel_iterative_find_node(&ids[0], &target), sequential);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn a_parallel_lookup_finds_a_stored_value_and_lets_the_clock_run() {
+        let (mut net, ids) = seeded(60, 22);
+        net.iterative_store(&ids[0], b"parallel".to_vec(), b"found".to_vec(), DEFAULT_TTL);
+        net.config.latency_model = LatencyModel::Fixed(Duration::from_millis(50));
+        let before = net.now();
+        let sim = SimNetwork::from_network(net);
+        assert_eq!(sim.parallel_iterative_find_value(&ids[59], b"parallel"), Ok(b"found".to_vec()));
+        assert_eq!(sim.parallel_iterative_find_value(&ids[59], b"missing"), Err(KademliaError::KeyNotFound));
+        assert!(sim.into_network().now() >= before + Duration::from_millis(100), "each round waits on its RPCs");
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tracing_tests {
+    use super::*;
+    use tracing_test::traced_test;
+
+    #[test]
+    #[traced_test]
+    fn lookups_open_their_spans_and_log_each_rpc_inside_them() {
+        let mut net: Network = Network::new_with_nodes_seeded(30, 29);
+        let ids: Vec<NodeId> = net.nodes.keys().copied().collect();
+        let target = net.key_to_id(b"traced");
+        net.iterative_find_node(&ids[0], &target);
+        net.iterative_find_value(&ids[1], b"traced").expect_err("nothing was stored");
+
+        // every RPC a lookup sends is logged inside the lookup's span, with IDs in hex
+        let find_node = format!("iterative_find_node{{start={} target={target}}}: kademlia: find_node from={}", ids[0], ids[0]);
+        let find_value = format!("iterative_find_value{{start={} key={target}}}: kademlia: find_value from={}", ids[1], ids[1]);
+        assert!(logs_contain(&find_node));
+        assert!(logs_contain(&find_value));
+        assert!(logs_contain("result=Err(KeyNotFound)"));
+
+    }
+}
+
+/// A find_value answer: the value, or else the responder's closest contacts to the key
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FindValueResult<const N: usize = ID_BYTES> {
+    Value(Vec<u8>),
+    Nodes(Vec<NodeId<N>>),
+}
+
+/// The RPCs a lookup sends, and what it tells whoever carries them about how they went.
+/// `MemTransport` carries them through an in-memory `Network`; an async, TCP or QUIC
+/// transport would implement the same calls to run the same `Router`.
+pub trait RpcTransport<const N: usize = ID_BYTES> {
+    fn ping(&mut self, from: NodeId<N>, to: NodeId<N>) -> Result<bool, KademliaError<N>>;
+
+    /// Ask `to` for up to `count` of its contacts closest to `target`
+    fn find_node(&mut self, from: NodeId<N>, to: NodeId<N>, target: NodeId<N>, count: usize) -> Result<Vec<NodeId<N>>, KademliaError<N>>;
+
+    /// Ask `to` for the value under `key`, or else up to `count` of its contacts closest to it
+    fn find_value(&mut self, from: NodeId<N>, to: NodeId<N>, key: &[u8], count: usize) -> Result<FindValueResult<N>, KademliaError<N>>;
+
+    /// Store `value` under `key` at `to` for the default TTL
+    fn store(&mut self, from: NodeId<N>, to: NodeId<N>, key: Vec<u8>, value: Vec<u8>) -> Result<(), KademliaError<N>>;
+
+    /// The contacts `id` starts a lookup from
+    fn known_peers(&self, id: &NodeId<N>) -> Vec<NodeId<N>>;
+
+    /// The current time, as lookups stamp their rounds
+    fn now(&self) -> Instant;
+
+    /// Round-trip time of the latest RPC
+    fn last_latency(&self) -> Duration;
+
+    /// An RPC from `from` to `to` failed with `err`
+    fn rpc_failed(&mut self, _from: &NodeId<N>, _to: &NodeId<N>, _err: &KademliaError<N>) {}
+
+    /// A lookup run by `id` was told about `peers`
+    fn peers_learned(&mut self, _id: &NodeId<N>, _peers: &[NodeId<N>]) {}
+
+    /// A lookup round, whose queries ran concurrently, took `elapsed`
+    fn round_finished(&mut self, _elapsed: Duration) {}
+
+    /// A lookup run by `id` for `target` is over
+    fn lookup_finished(&mut self, _id: &NodeId<N>, _target: &NodeId<N>) {}
+}
+
+/// A `Network` carries RPCs between its own nodes: each is forwarded (and counted) as the
+/// network's own methods do, and byte keys are hashed with the network's hasher
+impl<const N: usize> RpcTransport<N> for Network<N> {
+    fn ping(&mut self, from: NodeId<N>, to: NodeId<N>) -> Result<bool, KademliaError<N>> {
+        self.ping(&from, &to)
+    }
+
+    fn find_node(&mut self, from: NodeId<N>, to: NodeId<N>, target: NodeId<N>, count: usize) -> Result<Vec<NodeId<N>>, KademliaError<N>> {
+        self.find_node(&from, &to, &target, count)
+    }
+
+    /// One RPC, as in the paper: a responder without the value answers with its contacts
+    fn find_value(&mut self, from: NodeId<N>, to: NodeId<N>, key: &[u8], count: usize) -> Result<FindValueResult<N>, KademliaError<N>> {
+        let key_id = self.key_to_id(key);
+        self.stats.total_find_value_rpcs += 1;
+        let result = self.deliver(&from, &to, |node| match node.rpc_find_value(&from, &key_id) {
+            Some(value) => FindValueResult::Value(value),
+            None => FindValueResult::Nodes(node.rpc_find_node(&from, &key_id, count)),
+        });
+        match &result {
+            Ok(FindValueResult::Value(_)) => self.stats.total_value_hits += 1,
+            Ok(FindValueResult::Nodes(_)) => self.stats.total_value_misses += 1,
+            Err(_) => {}
+        }
+        result
+    }
+
+    fn store(&mut self, from: NodeId<N>, to: NodeId<N>, key: Vec<u8>, value: Vec<u8>) -> Result<(), KademliaError<N>> {
+        let key_id = self.key_to_id(&key);
+        self.store(&from, &to, key_id, value, DEFAULT_TTL)
+    }
+
+    fn known_peers(&self, id: &NodeId<N>) -> Vec<NodeId<N>> {
+        self.snapshot_peers(id)
+    }
+
+    fn now(&self) -> Instant {
+        self.now()
+    }
+
+    fn last_latency(&self) -> Duration {
+        self.last_latency
+    }
+
+    fn rpc_failed(&mut self, from: &NodeId<N>, to: &NodeId<N>, err: &KademliaError<N>) {
+        self.note_failure(from, to, err);
+    }
+
+    fn peers_learned(&mut self, id: &NodeId<N>, peers: &[NodeId<N>]) {
+        self.record_contacts(id, peers);
+    }
+
+    fn round_finished(&mut self, elapsed: Duration) {
+        self.clock.advance(elapsed);
+    }
+
+    /// The lookup counts as a refresh of the bucket the target falls in
+    fn lookup_finished(&mut self, id: &NodeId<N>, target: &NodeId<N>) {
+        let now = self.now();
+        if let Some(node) = self.nodes.get_mut(id) {
+            node.routing_table.mark_refreshed(target, now);
+        }
+    }
+}
+
+/// `RpcTransport` over a borrowed `Network`, which does the work; lets a caller hand out
+/// `&mut dyn RpcTransport` while keeping the network
+pub struct MemTransport<'a, const N: usize = ID_BYTES> {
+    network: &'a mut Network<N>,
+}
+
+impl<'a, const N: usize> MemTransport<'a, N> {
+    pub fn new(network: &'a mut Network<N>) -> Self {
+        Self { network }
+    }
+}
+
+impl<const N: usize> RpcTransport<N> for MemTransport<'_, N> {
+    fn ping(&mut self, from: NodeId<N>, to: NodeId<N>) -> Result<bool, KademliaError<N>> {
+        RpcTransport::ping(&mut *self.network, from, to)
+    }
+
+    fn find_node(&mut self, from: NodeId<N>, to: NodeId<N>, target: NodeId<N>, count: usize) -> Result<Vec<NodeId<N>>, KademliaError<N>> {
+        RpcTransport::find_node(&mut *self.network, from, to, target, count)
+    }
+
+    fn find_value(&mut self, from: NodeId<N>, to: NodeId<N>, key: &[u8], count: usize) -> Result<FindValueResult<N>, KademliaError<N>> {
+        RpcTransport::find_value(&mut *self.network, from, to, key, count)
+    }
+
+    fn store(&mut self, from: NodeId<N>, to: NodeId<N>, key: Vec<u8>, value: Vec<u8>) -> Result<(), KademliaError<N>> {
+        RpcTransport::store(&mut *self.network, from, to, key, value)
+    }
+
+    fn known_peers(&self, id: &NodeId<N>) -> Vec<NodeId<N>> {
+        RpcTransport::known_peers(&*self.network, id)
+    }
+
+    fn now(&self) -> Instant {
+        RpcTransport::now(&*self.network)
+    }
+
+    fn last_latency(&self) -> Duration {
+        RpcTransport::last_latency(&*self.network)
+    }
+
+    fn rpc_failed(&mut self, from: &NodeId<N>, to: &NodeId<N>, err: &KademliaError<N>) {
+        RpcTransport::rpc_failed(&mut *self.network, from, to, err);
+    }
+
+    fn peers_learned(&mut self, id: &NodeId<N>, peers: &[NodeId<N>]) {
+        RpcTransport::peers_learned(&mut *self.network, id, peers);
+    }
+
+    fn round_finished(&mut self, elapsed: Duration) {
+        RpcTransport::round_finished(&mut *self.network, elapsed);
+    }
+
+    fn lookup_finished(&mut self, id: &NodeId<N>, target: &NodeId<N>) {
+        RpcTransport::lookup_finished(&mut *self.network, id, target);
+    }
+}
+
+/// The iterative lookup algorithm on its own: which nodes to ask in each round, what to
+/// keep of their answers and when to stop, over whatever `RpcTransport` carries the RPCs
+#[derive(Clone, Copy, Debug)]
+pub struct Router {
+    config: NetworkConfig,
+    count: usize, // nodes each find_node asks for
+}
+
+impl Router {
+    /// A router whose lookups ask each node for k contacts
+    pub fn new(config: NetworkConfig) -> Self {
+        Self { config, count: config.k }
+    }
+
+    /// The same router, asking each node for `count` contacts instead (e.g. more while
+    /// joining, to fill a fresh table faster, or fewer to save bandwidth). The lookup still
+    /// looks for the k closest nodes.
+    pub fn requesting(self, count: usize) -> Self {
+        Self { count, ..self }
+    }
+
+    /// Find the k nodes closest to `target`, starting from `start`'s contacts (done once each
+    /// of the k closest found so far has answered, or after `max_steps` rounds)
+    pub fn iterative_find_node<const N: usize, T: RpcTransport<N>>(
+        &self,
+        transport: &mut T,
+        start: NodeId<N>,
+        target: NodeId<N>,
+    ) -> Vec<NodeId<N>> {
+        self.traced_iterative_find_node(transport, start, target).0
+    }
+
+    /// `iterative_find_node` that also records each query it made. A lookup for an existing
+    /// node's ID ends after the round in which that node answers.
+    pub fn traced_iterative_find_node<const N: usize, T: RpcTransport<N>>(
+        &self,
+        transport: &mut T,
+        start: NodeId<N>,
+        target: NodeId<N>,
+    ) -> (Vec<NodeId<N>>, QueryTrace<N>) {
+        let mut trace = QueryTrace::default();
+        let mut queried: HashSet<NodeId<N>> = HashSet::new();
+        let mut previously_closest: Vec<NodeId<N>> = Vec::new();
+        let mut shortlist = ClosestQueue::new(target, self.config.k);
+        // every node ever considered, so merging neighbors needs no scan of the shortlist
+        let mut seen: HashSet<NodeId<N>> = HashSet::new();
+        for peer in transport.known_peers(&start).into_iter().chain([start]) {
+            if seen.insert(peer) {
+                shortlist.push(peer);
+            }
+        }
+
+        for step in 0..self.config.max_steps {
+            // up to alpha closest not-yet-queried nodes, until the closest k have all answered
+            let batch = shortlist.next_round(&queried, &mut previously_closest, self.config.alpha);
+            if batch.is_empty() { break; }
+
+            // the round's queries run concurrently, so it lasts as long as its slowest one
+            let sent_at = transport.now();
+            let mut slowest = Duration::ZERO;
+            let mut reached_target = false;
+            for n in batch {
+                queried.insert(n);
+                let result = transport.find_node(start, n, target, self.count);
+                slowest = slowest.max(transport.last_latency());
+                let answered_at = sent_at + transport.last_latency();
+                // Unreachable peers are dropped from this lookup
+                let neighbors = match result {
+                    Ok(neighbors) => self.config.find_node.sanitize(&start, &n, neighbors, self.count),
+                    Err(err) => {
+                        transport.rpc_failed(&start, &n, &err);
+                        shortlist.remove(&n);
+                        trace.steps.push(QueryStep {
+                            queried: n,
+                            returned: Vec::new(),
+                            shortlist_size_after: shortlist.len(),
+                            step,
+                            sent_at,
+                            answered_at,
+                        });
+                        continue;
+                    }
+                };
+                // merge neighbors into shortlist, and note them as second-hand contacts
+                for m in &neighbors {
+                    if seen.insert(*m) {
+                        shortlist.push(*m);
+                    }
+                }
+                transport.peers_learned(&start, &neighbors);
+                // a node answering for its own ID has just told us its closest peers
+                reached_target |= n == target && n != start;
+                trace.steps.push(QueryStep {
+                    queried: n,
+                    returned: neighbors,
+                    shortlist_size_after: shortlist.len(),
+                    step,
+                    sent_at,
+                    answered_at,
+                });
+            }
+            transport.round_finished(slowest);
+            if reached_target { break; }
+        }
+        transport.lookup_finished(&start, &target);
+        (shortlist.into_vec(), trace)
+    }
+}
+
+/// Problems `Node::routing_health` found in one routing table
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HealthReport<const N: usize = ID_BYTES> {
+    pub node: NodeId<N>,
+    pub empty_buckets: Vec<usize>,        // empty, though the network is big enough to fill them
+    pub stale_contacts: Vec<NodeId<N>>,   // past `stale_after` failures, or silent for REFRESH_INTERVAL
+    pub overdue_buckets: Vec<usize>,      // gone REFRESH_INTERVAL or more without a lookup
+}
+
+impl<const N: usize> HealthReport<N> {
+    /// Everything flagged, counted together
+    pub fn problems(&self) -> usize {
+        self.empty_buckets.len() + self.stale_contacts.len() + self.overdue_buckets.len()
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.problems() == 0
+    }
+}
+
+/// What `Network::join` did for the new node
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JoinReport {
+    pub contacts_learned: usize, // contacts in its routing table now that were not before
+    pub lookups: usize,          // one for its own ID, then one per bucket farther than its nearest neighbor
+}
+
+/// Something a `Simulation` makes happen at a scheduled instant
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SimEvent<const N: usize = ID_BYTES> {
+    /// Crash a node without notice (`Network::fail_node`)
+    NodeFailure(NodeId<N>),
+    /// Refresh one bucket of a node, then again every REFRESH_INTERVAL while both exist
+    BucketRefresh(NodeId<N>, usize),
+    /// Re-store an origin's publication of a key, then again at every half TTL while the
+    /// origin is alive and still publishes it
+    Republish(NodeId<N>, Vec<u8>),
+    /// Drop expired entries on every node
+    ExpireEntries,
+}
+
+/// A discrete-event driver for a `Network`: time only moves from one scheduled event to
+/// the next, so hours of refreshes, republishing and failures run instantly. Lookups an
+/// event starts take the network's simulated RPC latency, so an event that finishes past
+/// the next one's time makes it fire late, as on a busy node.
+pub struct Simulation<const N: usize = ID_BYTES> {
+    network: Network<N>,
+    now: Instant, // the network's clock, as of the last event fired
+    events: BinaryHeap<Reverse<(Instant, SimEvent<N>)>>,
+}
+
+impl<const N: usize> Simulation<N> {
+    /// Drive `network`, starting from its current time with nothing scheduled
+    pub fn new(network: Network<N>) -> Self {
+        Self {
+            now: network.now(),
+            network,
+            events: BinaryHeap::new(),
+        }
+    }
+
+    /// Queue `event` to fire at `at` (on the next advance if `at` has already passed)
+    pub fn schedule(&mut self, at: Instant, event: SimEvent<N>) {
+        self.events.push(Reverse((at, event)));
+    }
+
+    /// Publish a value from `origin` now and schedule its republication at half its TTL
+    pub fn publish(&mut self, origin: NodeId<N>, key: Vec<u8>, value: Vec<u8>, ttl: Duration) {
+        let published_at = self.now;
+        self.network.register_publisher(origin, key.clone(), value, ttl);
+        self.now = self.network.now();
+        self.schedule(published_at + ttl / 2, SimEvent::Republish(origin, key));
+    }
+
+    /// Fire every event due up to `t` in time order, then move the clock to `t`
+    pub fn advance_to(&mut self, t: Instant) {
+        while self.events.peek().is_some_and(|Reverse((at, _))| *at <= t) {
+            let Some(Reverse((at, event))) = self.events.pop() else { break };
+            self.set_time(at);
+            self.fire(event);
+            self.now = self.network.now();
+        }
+        self.set_time(t);
+    }
+
+    /// Fire every event due in the next `duration`
+    pub fn run_for(&mut self, duration: Duration) {
+        self.advance_to(self.now + duration);
+    }
+
+    /// Move the network's clock forward to `t` (never backwards)
+    pub fn set_time(&mut self, t: Instant) {
+        let current = self.network.now();
+        if t > current {
+            self.network.clock.advance(t - current);
+        }
+        self.now = self.network.now();
+    }
+
+    pub fn fire(&mut self, event: SimEvent<N>) {
+        match event {
+            SimEvent::NodeFailure(id) => {
+                self.network.fail_node(&id);
+            }
+            SimEvent::BucketRefresh(id, index) => {
+                if self.network.refresh_bucket(&id, index) {
+                    self.schedule(self.now + REFRESH_INTERVAL, SimEvent::BucketRefresh(id, index));
+                }
+            }
+            SimEvent::Republish(origin, key) => {
+                if let Some(ttl) = self.network.republish(&origin, &key) {
+                    self.schedule(self.now + ttl / 2, SimEvent::Republish(origin, key));
+                }
+            }
+            SimEvent::ExpireEntries => self.network.expire_entries(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod simulation_tests {
+    use super::*;
+
+    #[test]
+    fn republishing_outlives_the_ttl_and_a_crashed_holder() {
+        let mut sim: Simulation = Simulation::new(Network::new_with_nodes_seeded(10, 5));
+        let (key, ttl) = (b"hello".to_vec(), Duration::from_secs(3600));
+        let ranked = sim.network.top_k_closest_to_key(&key, usize::MAX);
+        let (doomed, origin) = (ranked[0], ranked[ranked.len() - 1]);
+        sim.publish(origin, key.clone(), b"world".to_vec(), ttl);
+        for id in sim.network.nodes.keys().copied().collect::<Vec<_>>() {
+            sim.schedule(sim.now, SimEvent::BucketRefresh(id, 0));
+        }
+        sim.schedule(sim.now + Duration::from_secs(600), SimEvent::NodeFailure(doomed));
+        sim.run_for(Duration::from_secs(599));
+        assert!(sim.network.nodes.contains_key(&doomed), "the failure fired early");
+        sim.run_for(ttl * 3);
+        assert!(!sim.network.nodes.contains_key(&doomed));
+        sim.schedule(sim.now, SimEvent::ExpireEntries);
+        sim.run_for(Duration::ZERO);
+        assert!(!sim.network.find_all_holders(&key).is_empty());
+        let reader = ranked[1];
+        assert_eq!(sim.network.iterative_find_value(&reader, &key), Ok(b"world".to_vec()));
+    }
+
+    #[test]
+    fn an_unrepublished_value_expires() {
+        let mut sim: Simulation = Simulation::new(Network::new_with_nodes_seeded(10, 5));
+        let writer = *sim.network.nodes.keys().min().expect("nodes");
+        sim.network.iterative_store(&writer, b"hello".to_vec(), b"world".to_vec(), Duration::from_secs(3600));
+        for at in [1800, 3601] {
+            sim.schedule(sim.now + Duration::from_secs(at), SimEvent::ExpireEntries);
+        }
+        sim.run_for(Duration::from_secs(1800));
+        assert!(!sim.network.find_all_holders(b"hello").is_empty(), "expired before its TTL");
+        sim.run_for(Duration::from_secs(1801));
+        assert!(sim.network.find_all_holders(b"hello").is_empty());
+    }
+}
+
+/// A network whose nodes each sit behind their own lock, so the alpha queries of a
+/// lookup round can run concurrently instead of one after another
+#[cfg(feature = "rayon")]
+pub struct SimNetwork<const N: usize = ID_BYTES> {
+    nodes: HashMap<NodeId<N>, Arc<Mutex<Node<N>>>>,
+    hasher: Arc<dyn KeyHasher<N>>,
+    clock: SimClock,
+    rng: StdRng,
+    transport_rng: Mutex<StdRng>, // shared by the queries of a round, one draw at a time
+    config: NetworkConfig,
+    stats: NetworkStats, // handed back by `into_network`; parallel RPCs are not counted
+    partition: Option<HashSet<(NodeId<N>, NodeId<N>)>>,
+    routing_events: Option<Arc<dyn RoutingEvents<N>>>,
+    // (owner, candidate) for each bucket an RPC found full; a ping from inside a round would
+    // contend for the locks the round holds, so `into_network` runs these checks instead
+    deferred_evictions: Mutex<Vec<(NodeId<N>, NodeId<N>)>>,
+}
+
+/// Mutable lookup state shared by the parallel queries of one round
+#[cfg(feature = "rayon")]
+struct RoundState<const N: usize> {
+    shortlist: ClosestQueue<N>,
+    seen: HashSet<NodeId<N>>, // every node considered so far in the lookup
+    unreachable: Vec<(NodeId<N>, KademliaError<N>)>, // peers whose RPC failed, and how
+    learned: Vec<NodeId<N>>, // neighbors the responders named, to record as second-hand contacts
+    slowest: Duration, // the round lasts as long as its slowest query
+    found: Option<Vec<u8>>, // a value some responder had, which ends the lookup
+    reached_target: bool, // a node answered for its own ID
+}
+
+#[cfg(feature = "rayon")]
+impl<const N: usize> SimNetwork<N> {
+    /// Wrap every node of a sequential network in its own lock
+    pub fn from_network(network: Network<N>) -> Self {
+        let nodes = network
+            .nodes
+            .into_iter()
+            .map(|(id, node)| (id, Arc::new(Mutex::new(node))))
+            .collect();
+        Self {
+            nodes,
+            hasher: network.hasher,
+            clock: network.clock,
+            rng: network.rng,
+            transport_rng: Mutex::new(network.transport_rng),
+            config: network.config,
+            stats: network.stats,
+            partition: network.partition,
+            routing_events: network.routing_events,
+            deferred_evictions: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Unwrap back into a sequential network (panics if a node handle is still shared), then
+    /// run the ping-before-evict checks the parallel RPCs deferred
+    pub fn into_network(self) -> Network<N> {
+        let mut network = Network {
+            nodes: HashMap::new(),
+            hasher: self.hasher,
+            clock: self.clock,
+            publishers: HashMap::new(),
+            rumors: Vec::new(),
+            partition: self.partition,
+            routing_events: self.routing_events,
+            rng: self.rng,
+            transport_rng: self.transport_rng.into_inner().expect("transport RNG lock poisoned"),
+            config: self.config,
+            stats: self.stats,
+            evicting: false,
+            last_latency: Duration::ZERO,
+        };
+        for (id, node) in self.nodes {
+            let node = Arc::try_unwrap(node)
+                .expect("node still shared")
+                .into_inner()
+                .expect("node lock poisoned");
+            network.nodes.insert(id, node);
+        }
+        for (owner, candidate) in self.deferred_evictions.into_inner().expect("eviction queue lock poisoned") {
+            network.ping_before_evict(&owner, &candidate);
+        }
+        network
+    }
+
+    /// Draw one round-trip time from the config's latency model
+    fn sample_latency(&self) -> Duration {
+        match self.config.latency_model {
+            LatencyModel::Zero => Duration::ZERO,
+            model => model.sample(&mut *self.transport_rng.lock().expect("transport RNG lock poisoned")),
+        }
+    }
+
+    /// Lock a live node for the duration of one RPC, whose round trip takes `latency`; a
+    /// bucket it finds full on either side is queued with `defer_eviction` rather than
+    /// pinged from here
+    fn deliver<T>(
+        &self,
+        from: &NodeId<N>,
+        to: &NodeId<N>,
+        latency: Duration,
+        rpc: impl FnOnce(&mut Node<N>) -> T,
+    ) -> Result<T, KademliaError<N>> {
+        let sender = self.nodes.get(from).ok_or_else(|| KademliaError::ProtocolError(format!("unknown sender {from}")))?;
+        let (sender_key, sender_origin, sender_nonce) = {
+            let sender = sender.lock().expect("node lock poisoned");
+            (sender.public_key(), sender.origin, sender.pow_nonce)
+        };
+        let node = self.nodes.get(to).ok_or(KademliaError::NodeNotFound(*to))?;
+        if self.partition.as_ref().is_some_and(|blocked| blocked.contains(&(*from, *to))) {
+            return Err(KademliaError::Partitioned(*to));
+        }
+        let loss_rate = self.config.packet_loss_rate;
+        if loss_rate > 0.0 && self.transport_rng.lock().expect("transport RNG lock poisoned").gen_bool(loss_rate) {
+            return Err(KademliaError::Timeout(*to));
+        }
+        let mut node = node.lock().expect("node lock poisoned");
+        if !node.alive { return Err(KademliaError::Timeout(*to)); }
+        // every RPC introduces its sender, who must own the ID it claims
+        match node.track_peer(from, sender_key.as_ref(), sender_origin, sender_nonce) {
+            TrackOutcome::Rejected => {
+                return Err(KademliaError::ProtocolError(format!("sender {from} does not own its ID or lacks the required proof of work")));
+            }
+            TrackOutcome::BucketFull { evict_candidate } => self.defer_eviction(*to, evict_candidate),
+            TrackOutcome::Tracked | TrackOutcome::Refused => {}
+        }
+        let reply = rpc(&mut node);
+        let (target_key, target_origin, target_nonce) = (node.public_key(), node.origin, node.pow_nonce);
+        drop(node);
+        // the reply shows the target is alive, so the sender learns (or refreshes) it in turn
+        if let Some(sender) = self.nodes.get(from) {
+            let mut sender = sender.lock().expect("node lock poisoned");
+            match sender.track_peer(to, target_key.as_ref(), target_origin, target_nonce) {
+                TrackOutcome::Rejected => {
+                    return Err(KademliaError::ProtocolError(format!("{to} does not own its ID or lacks the required proof of work")));
+                }
+                TrackOutcome::BucketFull { evict_candidate } => self.defer_eviction(*from, evict_candidate),
+                TrackOutcome::Tracked | TrackOutcome::Refused => {}
+            }
+            sender.routing_table.record_rtt(to, latency);
+        }
+        Ok(reply)
+    }
+
+    /// Queue a ping-before-evict for `into_network`: the one place full buckets are handled
+    fn defer_eviction(&self, owner: NodeId<N>, candidate: NodeId<N>) {
+        self.deferred_evictions.lock().expect("eviction queue lock poisoned").push((owner, candidate));
+    }
+
+    /// Remove a peer from every node's peer list
+    fn evict_peer_from_all(&self, peer: &NodeId<N>) {
+        for node in self.nodes.values() {
+            node.lock().expect("node lock poisoned").remove_peer(peer);
+        }
+    }
+
+    /// React to a failed RPC as `Network::note_failure` does: count a timeout against the
+    /// caller's contact, evict a peer that is gone everywhere
+    fn note_failure(&self, from: &NodeId<N>, to: &NodeId<N>, err: &KademliaError<N>) {
+        if matches!(err, KademliaError::Timeout(_) | KademliaError::Partitioned(_)) {
+            if let Some(node) = self.nodes.get(from) {
+                node.lock().expect("node lock poisoned").routing_table.record_failure(to);
+            }
+        } else {
+            self.evict_peer_from_all(to);
+        }
+    }
+
+    fn snapshot_peers(&self, id: &NodeId<N>) -> Vec<NodeId<N>> {
+        self.nodes
+            .get(id)
+            .map(|n| n.lock().expect("node lock poisoned").routing_table.peers().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Tell `id` about peers a lookup of its learned of second-hand (`Node::record_contact`)
+    fn record_contacts(&self, id: &NodeId<N>, peers: &[NodeId<N>]) {
+        if let Some(node) = self.nodes.get(id) {
+            let mut node = node.lock().expect("node lock poisoned");
+            for peer in peers {
+                node.record_contact(peer);
+            }
+        }
+    }
+
+    /// Parallel iterative find_node: each round queries up to alpha nodes concurrently
+    pub fn parallel_iterative_find_node(&self, start: &NodeId<N>, target: &NodeId<N>) -> Vec<NodeId<N>> {
+        let (k, from) = (self.config.k, *start);
+        match self.parallel_lookup(start, target, |node| FindValueResult::Nodes(node.rpc_find_node(&from, target, k))) {
+            FindValueResult::Nodes(closest) => closest,
+            FindValueResult::Value(_) => unreachable!("find_node never answers with a value"),
+        }
+    }
+
+    /// Parallel iterative find_value: like the parallel find_node, but each query is a single
+    /// find_value RPC and the lookup stops once one answers with the value
+    pub fn parallel_iterative_find_value(&self, start: &NodeId<N>, key: &[u8]) -> Result<Vec<u8>, KademliaError<N>> {
+        let (k, from, key_id) = (self.config.k, *start, self.hasher.hash_key(key));
+        let rpc = |node: &mut Node<N>| match node.rpc_find_value(&from, &key_id) {
+            Some(value) => FindValueResult::Value(value),
+            None => FindValueResult::Nodes(node.rpc_find_node(&from, &key_id, k)),
+        };
+        match self.parallel_lookup(start, &key_id, rpc) {
+            FindValueResult::Value(value) => Ok(value),
+            FindValueResult::Nodes(_) => Err(KademliaError::KeyNotFound),
+        }
+    }
+
+    /// The rounds both parallel lookups share, following `Router`: send `rpc` to up to alpha
+    /// of the closest nodes not yet asked, all at once, and merge the contacts they answer
+    /// with, until the closest k have all answered, a value turns up or a node answers for
+    /// its own ID. Each round advances the clock by its slowest query. Returns the value,
+    /// or else the k closest nodes found.
+    fn parallel_lookup(
+        &self,
+        start: &NodeId<N>,
+        target: &NodeId<N>,
+        rpc: impl Fn(&mut Node<N>) -> FindValueResult<N> + Sync,
+    ) -> FindValueResult<N> {
+        let mut queried: HashSet<NodeId<N>> = HashSet::new();
+        let mut previously_closest: Vec<NodeId<N>> = Vec::new();
+        let mut shortlist = ClosestQueue::new(*target, self.config.k);
+        let mut seen: HashSet<NodeId<N>> = HashSet::new();
+        for peer in self.snapshot_peers(start).into_iter().chain([*start]) {
+            if seen.insert(peer) {
+                shortlist.push(peer);
+            }
+        }
+
+        for _step in 0..self.config.max_steps {
+            // up to alpha closest not-yet-queried nodes, until the closest k have all answered
+            let batch = shortlist.next_round(&queried, &mut previously_closest, self.config.alpha);
+            if batch.is_empty() { break; }
+            queried.extend(&batch);
+
+            let state = Mutex::new(RoundState {
+                shortlist,
+                seen,
+                unreachable: Vec::new(),
+                learned: Vec::new(),
+                slowest: Duration::ZERO,
+                found: None,
+                reached_target: false,
+            });
+            batch.par_iter().for_each(|n| {
+                let latency = self.sample_latency();
+                let result = self.deliver(start, n, latency, &rpc);
+                let mut state = state.lock().expect("round state poisoned");
+                state.slowest = state.slowest.max(latency);
+                match result {
+                    Ok(FindValueResult::Value(value)) => {
+                        state.found.get_or_insert(value);
+                    }
+                    Ok(FindValueResult::Nodes(neighbors)) => {
+                        let RoundState { shortlist, seen, learned, reached_target, .. } = &mut *state;
+                        for m in self.config.find_node.sanitize(start, n, neighbors, self.config.k) {
+                            if seen.insert(m) {
+                                shortlist.push(m);
+                            }
+                            learned.push(m);
+                        }
+                        *reached_target |= n == target && n != start;
+                    }
+                    Err(err) => state.unreachable.push((*n, err)),
+                }
+            });
+            let state = state.into_inner().expect("round state poisoned");
+            // handle failures outside the parallel section so no RPC holds a lock we need
+            for (dead, err) in &state.unreachable {
+                self.note_failure(start, dead, err);
+            }
+            self.record_contacts(start, &state.learned);
+            self.clock.advance(state.slowest);
+            if let Some(value) = state.found { return FindValueResult::Value(value); }
+            shortlist = state.shortlist;
+            seen = state.seen;
+            for (dead, _) in &state.unreachable {
+                shortlist.remove(dead);
+            }
+            if state.reached_target { break; }
+        }
+        // the lookup counts as a refresh of the bucket the target falls in
+        if let Some(node) = self.nodes.get(start) {
+            node.lock().expect("node lock poisoned").routing_table.mark_refreshed(target, self.clock.now());
+        }
+        FindValueResult::Nodes(shortlist.into_vec())
+    }
+}
@@ -1,354 +1,23 @@
-use rand::Rng;
-use sha1::{Digest, Sha1};
-use std::cmp::Ordering;
-use std::collections::HashMap;
-
-/// Kademlia's bucket size (commonly 20 in papers); we use a smaller number for demo
-const K: usize = 8;
-/// Concurrency factor alpha in Kademlia (number of parallel queries); we serialize for simplicity
-const ALPHA: usize = 3;
-/// Max iterations for lookup to avoid infinite loops in small demos
-const MAX_STEPS: usize = 8;
-
-/// A 160-bit identifier, like in Kademlia (commonly from SHA-1 space)
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-struct NodeId([u8; 20]);
-
-impl NodeId {
-    /// Create a random 160-bit NodeId
-    fn random() -> Self {
-        let mut rng = rand::thread_rng();
-        let mut bytes = [0u8; 20];
-        rng.fill(&mut bytes);
-        NodeId(bytes)
-    }
-
-    /// Construct from a 20-byte array
-    fn from_bytes(bytes: [u8; 20]) -> Self {
-        NodeId(bytes)
-    }
-
-    /// Compute XOR distance between two IDs as a big-endian integer in bytes
-    fn xor_distance(&self, other: &NodeId) -> [u8; 20] {
-        let mut out = [0u8; 20];
-        for i in 0..20 {
-            out[i] = self.0[i] ^ other.0[i];
-        }
-        out
-    }
-}
-
-/// Compare two 160-bit distances (big-endian) for sorting
-fn compare_distances(a: &[u8; 20], b: &[u8; 20]) -> Ordering {
-    for i in 0..20 {
-        if a[i] < b[i] {
-            return Ordering::Less;
-        } else if a[i] > b[i] {
-            return Ordering::Greater;
-        }
-    }
-    Ordering::Equal
-}
-
-/// A basic node in the DHT
-#[derive(Debug)]
-struct Node {
-    id: NodeId,
-    storage: HashMap<Vec<u8>, Vec<u8>>, // very simple key-value store
-    peers: Vec<NodeId>,                  // simplified k-bucket: LRU list, max K
-    alive: bool,                         // liveness flag
-}
-
-impl Node {
-    /// Create a new node with a random ID
-    fn new() -> Self {
-        Self {
-            id: NodeId::random(),
-            storage: HashMap::new(),
-            peers: Vec::new(),
-            alive: true,
-        }
-    }
-
-    /// Update local peer list (LRU behavior, max K, no self)
-    fn track_peer(&mut self, peer: &NodeId) {
-        if *peer == self.id {
-            return;
-        }
-        if let Some(pos) = self.peers.iter().position(|p| p == peer) {
-            let existing = self.peers.remove(pos);
-            self.peers.push(existing);
-        } else {
-            self.peers.push(*peer);
-            if self.peers.len() > K {
-                self.peers.remove(0);
-            }
-        }
-    }
-
-    /// Remove a peer if present
-    fn evict_peer(&mut self, peer: &NodeId) {
-        if let Some(pos) = self.peers.iter().position(|p| p == peer) {
-            self.peers.remove(pos);
-        }
-    }
-
-    /// RPC: ping - used to check liveness
-    fn rpc_ping(&mut self, from: &NodeId) -> bool {
-        self.track_peer(from);
-        true
-    }
-
-    /// RPC: store - store a key/value locally
-    fn rpc_store(&mut self, from: &NodeId, key: Vec<u8>, value: Vec<u8>) {
-        self.track_peer(from);
-        self.storage.insert(key, value);
-    }
-
-    /// RPC: find_value - try to get a value for a key
-    fn rpc_find_value(&mut self, from: &NodeId, key: &[u8]) -> Option<Vec<u8>> {
-        self.track_peer(from);
-        self.storage.get(key).cloned()
-    }
-
-    /// RPC: find_node - return up to K known nodes closest to the target id
-    fn rpc_find_node(&mut self, from: &NodeId, target: &NodeId) -> Vec<NodeId> {
-        self.track_peer(from);
-        let mut peers = self.peers.clone();
-        peers.sort_by(|a, b| {
-            let da = target.xor_distance(a);
-            let db = target.xor_distance(b);
-            compare_distances(&da, &db)
-        });
-        peers.truncate(K);
-        peers
-    }
-}
-
-/// An in-memory network that owns nodes and forwards RPC calls between them
-struct Network {
-    nodes: HashMap<NodeId, Node>,
-}
-
-impl Network {
-    fn new() -> Self {
-        Self { nodes: HashMap::new() }
-    }
-
-    /// Create and register a new node with a unique ID; returns its NodeId
-    fn add_node(&mut self) -> NodeId {
-        loop {
-            let node = Node::new();
-            if !self.nodes.contains_key(&node.id) {
-                let id = node.id;
-                self.nodes.insert(id, node);
-                return id;
-            }
-        }
-    }
-
-    /// Add a new node and bootstrap it via a known peer
-    fn add_and_join(&mut self, bootstrap: &NodeId) -> Option<NodeId> {
-        let id = self.add_node();
-        // try to contact bootstrap so it learns about us (and vice versa)
-        let _ = self.ping(&id, bootstrap);
-        // run a find_node towards our own ID to discover neighbors
-        let _ = self.iterative_find_node(&id, &id);
-        Some(id)
-    }
-
-    /// Mark a node as dead (simulate failure)
-    fn kill_node(&mut self, id: &NodeId) -> bool {
-        if let Some(node) = self.nodes.get_mut(id) {
-            node.alive = false;
-            true
-        } else {
-            false
-        }
-    }
-
-    /// Remove a peer from every node's peer list
-    fn evict_peer_from_all(&mut self, peer: &NodeId) {
-        for node in self.nodes.values_mut() {
-            node.evict_peer(peer);
-        }
-    }
-
-    /// Helper to print a node's ID as hex
-    fn id_hex(id: &NodeId) -> String {
-        id.0.iter().map(|b| format!("{b:02x}")).collect()
-    }
-
-    /// Compute a key's 160-bit ID using SHA-1
-    fn key_to_id(key: &[u8]) -> NodeId {
-        let digest = Sha1::digest(key);
-        let mut bytes = [0u8; 20];
-        bytes.copy_from_slice(&digest[..20]);
-        NodeId::from_bytes(bytes)
-    }
-
-    /// Snapshot known peers of a node (to avoid borrow issues during iteration)
-    fn snapshot_peers(&self, id: &NodeId) -> Vec<NodeId> {
-        self.nodes.get(id).map(|n| n.peers.clone()).unwrap_or_default()
-    }
-
-    /// Return up to K closest nodes from `candidates` to `target` (by XOR)
-    fn closest_k(&self, target: &NodeId, candidates: &[NodeId]) -> Vec<NodeId> {
-        let mut list = candidates.to_vec();
-        list.sort_by(|a, b| {
-            let da = target.xor_distance(a);
-            let db = target.xor_distance(b);
-            compare_distances(&da, &db)
-        });
-        list.truncate(K);
-        list
-    }
-
-    /// RPC forwarding: ping from one node to another
-    fn ping(&mut self, from: &NodeId, to: &NodeId) -> Option<bool> {
-        let target = self.nodes.get_mut(to)?;
-        if !target.alive { return None; }
-        Some(target.rpc_ping(from))
-    }
-
-    /// RPC forwarding: store a key/value on a target node
-    fn store(&mut self, from: &NodeId, to: &NodeId, key: Vec<u8>, value: Vec<u8>) -> Option<()> {
-        let target = self.nodes.get_mut(to)?;
-        if !target.alive { return None; }
-        target.rpc_store(from, key, value);
-        Some(())
-    }
-
-    /// RPC forwarding: find_value on a target node
-    fn find_value(&mut self, from: &NodeId, to: &NodeId, key: &[u8]) -> Option<Option<Vec<u8>>> {
-        let target = self.nodes.get_mut(to)?;
-        if !target.alive { return None; }
-        Some(target.rpc_find_value(from, key))
-    }
-
-    /// RPC forwarding: find_node on a target node
-    fn find_node(&mut self, from: &NodeId, to: &NodeId, target_id: &NodeId) -> Option<Vec<NodeId>> {
-        let target = self.nodes.get_mut(to)?;
-        if !target.alive { return None; }
-        Some(target.rpc_find_node(from, target_id))
-    }
-
-    /// Iterative find_node: start from `start`, walk the network to find K closest to `target`
-    fn iterative_find_node(&mut self, start: &NodeId, target: &NodeId) -> Vec<NodeId> {
-        let mut queried: Vec<NodeId> = Vec::new();
-        let mut shortlist: Vec<NodeId> = self.snapshot_peers(start);
-        if !shortlist.contains(start) {
-            shortlist.push(*start);
-        }
-        shortlist = self.closest_k(target, &shortlist);
-
-        for _step in 0..MAX_STEPS {
-            // pick up to ALPHA closest not-yet-queried nodes
-            let mut batch: Vec<NodeId> = Vec::new();
-            for n in &shortlist {
-                if !queried.contains(n) {
-                    batch.push(*n);
-                }
-                if batch.len() == ALPHA { break; }
-            }
-            if batch.is_empty() { break; }
-
-            let mut any_progress = false;
-            for n in batch {
-                queried.push(n);
-                // Skip or evict dead/unreachable peers
-                if self.ping(start, &n) != Some(true) {
-                    self.evict_peer_from_all(&n);
-                    continue;
-                }
-                if let Some(neighbors) = self.find_node(start, &n, target) {
-                    // merge neighbors into shortlist
-                    for m in neighbors {
-                        if !shortlist.contains(&m) {
-                            shortlist.push(m);
-                        }
-                    }
-                    let before = shortlist.clone();
-                    shortlist = self.closest_k(target, &shortlist);
-                    if shortlist != before { any_progress = true; }
-                }
-            }
-            if !any_progress { break; }
-        }
-        self.closest_k(target, &shortlist)
-    }
-
-    /// Iterative find_value: like find_node but stop if a value is found
-    fn iterative_find_value(&mut self, start: &NodeId, key: &[u8]) -> Option<Vec<u8>> {
-        let key_id = Self::key_to_id(key);
-        let mut queried: Vec<NodeId> = Vec::new();
-        let mut shortlist: Vec<NodeId> = self.snapshot_peers(start);
-        if !shortlist.contains(start) {
-            shortlist.push(*start);
-        }
-        shortlist = self.closest_k(&key_id, &shortlist);
-
-        for _step in 0..MAX_STEPS {
-            let mut batch: Vec<NodeId> = Vec::new();
-            for n in &shortlist {
-                if !queried.contains(n) {
-                    batch.push(*n);
-                }
-                if batch.len() == ALPHA { break; }
-            }
-            if batch.is_empty() { break; }
-
-            let mut any_progress = false;
-            for n in batch {
-                queried.push(n);
-                // Skip or evict dead/unreachable peers
-                if self.ping(start, &n) != Some(true) {
-                    self.evict_peer_from_all(&n);
-                    continue;
-                }
-                if let Some(result) = self.find_value(start, &n, key) {
-                    if let Some(value) = result { return Some(value); }
-                }
-                if let Some(neighbors) = self.find_node(start, &n, &key_id) {
-                    for m in neighbors {
-                        if !shortlist.contains(&m) {
-                            shortlist.push(m);
-                        }
-                    }
-                    let before = shortlist.clone();
-                    shortlist = self.closest_k(&key_id, &shortlist);
-                    if shortlist != before { any_progress = true; }
-                }
-            }
-            if !any_progress { break; }
-        }
-        None
-    }
-
-    /// Iterative store: route to K closest nodes to key_id and store there
-    fn iterative_store(&mut self, start: &NodeId, key: Vec<u8>, value: Vec<u8>) {
-        let key_id = Self::key_to_id(&key);
-        let closest = self.iterative_find_node(start, &key_id);
-        for target in closest {
-            if self.ping(start, &target) == Some(true) {
-                let _ = self.store(start, &target, key.clone(), value.clone());
-            } else {
-                self.evict_peer_from_all(&target);
-            }
-        }
-    }
-}
+use kademlia::{ID_BYTES, DEFAULT_TTL, Network, NetworkConfig, NodeId};
+#[cfg(feature = "rayon")]
+use kademlia::SimNetwork;
+use std::time::Duration;
 
 fn main() {
+    // RUST_LOG=kademlia=debug shows every RPC, nested in the lookup that sent it
+    #[cfg(feature = "tracing")]
+    tracing_subscriber::fmt().with_env_filter(tracing_subscriber::EnvFilter::from_default_env()).init();
+
     // Build a small in-memory network and add nodes
-    let mut network = Network::new();
+    let mut network: Network = Network::new();
     let id0 = network.add_node();
     let id1 = network.add_node();
     let id2 = network.add_node();
 
-    println!("Node 0: {}", Network::id_hex(&id0));
-    println!("Node 1: {}", Network::id_hex(&id1));
-    println!("Node 2: {}", Network::id_hex(&id2));
+    // Short labels keep the output readable; the full hex and base32 forms stay available
+    println!("Node 0: {} (hex {id0}, base32 {})", id0.short(), id0.to_base32());
+    println!("Node 1: {}", id1.short());
+    println!("Node 2: {}", id2.short());
 
     // Bootstrap: let nodes learn about each other by contacting
     let _ = network.ping(&id1, &id0);
@@ -358,25 +27,140 @@ fn main() {
     // Iterative store: route to K closest to the key
     let key = b"hello".to_vec();
     let value = b"world".to_vec();
-    network.iterative_store(&id1, key.clone(), value.clone());
+    network.iterative_store(&id1, key.clone(), value.clone(), DEFAULT_TTL);
+    println!("'hello' is held by {} nodes", network.find_all_holders(&key).len());
 
     // Add a new node and join via id0
-    let id3 = network.add_and_join(&id0).expect("join failed");
-    println!("Node 3 (joined via 0): {}", Network::id_hex(&id3));
+    let id3 = network.add_and_bootstrap(&id0).expect("join failed");
+    println!("Node 3 (joined via 0): {}", id3.short());
+    if let Some(node3) = network.node(&id3) {
+        println!("Node 3 routing table holds {} peers", node3.routing_table().len());
+    }
 
     // Kill node 1 (simulate failure)
     let _ = network.kill_node(&id1);
     println!("Simulated failure: node 1 is now dead");
+    if let Err(err) = network.ping(&id0, &id1) {
+        println!("Ping from node 0 to node 1: {err}");
+    }
 
-    // Iterative find_value from id3 should still work; dead peers get evicted
+    // Iterative find_value from id3 should still work; dead peers are skipped
     let got = network.iterative_find_value(&id3, &key);
     println!(
         "Iterative find_value from node3 for 'hello': {:?}",
         got.map(|v| String::from_utf8_lossy(&v).to_string())
     );
 
+    // Values expire once their TTL passes in simulated time
+    network.iterative_store(&id2, b"ephemeral".to_vec(), b"gone soon".to_vec(), Duration::from_secs(1));
+    network.tick(Duration::from_secs(2));
+    println!(
+        "find_value for 'ephemeral' after its TTL: {:?}",
+        network.iterative_find_value(&id3, b"ephemeral")
+    );
+
+    // A registered publisher re-stores its value at half-TTL, keeping it alive past the original expiry
+    network.register_publisher(id2, b"durable".to_vec(), b"kept alive".to_vec(), Duration::from_secs(10));
+    network.tick(Duration::from_secs(6));
+    network.do_republish(network.now());
+    network.tick(Duration::from_secs(6));
+    println!(
+        "find_value for 'durable' 12s after a 10s-TTL publish: {:?}",
+        network.iterative_find_value(&id3, b"durable").map(|v| String::from_utf8_lossy(&v).to_string())
+    );
+
     // Show iterative find_node for id2 starting from id3 (skips dead nodes)
     let closest_to_id2 = network.iterative_find_node(&id3, &id2);
-    let list: Vec<String> = closest_to_id2.iter().map(|nid| Network::id_hex(nid)).collect();
+    let list: Vec<String> = closest_to_id2.iter().map(NodeId::short).collect();
     println!("Iterative closest to id2 (from id3): {:?}", list);
+
+    // With `--features rayon`, repeat the lookup with each round's alpha queries in flight at once
+    #[cfg(feature = "rayon")]
+    {
+        let sim = SimNetwork::from_network(network);
+        let got = sim.parallel_iterative_find_value(&id3, &key);
+        println!(
+            "Parallel find_value from node3 for 'hello': {:?}",
+            got.map(|v| String::from_utf8_lossy(&v).to_string())
+        );
+    }
+
+    // Estimate network size from the distances to a node's closest peers
+    let mut big: Network = Network::with_seed(42);
+    let first = big.add_node();
+    for _ in 1..200 {
+        big.add_and_bootstrap(&first).expect("join failed");
+    }
+    println!("Estimated size of a 200-node network from its bootstrap node: {:.0}", big.estimate_size(&first));
+
+    // RPC counters: what one store-then-lookup round trip costs in a 200-node network
+    big.reset_stats();
+    let counted_id = big.key_to_id(b"counted");
+    let far = *big.node_ids().max_by_key(|id| counted_id.xor_distance(id)).expect("network is not empty");
+    big.iterative_store(&first, b"counted".to_vec(), b"round trip".to_vec(), DEFAULT_TTL);
+    let (found, query) = big.verbose_iterative_find_value(&far, b"counted");
+    println!(
+        "Round trip found the value: {}, in {} hops asking {} nodes; network stats: {:?}",
+        found.is_some(),
+        query.hops,
+        query.nodes_queried,
+        big.stats()
+    );
+
+    // Parameters are per network: with k = 3, lookups return at most three nodes
+    let mut narrow: Network = Network::with_config(NetworkConfig::builder().k(3).build());
+    let entry = narrow.add_node();
+    for _ in 0..10 {
+        narrow.add_and_bootstrap(&entry).expect("join failed");
+    }
+    println!("Lookup in a k=3 network returned {} nodes", narrow.iterative_find_node(&entry, &entry).len());
+
+    // Most peers share only a short prefix with a node: half the keyspace shares none at all
+    let density = big.density_report();
+    let used = density.peers_per_bucket.iter().rposition(|&c| c > 0).map_or(0, |i| i + 1);
+    println!(
+        "Peers per shared prefix length, summed over {} nodes: {:?}",
+        density.nodes,
+        &density.peers_per_bucket[..used]
+    );
+
+    // Identity-backed IDs: a contact is only accepted if its ID is the hash of the key it presents
+    let holder = big.add_identified_node();
+    let owner = big.add_identified_node();
+    let owner_key = big.node(&owner).expect("just added").public_key().expect("node was created with an identity");
+    println!("Introduce an ID with its own key: {:?}", big.introduce(&holder, &owner, &owner_key));
+    match big.introduce(&holder, &first, &owner_key) {
+        Ok(()) => println!("Introduce an ID with a borrowed key: accepted"),
+        Err(err) => println!("Introduce an ID with a borrowed key: {err}"),
+    }
+    let from_key1 = NodeId::<ID_BYTES>::from_public_key(b"key1");
+    let mut keyed: Network = Network::new();
+    let twice = keyed.add_node_with_pubkey(b"key1").and_then(|_| keyed.add_node_with_pubkey(b"key1"));
+    println!(
+        "IDs from raw keys: deterministic {}, distinct {}; registering key1 twice: {:?}",
+        from_key1 == NodeId::from_public_key(b"key1"),
+        from_key1 != NodeId::from_public_key(b"key2"),
+        twice.map_err(|err| err.to_string())
+    );
+
+    // A graceful departure: every table forgets the node at once, promoting replacements
+    let leaving = *big.node_ids().max().expect("network is not empty");
+    let knew = big.nodes().filter(|node| node.has_peer(&leaving)).count();
+    big.remove_node(&leaving);
+    let still = big.nodes().filter(|node| node.has_peer(&leaving)).count();
+    println!("Node {} left: known by {knew} nodes before, {still} after", leaving.short());
+
+    // The same simulation over a toy 32-bit keyspace, small enough to reason about by hand;
+    // seeding the network makes the IDs (and so the whole run) reproducible
+    let mut toy: Network<4> = Network::with_seed(7);
+    let seed = toy.add_node();
+    let members: Vec<NodeId<4>> = (0..5).filter_map(|_| toy.add_and_bootstrap(&seed).ok()).collect();
+    toy.iterative_store(&members[0], key.clone(), value.clone(), DEFAULT_TTL);
+    let got = toy.iterative_find_value(&members[4], &key);
+    println!(
+        "32-bit network: key id {}, find_value from {}: {:?}",
+        toy.key_to_id(&key),
+        members[4],
+        got.map(|v| String::from_utf8_lossy(&v).to_string())
+    );
 }
@@ -0,0 +1,69 @@
+//! Properties of the XOR metric, checked on random IDs
+
+use kademlia::{Network, NodeId};
+use proptest::prelude::*;
+
+fn node_id() -> impl Strategy<Value = NodeId> {
+    any::<[u8; 20]>().prop_map(NodeId::from_bytes)
+}
+
+/// A 160-bit distance as a 161-bit big-endian integer, room for the carry of a sum
+fn widen(d: &[u8; 20]) -> [u8; 21] {
+    let mut wide = [0; 21];
+    wide[1..].copy_from_slice(d);
+    wide
+}
+
+/// `a + b` as 161-bit big-endian integers
+fn sum(a: &[u8; 20], b: &[u8; 20]) -> [u8; 21] {
+    let (a, b) = (widen(a), widen(b));
+    let mut total = [0; 21];
+    let mut carry = 0;
+    for i in (0..21).rev() {
+        let digit = a[i] as u16 + b[i] as u16 + carry;
+        total[i] = digit as u8;
+        carry = digit >> 8;
+    }
+    total
+}
+
+proptest! {
+    #[test]
+    fn an_id_is_at_distance_zero_from_itself(x in node_id()) {
+        prop_assert!(x.xor_distance(&x).is_zero());
+    }
+
+    #[test]
+    fn distance_is_symmetric(x in node_id(), y in node_id()) {
+        prop_assert_eq!(x.xor_distance(&y), y.xor_distance(&x));
+    }
+
+    #[test]
+    fn distance_is_bounded_by_the_xor_of_the_two_legs(x in node_id(), y in node_id(), z in node_id()) {
+        // the distance of x ^ y ^ y ^ z from zero is d(x, y) XOR d(y, z)
+        let legs = x.xor(&y).xor(&y.xor(&z)).xor_distance(&NodeId::ZERO);
+        prop_assert!(x.xor_distance(&z) <= legs);
+    }
+
+    #[test]
+    fn distance_obeys_the_triangle_inequality(x in node_id(), y in node_id(), z in node_id()) {
+        let legs = sum(x.xor_distance(&y).as_bytes(), y.xor_distance(&z).as_bytes());
+        prop_assert!(widen(x.xor_distance(&z).as_bytes()) <= legs);
+    }
+
+    #[test]
+    fn a_closer_id_shares_at_least_as_long_a_prefix(a in node_id(), b in node_id(), c in node_id()) {
+        if a.xor_distance(&b) < a.xor_distance(&c) {
+            prop_assert!(a.common_prefix_len(&b) >= a.common_prefix_len(&c));
+        }
+    }
+
+    #[test]
+    fn closest_k_comes_back_sorted_by_distance(
+        target in node_id(),
+        candidates in prop::collection::vec(node_id(), 0..100),
+    ) {
+        let closest = Network::<20>::new().closest_k(&target, &candidates);
+        prop_assert!(closest.windows(2).all(|w| target.xor_distance(&w[0]) <= target.xor_distance(&w[1])));
+    }
+}